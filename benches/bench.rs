@@ -1,4 +1,6 @@
 use criterion::{criterion_group, criterion_main, Criterion};
+use phips_1brc::{AggregatedData, QuantileSketch};
+use std::num::NonZeroUsize;
 
 fn single_threaded_benchmarks(c: &mut Criterion) {
     c.bench_function("single: 1brc (100 entries)", |b| {
@@ -26,9 +28,25 @@ fn multi_threaded_benchmarks(c: &mut Criterion) {
     });
 }
 
+/// Compares the overhead of the quantile sketch with the default aggregator.
+fn aggregator_benchmarks(c: &mut Criterion) {
+    let threads = NonZeroUsize::MIN;
+    c.bench_function("aggregator: AggregatedData (10000 entries)", |b| {
+        b.iter(|| {
+            phips_1brc::aggregate_custom::<AggregatedData>("./measurements_10000.txt", threads)
+        })
+    });
+    c.bench_function("aggregator: QuantileSketch (10000 entries)", |b| {
+        b.iter(|| {
+            phips_1brc::aggregate_custom::<QuantileSketch>("./measurements_10000.txt", threads)
+        })
+    });
+}
+
 criterion_group!(
     benches,
     single_threaded_benchmarks,
-    multi_threaded_benchmarks
+    multi_threaded_benchmarks,
+    aggregator_benchmarks
 );
 criterion_main!(benches);
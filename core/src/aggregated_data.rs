@@ -0,0 +1,174 @@
+use core::cmp::{max_by, min_by};
+use likely_stable::unlikely;
+use serde::{Deserialize, Serialize};
+
+/// Aggregated data per station. The temperature is encoded as integer
+/// multiplied by 10. `-15.7 => -157`. The corresponding getters return the real
+/// value.
+///
+/// The serialized form contains the encoded fields, so that partial results
+/// can be exchanged between processes without losing precision. Deserializing
+/// fails for data that can't result from any measurements, such as no
+/// measurements at all or a minimum above the maximum.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "EncodedFields")]
+pub struct AggregatedData {
+    min: i16,
+    max: i16,
+    sum: i64,
+    sample_count: u32,
+}
+
+impl Default for AggregatedData {
+    fn default() -> Self {
+        Self {
+            min: i16::MAX,
+            max: i16::MIN,
+            sum: 0,
+            sample_count: 0,
+        }
+    }
+}
+
+/// The serialized form of [`AggregatedData`], before it is validated.
+#[derive(Deserialize)]
+struct EncodedFields {
+    min: i16,
+    max: i16,
+    sum: i64,
+    sample_count: u32,
+}
+
+impl TryFrom<EncodedFields> for AggregatedData {
+    type Error = &'static str;
+
+    fn try_from(fields: EncodedFields) -> Result<Self, Self::Error> {
+        let count = i64::from(fields.sample_count);
+        if count == 0 {
+            Err("sample_count must not be 0")
+        } else if fields.min > fields.max {
+            Err("min must not be greater than max")
+        } else if !(i64::from(fields.min) * count..=i64::from(fields.max) * count)
+            .contains(&fields.sum)
+        {
+            Err("sum must be between min and max times sample_count")
+        } else {
+            Ok(Self::new(
+                fields.min,
+                fields.max,
+                fields.sum,
+                fields.sample_count,
+            ))
+        }
+    }
+}
+
+impl AggregatedData {
+    /// Creates the data from the encoded fields, for example of partial results
+    /// that were exchanged in another format.
+    #[must_use]
+    pub const fn new(min: i16, max: i16, sum: i64, sample_count: u32) -> Self {
+        Self {
+            min,
+            max,
+            sum,
+            sample_count,
+        }
+    }
+
+    #[allow(clippy::collapsible_else_if)]
+    pub const fn add_datapoint(&mut self, measurement: i16) {
+        if unlikely(self.empty()) {
+            self.min = measurement;
+            self.max = measurement;
+        } else {
+            if measurement < self.min {
+                self.min = measurement
+            } else if measurement > self.max {
+                self.max = measurement
+            }
+        }
+
+        self.sum += measurement as i64;
+        self.sample_count += 1;
+    }
+
+    /// Merge the data with another instance.
+    pub fn merge(&mut self, other: &Self) {
+        self.max = max_by(self.max, other.max, |a, b| a.partial_cmp(b).unwrap());
+        self.min = min_by(self.min, other.min, |a, b| a.partial_cmp(b).unwrap());
+        self.sum += other.sum;
+        self.sample_count += other.sample_count;
+    }
+
+    #[must_use]
+    pub fn avg(&self) -> f32 {
+        self.sum as f32 / ((self.sample_count * 10) as f32)
+    }
+
+    #[must_use]
+    pub fn max(&self) -> f32 {
+        self.max as f32 / 10.0
+    }
+
+    #[must_use]
+    pub fn min(&self) -> f32 {
+        self.min as f32 / 10.0
+    }
+
+    /// The difference between [`Self::max`] and [`Self::min`].
+    #[must_use]
+    pub fn range(&self) -> f32 {
+        (self.max as i32 - self.min as i32) as f32 / 10.0
+    }
+
+    #[must_use]
+    pub const fn count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// The minimum, encoded as integer multiplied by 10.
+    #[must_use]
+    pub const fn encoded_min(&self) -> i16 {
+        self.min
+    }
+
+    /// The maximum, encoded as integer multiplied by 10.
+    #[must_use]
+    pub const fn encoded_max(&self) -> i16 {
+        self.max
+    }
+
+    /// The sum of all measurements, encoded as integer multiplied by 10.
+    #[must_use]
+    pub const fn encoded_sum(&self) -> i64 {
+        self.sum
+    }
+
+    /// Hasn't received a data point so far.
+    #[must_use]
+    pub const fn empty(&self) -> bool {
+        self.max == i16::MIN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregated_data::AggregatedData;
+    use core::mem::size_of;
+
+    #[test]
+    fn layout() {
+        assert_eq!(size_of::<AggregatedData>(), 16);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut data = AggregatedData::default();
+        [20, 40, 90]
+            .into_iter()
+            .for_each(|measurement| data.add_datapoint(measurement));
+        assert_eq!(data.range(), 7.0);
+        assert_eq!(data.count(), 3);
+    }
+}
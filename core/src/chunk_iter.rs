@@ -1,8 +1,10 @@
-use std::cmp::min;
+use core::cmp::min;
+use core::ops::Range;
 
 /// Iterates the file in `n` chunks, but with respect to line endings.
 /// This helps us to distribute the workload exactly between multiple
 /// threads.
+#[derive(Debug)]
 pub struct ChunkIter<'a> {
     bytes_per_chunk: usize,
     file_bytes: &'a [u8],
@@ -11,6 +13,7 @@ pub struct ChunkIter<'a> {
 }
 
 impl<'a> ChunkIter<'a> {
+    #[must_use]
     pub const fn new(file_bytes: &'a [u8], chunk_count: usize) -> Self {
         let bytes_per_chunk = file_bytes.len().div_ceil(chunk_count);
         Self {
@@ -46,6 +49,31 @@ impl<'a> Iterator for ChunkIter<'a> {
     }
 }
 
+/// Returns the index of the first line beginning at or after `position`, or
+/// the length of the bytes if there is none.
+///
+/// A line belongs to the range that contains its first byte, which is the
+/// same rule [`ChunkIter`] applies.
+#[must_use]
+pub fn line_start_at_or_after(bytes: &[u8], position: usize) -> usize {
+    if position == 0 {
+        return 0;
+    }
+    if position >= bytes.len() {
+        return bytes.len();
+    }
+    // -1: the line begins at `position` if the previous byte is a newline
+    memchr::memchr(b'\n', &bytes[position - 1..]).map_or(bytes.len(), |newline| position + newline)
+}
+
+/// Snaps both ends of the byte range to line beginnings, so that the range
+/// contains exactly the lines whose first byte is within it. Adjacent ranges
+/// stay adjacent after snapping.
+#[must_use]
+pub fn snap_to_lines(bytes: &[u8], range: Range<usize>) -> Range<usize> {
+    line_start_at_or_after(bytes, range.start)..line_start_at_or_after(bytes, range.end)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,4 +129,26 @@ mod tests {
         assert_eq!(Some("bb\ncc\ndd\nee\n"), iter.next());
         assert_eq!(Some("ff\ngg\n"), iter.next());
     }
+
+    #[test]
+    fn test_snap_to_lines() {
+        let data = b"aaa\nbbb\nccc\n".as_slice();
+        assert_eq!(line_start_at_or_after(data, 0), 0);
+        assert_eq!(line_start_at_or_after(data, 1), 4);
+        assert_eq!(line_start_at_or_after(data, 4), 4);
+        assert_eq!(line_start_at_or_after(data, 11), 12);
+        assert_eq!(line_start_at_or_after(data, 20), 12);
+
+        assert_eq!(snap_to_lines(data, 2..5), 4..8);
+        assert_eq!(snap_to_lines(data, 5..6), 8..8, "no line begins within");
+        assert_eq!(snap_to_lines(data, 0..12), 0..12);
+
+        // Consistent with the chunks of `ChunkIter`.
+        let mut start = 0;
+        for chunk in ChunkIter::new(data, 2) {
+            let end = start + chunk.len();
+            assert_eq!(snap_to_lines(data, start..end), start..end);
+            start = end;
+        }
+    }
 }
@@ -0,0 +1,206 @@
+//! The `no_std` core of the 1BRC implementation: the line parser (plus a
+//! validating one for untrusted input), the aggregated data per station, and
+//! the line-aligned chunking. It only needs an allocator, so it also runs on
+//! embedded devices and on wasm.
+//!
+//! Reading files, threads, and the hash maps are part of the `phips-1brc`
+//! crate, which builds on this one.
+
+#![no_std]
+#![deny(
+    clippy::all,
+    clippy::cargo,
+    clippy::nursery,
+    clippy::must_use_candidate,
+    // clippy::restriction,
+    // clippy::pedantic
+)]
+// now allow a few rules which are denied by the above statement
+// --> they are ridiculous and not necessary
+#![allow(
+    clippy::suboptimal_flops,
+    clippy::redundant_pub_crate,
+    clippy::fallible_impl_from,
+    clippy::option_if_let_else
+)]
+// I can't do anything about this; fault of the dependencies
+#![allow(clippy::multiple_crate_versions)]
+// allow: required because of derive macro.. :(
+#![allow(clippy::use_self)]
+#![deny(missing_debug_implementations)]
+#![deny(rustdoc::all)]
+
+extern crate alloc;
+
+mod aggregated_data;
+mod chunk_iter;
+mod validation;
+
+pub use aggregated_data::AggregatedData;
+pub use chunk_iter::{line_start_at_or_after, snap_to_lines, ChunkIter};
+pub use validation::{parse_line, InvalidLine, MAX_ABS_MEASUREMENT, MAX_STATION_LEN};
+
+use crate::data_set_properties::{MIN_MEASUREMENT_LEN, MIN_STATION_LEN};
+use alloc::collections::BTreeMap;
+use core::str::from_utf8_unchecked;
+
+/// Some characteristics specifically to the [1BRC data set](https://github.com/gunnarmorling/1brc/blob/db064194be375edc02d6dbcd21268ad40f7e2869/src/main/java/dev/morling/onebrc/CreateMeasurements.java).
+pub mod data_set_properties {
+    /// The amount of distinct weather stations (cities).
+    pub const STATIONS_IN_DATASET: usize = 413;
+    /// The minimum station name length (for example: `Jos`).
+    pub const MIN_STATION_LEN: usize = 3;
+    /// The minimum measurement (str) len (for example: `6.6`).
+    pub const MIN_MEASUREMENT_LEN: usize = 3;
+}
+
+/// Aggregates the lines per station, sorted by station name. This is the
+/// entry point for devices without threads, such as gateways that
+/// pre-aggregate sensor readings before they are uploaded.
+///
+/// The results of multiple calls are combined with [`AggregatedData::merge`].
+///
+/// # Safety
+/// All lines must be valid according to the rules of the challenge, see
+/// [`process_line`]. The last line must end with a newline.
+#[must_use]
+pub unsafe fn aggregate_lines(bytes: &[u8]) -> BTreeMap<&str, AggregatedData> {
+    let mut stats = BTreeMap::<_, AggregatedData>::new();
+    let mut consumed_bytes_count = 0;
+    while consumed_bytes_count < bytes.len() {
+        let remaining_bytes = unsafe { bytes.get_unchecked(consumed_bytes_count..) };
+        let (station, measurement) =
+            unsafe { process_line(remaining_bytes, &mut consumed_bytes_count) };
+        stats.entry(station).or_default().add_datapoint(measurement);
+    }
+    stats
+}
+
+/// Reads a line from the bytes and processes it.
+///
+/// This expects that `bytes[0]` is the beginning of a new line. It returns the
+/// processed data and updates the `consumed_bytes_count` so that the next
+/// iteration can begin at the beginning of a new line.
+///
+/// # Safety
+/// The line must be valid according to the rules of the challenge: a UTF-8
+/// station name of at least [`MIN_STATION_LEN`] bytes, `;`, a measurement
+/// with exactly one decimal place, and `\n`. No checks are performed.
+#[inline(always)]
+pub unsafe fn process_line<'a>(
+    bytes: &'a [u8],
+    consumed_bytes_count: &mut usize,
+) -> (&'a str, i16) {
+    // Look for ";", and skip irrelevant bytes beforehand.
+    let search_offset = MIN_STATION_LEN;
+    let delimiter = memchr::memchr(b';', unsafe { bytes.get_unchecked(search_offset..) })
+        .map(|pos| pos + search_offset)
+        .unwrap();
+    // Look for "\n", and skip irrelevant bytes beforehand.
+    let search_offset = delimiter + 1 + MIN_MEASUREMENT_LEN;
+    let newline = memchr::memchr(b'\n', unsafe { bytes.get_unchecked(search_offset..) })
+        .map(|pos| pos + search_offset)
+        .unwrap();
+
+    let station = unsafe { from_utf8_unchecked(bytes.get_unchecked(0..delimiter)) };
+    let measurement = unsafe { from_utf8_unchecked(bytes.get_unchecked(delimiter + 1..newline)) };
+
+    let measurement = unsafe { fast_f32_parse_encoded(measurement) };
+
+    // Ensure the next iteration works on the next line.
+    *consumed_bytes_count += newline + 1;
+
+    (station, measurement)
+}
+
+/// Optimized fast decimal number parsing that encodes a float in an integer,
+/// which is multiplied by 10.
+///
+/// This benefits from the fact that we know that all input data has exactly 1
+/// decimal place.
+///
+/// - `15.5` -> `155`
+/// - `-7.1` -> `-71`
+///
+/// The range of possible values is within `-99.9..=99.9`.
+///
+/// To get back to the actual floating point value, one has to convert the value
+/// to float and divide it by 10.
+///
+/// # Safety
+/// The input must not be empty.
+#[must_use]
+pub unsafe fn fast_f32_parse_encoded(input: &str) -> i16 {
+    let mut bytes = input.as_bytes();
+
+    let negative = unsafe { *bytes.get_unchecked(0) } == b'-';
+
+    if negative {
+        // Only parse digits.
+        bytes = unsafe { bytes.get_unchecked(1..) };
+    }
+
+    let mut val = 0;
+    for &byte in bytes {
+        if byte == b'.' {
+            continue;
+        }
+        let digit = (byte - b'0') as i16;
+        val = val * 10 + digit;
+    }
+
+    if negative {
+        -val
+    } else {
+        val
+    }
+}
+
+/// Rounds `numerator / denominator` to the nearest integer. Ties are rounded
+/// towards positive infinity, like the reference implementation of the 1BRC
+/// does.
+///
+/// For example, the mean in tenths of a degree is `round_half_up(sum, count)`.
+#[must_use]
+pub const fn round_half_up(numerator: i128, denominator: i128) -> i128 {
+    (2 * numerator + denominator).div_euclid(2 * denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate_lines() {
+        let input = "Berlin;10.0\nHamburg;-12.7\nNew York;21.5\nBerlin;-15.7\n";
+        let stats = unsafe { aggregate_lines(input.as_bytes()) };
+        assert_eq!(
+            stats.into_iter().collect::<alloc::vec::Vec<_>>(),
+            [
+                ("Berlin", AggregatedData::new(-157, 100, -57, 2)),
+                ("Hamburg", AggregatedData::new(-127, -127, -127, 1)),
+                ("New York", AggregatedData::new(215, 215, 215, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fast_f32_parse() {
+        unsafe {
+            assert_eq!(fast_f32_parse_encoded("0.0"), 00);
+            assert_eq!(fast_f32_parse_encoded("5.0"), 50);
+            assert_eq!(fast_f32_parse_encoded("5.7"), 57);
+            assert_eq!(fast_f32_parse_encoded("-5.7"), -57);
+            assert_eq!(fast_f32_parse_encoded("-99.9"), -999);
+        }
+    }
+
+    #[test]
+    fn test_round_half_up() {
+        assert_eq!(round_half_up(5, 2), 3);
+        assert_eq!(round_half_up(-5, 2), -2);
+        assert_eq!(round_half_up(4, 3), 1);
+        assert_eq!(round_half_up(-4, 3), -1);
+        assert_eq!(round_half_up(-7, 3), -2);
+    }
+}
@@ -0,0 +1,109 @@
+//! A validating line parser for untrusted input. In contrast to
+//! [`crate::process_line`], each line is checked against the specification of
+//! the challenge: a station name of 1 to [`MAX_STATION_LEN`] bytes of UTF-8
+//! without `;`, and a value within `-99.9..=99.9` with exactly one decimal
+//! place.
+
+use core::str::from_utf8;
+
+/// The maximum length of a station name in bytes.
+pub const MAX_STATION_LEN: usize = 100;
+
+/// The maximum absolute value, encoded as integer multiplied by 10.
+pub const MAX_ABS_MEASUREMENT: i16 = 999;
+
+/// Why a line is invalid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidLine {
+    Empty,
+    /// No `;`, an empty station name, or a value that isn't a number with
+    /// exactly one decimal place.
+    Malformed,
+    /// A value outside of `-99.9..=99.9`.
+    ValueOutOfRange,
+    /// A station name longer than [`MAX_STATION_LEN`] bytes.
+    StationTooLong,
+    /// A station name that isn't valid UTF-8.
+    InvalidUtf8,
+}
+
+/// Validates a line without the newline and returns the station and the
+/// measurement, encoded as integer multiplied by 10.
+pub fn parse_line(line: &[u8]) -> Result<(&str, i16), InvalidLine> {
+    if line.is_empty() {
+        return Err(InvalidLine::Empty);
+    }
+    let delimiter = memchr::memchr(b';', line).ok_or(InvalidLine::Malformed)?;
+    let (station, value) = (&line[..delimiter], &line[delimiter + 1..]);
+    if station.is_empty() {
+        return Err(InvalidLine::Malformed);
+    }
+    if station.len() > MAX_STATION_LEN {
+        return Err(InvalidLine::StationTooLong);
+    }
+    let station = from_utf8(station).map_err(|_| InvalidLine::InvalidUtf8)?;
+    let measurement = parse_measurement(value)?;
+    Ok((station, measurement))
+}
+
+/// Parses a value with exactly one decimal place into an integer multiplied
+/// by ten.
+fn parse_measurement(value: &[u8]) -> Result<i16, InvalidLine> {
+    let (negative, digits) = match value.split_first() {
+        Some((b'-', digits)) => (true, digits),
+        _ => (false, value),
+    };
+    let [integer @ .., b'.', decimal] = digits else {
+        return Err(InvalidLine::Malformed);
+    };
+    if integer.is_empty() || !integer.iter().chain([decimal]).all(u8::is_ascii_digit) {
+        return Err(InvalidLine::Malformed);
+    }
+
+    let mut encoded = 0_i64;
+    for &digit in integer.iter().chain([decimal]) {
+        encoded = encoded * 10 + (digit - b'0') as i64;
+        if encoded > i64::from(MAX_ABS_MEASUREMENT) {
+            return Err(InvalidLine::ValueOutOfRange);
+        }
+    }
+    Ok(if negative { -encoded } else { encoded } as i16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_measurement() {
+        assert_eq!(parse_measurement(b"0.0"), Ok(0));
+        assert_eq!(parse_measurement(b"-15.7"), Ok(-157));
+        assert_eq!(parse_measurement(b"99.9"), Ok(999));
+        assert_eq!(
+            parse_measurement(b"-100.0"),
+            Err(InvalidLine::ValueOutOfRange)
+        );
+        assert_eq!(
+            parse_measurement(b"123456789012345678901234.5"),
+            Err(InvalidLine::ValueOutOfRange)
+        );
+        assert_eq!(parse_measurement(b"1.25"), Err(InvalidLine::Malformed));
+        assert_eq!(parse_measurement(b"1"), Err(InvalidLine::Malformed));
+        assert_eq!(parse_measurement(b".5"), Err(InvalidLine::Malformed));
+        assert_eq!(parse_measurement(b"-"), Err(InvalidLine::Malformed));
+        assert_eq!(parse_measurement(b"1.x"), Err(InvalidLine::Malformed));
+        assert_eq!(parse_measurement(b"1.0\r"), Err(InvalidLine::Malformed));
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line(b"Berlin;-15.7"), Ok(("Berlin", -157)));
+        assert_eq!(parse_line(b""), Err(InvalidLine::Empty));
+        assert_eq!(parse_line(b";1.0"), Err(InvalidLine::Malformed));
+        assert_eq!(parse_line(b"Berlin"), Err(InvalidLine::Malformed));
+        assert_eq!(parse_line(b"\xff;1.0"), Err(InvalidLine::InvalidUtf8));
+        let mut long = [b'x'; MAX_STATION_LEN + 5];
+        long[MAX_STATION_LEN + 1..].copy_from_slice(b";1.0");
+        assert_eq!(parse_line(&long), Err(InvalidLine::StationTooLong));
+    }
+}
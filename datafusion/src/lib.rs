@@ -0,0 +1,384 @@
+//! DataFusion integration: a [`TableProvider`] over a measurements file, and
+//! an optimizer rule that delegates `min`/`max`/`avg`/`count` of the
+//! temperature grouped by station to the optimized aggregation of the
+//! library.
+//!
+//! ```no_run
+//! # async fn example() -> datafusion::error::Result<()> {
+//! let ctx = phips_1brc_datafusion::session_context();
+//! phips_1brc_datafusion::register_measurements(&ctx, "measurements.txt")?;
+//! let stations = ctx
+//!     .sql(
+//!         "SELECT station, min(temperature), avg(temperature), max(temperature) \
+//!          FROM 'measurements.txt' GROUP BY station",
+//!     )
+//!     .await?
+//!     .collect()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+#![deny(
+    clippy::all,
+    clippy::cargo,
+    clippy::nursery,
+    clippy::must_use_candidate,
+    // clippy::restriction,
+    // clippy::pedantic
+)]
+// now allow a few rules which are denied by the above statement
+// --> they are ridiculous and not necessary
+#![allow(
+    clippy::suboptimal_flops,
+    clippy::redundant_pub_crate,
+    clippy::fallible_impl_from,
+    clippy::option_if_let_else
+)]
+// I can't do anything about this; fault of the dependencies
+#![allow(clippy::multiple_crate_versions)]
+// allow: required because of derive macro.. :(
+#![allow(clippy::use_self)]
+#![deny(missing_debug_implementations)]
+#![deny(rustdoc::all)]
+
+mod pushdown;
+
+pub use pushdown::AggregatePushdown;
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{Float64Builder, StringBuilder};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::streaming::StreamingTable;
+use datafusion::catalog::{Session, TableProvider};
+use datafusion::datasource::TableType;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::session_state::SessionStateBuilder;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::PartitionStream;
+use datafusion::physical_plan::ExecutionPlan;
+use datafusion::prelude::SessionContext;
+use phips_1brc::{OutputOptions, STATION_COLUMN, TEMPERATURE_COLUMN};
+use std::any::Any;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::thread::available_parallelism;
+
+/// The amount of rows per record batch of a scan.
+const BATCH_SIZE: usize = 8192;
+
+/// Creates a session with the [`AggregatePushdown`] rule.
+#[must_use]
+pub fn session_context() -> SessionContext {
+    let state = SessionStateBuilder::new()
+        .with_default_features()
+        .with_optimizer_rule(Arc::new(AggregatePushdown))
+        .build();
+    SessionContext::new_with_state(state)
+}
+
+/// Registers the measurements file as table, named like the path, so that it
+/// can be queried with `FROM '<path>'`.
+pub fn register_measurements(ctx: &SessionContext, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy().into_owned();
+    ctx.register_table(name, Arc::new(MeasurementsTable::try_new(path)?))?;
+    Ok(())
+}
+
+/// A measurements file (`<station>;<measurement>` per line) as table with the
+/// columns `station` (Utf8) and `temperature` (Float64).
+///
+/// Scans read the line-aligned chunks of the regular multi-threaded
+/// processing as partitions. Aggregations that [`AggregatePushdown`]
+/// recognizes don't scan the rows at all.
+#[derive(Debug, Clone)]
+pub struct MeasurementsTable {
+    path: PathBuf,
+    threads: NonZeroUsize,
+    /// Empty files have no chunks; the library can't process them.
+    empty: bool,
+}
+
+impl MeasurementsTable {
+    /// Uses one partition per available core.
+    pub fn try_new(path: impl Into<PathBuf>) -> Result<Self> {
+        let threads = available_parallelism()?;
+        Self::with_threads(path, threads)
+    }
+
+    /// Uses the given amount of partitions and threads.
+    pub fn with_threads(path: impl Into<PathBuf>, threads: NonZeroUsize) -> Result<Self> {
+        let path = path.into();
+        // The library panics on I/O errors.
+        let empty = File::open(&path)?.metadata()?.len() == 0;
+        Ok(Self {
+            path,
+            threads,
+            empty,
+        })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The stations of the file, aggregated with the library. This is what
+    /// [`AggregatePushdown`] scans instead of the rows.
+    fn aggregate(&self) -> RecordBatch {
+        let results = if self.empty {
+            phips_1brc::Results::default()
+        } else {
+            phips_1brc::aggregate_with_threads(&self.path, self.threads, None)
+        };
+        results
+            .to_record_batch(&OutputOptions::default())
+            .expect("no ranking")
+    }
+}
+
+fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new(STATION_COLUMN, DataType::Utf8, false),
+        Field::new(TEMPERATURE_COLUMN, DataType::Float64, false),
+    ]))
+}
+
+#[async_trait]
+impl TableProvider for MeasurementsTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let chunks = if self.empty {
+            Vec::new()
+        } else {
+            phips_1brc::chunk_boundaries(&self.path, self.threads)
+        };
+        let partitions = chunks
+            .into_iter()
+            .map(|range| {
+                Arc::new(ChunkPartition {
+                    path: self.path.clone(),
+                    range,
+                }) as Arc<dyn PartitionStream>
+            })
+            .collect();
+        StreamingTable::try_new(schema(), partitions)?
+            .scan(state, projection, filters, limit)
+            .await
+    }
+}
+
+/// The rows of a line-aligned chunk of the file.
+#[derive(Debug)]
+struct ChunkPartition {
+    path: PathBuf,
+    range: Range<usize>,
+}
+
+impl PartitionStream for ChunkPartition {
+    fn schema(&self) -> &SchemaRef {
+        // `PartitionStream` requires a reference.
+        static SCHEMA: OnceLock<SchemaRef> = OnceLock::new();
+        SCHEMA.get_or_init(schema)
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let batches = ChunkBatches::open(&self.path, self.range.clone());
+        let batches: Box<dyn Iterator<Item = Result<RecordBatch>> + Send> = match batches {
+            Ok(batches) => Box::new(batches),
+            Err(err) => Box::new(std::iter::once(Err(err.into()))),
+        };
+        Box::pin(RecordBatchStreamAdapter::new(
+            schema(),
+            futures::stream::iter(batches),
+        ))
+    }
+}
+
+/// Parses the lines of a chunk into record batches of [`BATCH_SIZE`] rows.
+#[derive(Debug)]
+struct ChunkBatches {
+    reader: io::Take<BufReader<File>>,
+    line: Vec<u8>,
+}
+
+impl ChunkBatches {
+    fn open(path: &Path, range: Range<usize>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(range.start as u64))?;
+        Ok(Self {
+            reader: BufReader::with_capacity(1 << 20, file).take(range.len() as u64),
+            line: Vec::new(),
+        })
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let mut stations = StringBuilder::new();
+        let mut temperatures = Float64Builder::with_capacity(BATCH_SIZE);
+        while temperatures.len() < BATCH_SIZE {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                break;
+            }
+            let (station, temperature) = parse_line(&self.line)?;
+            stations.append_value(station);
+            temperatures.append_value(temperature);
+        }
+        if temperatures.is_empty() {
+            return Ok(None);
+        }
+        let batch = RecordBatch::try_new(
+            schema(),
+            vec![Arc::new(stations.finish()), Arc::new(temperatures.finish())],
+        )?;
+        Ok(Some(batch))
+    }
+}
+
+impl Iterator for ChunkBatches {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch().transpose()
+    }
+}
+
+fn parse_line(line: &[u8]) -> Result<(&str, f64)> {
+    let invalid =
+        || DataFusionError::Execution(format!("invalid line: {:?}", String::from_utf8_lossy(line)));
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let (station, temperature) = line
+        .trim_end_matches('\n')
+        .split_once(';')
+        .ok_or_else(invalid)?;
+    let temperature = temperature.parse().map_err(|_| invalid())?;
+    Ok((station, temperature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::AsArray;
+    use datafusion::arrow::datatypes::{Float64Type, Int64Type};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+
+    const MEASUREMENTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../measurements_10000.txt");
+
+    fn register(ctx: &SessionContext) {
+        ctx.register_table(
+            "measurements",
+            Arc::new(
+                MeasurementsTable::with_threads(MEASUREMENTS, NonZeroUsize::new(4).unwrap())
+                    .unwrap(),
+            ),
+        )
+        .unwrap();
+    }
+
+    fn context() -> SessionContext {
+        let ctx = session_context();
+        register(&ctx);
+        ctx
+    }
+
+    /// Like [`context`], but without the [`AggregatePushdown`] rule, so that
+    /// DataFusion aggregates the scanned rows itself.
+    fn context_without_pushdown() -> SessionContext {
+        let ctx = SessionContext::new();
+        register(&ctx);
+        ctx
+    }
+
+    const QUERY: &str = "SELECT station, min(temperature), avg(temperature), \
+                         max(temperature), count(temperature) \
+                         FROM measurements GROUP BY station ORDER BY station";
+
+    async fn explain(ctx: &SessionContext) -> String {
+        let plan = ctx.sql(&format!("EXPLAIN {QUERY}")).await.unwrap();
+        pretty_format_batches(&plan.collect().await.unwrap())
+            .unwrap()
+            .to_string()
+    }
+
+    /// The rows of the result of [`QUERY`]: station, min, mean, max, and count.
+    async fn rows(ctx: &SessionContext) -> Vec<(String, f64, f64, f64, i64)> {
+        let batches = ctx.sql(QUERY).await.unwrap().collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let station = batch.column(0).as_string::<i32>();
+                let min = batch.column(1).as_primitive::<Float64Type>();
+                let mean = batch.column(2).as_primitive::<Float64Type>();
+                let max = batch.column(3).as_primitive::<Float64Type>();
+                let count = batch.column(4).as_primitive::<Int64Type>();
+                (0..batch.num_rows()).map(move |row| {
+                    (
+                        station.value(row).to_string(),
+                        min.value(row),
+                        mean.value(row),
+                        max.value(row),
+                        count.value(row),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_pushdown() {
+        let ctx = context();
+        let plan = explain(&ctx).await;
+        assert!(!plan.contains("AggregateExec"), "{plan}");
+
+        let rows = rows(&ctx).await;
+        let expected = phips_1brc::aggregate_with_threads(MEASUREMENTS, NonZeroUsize::MIN, None);
+        assert_eq!(rows.len(), expected.stations().len());
+
+        let (station, data) = &expected.stations()[0];
+        let (row_station, min, _, _, count) = &rows[0];
+        assert_eq!(row_station, station);
+        assert_eq!(*min, f64::from(data.encoded_min()) / 10.0);
+        assert_eq!(*count, i64::from(data.count()));
+    }
+
+    #[tokio::test]
+    async fn test_same_result_as_scan() {
+        let ctx = context_without_pushdown();
+        let plan = explain(&ctx).await;
+        assert!(plan.contains("AggregateExec"), "{plan}");
+
+        let scanned = rows(&ctx).await;
+        let pushed_down = rows(&context()).await;
+        assert_eq!(scanned.len(), pushed_down.len());
+        for (a, b) in scanned.iter().zip(&pushed_down) {
+            assert_eq!((&a.0, a.1, a.3, a.4), (&b.0, b.1, b.3, b.4));
+            // The scan sums the parsed floats, the library the exact integers.
+            assert!((a.2 - b.2).abs() < 1e-9, "{a:?} != {b:?}");
+        }
+    }
+}
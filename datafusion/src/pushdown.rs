@@ -0,0 +1,198 @@
+//! The optimizer rule that replaces supported aggregations over a
+//! [`MeasurementsTable`] with a scan of the aggregated stations.
+
+use crate::MeasurementsTable;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{DataType, SchemaRef};
+use datafusion::catalog::streaming::StreamingTable;
+use datafusion::catalog::{Session, TableProvider};
+use datafusion::common::tree_node::Transformed;
+use datafusion::datasource::{provider_as_source, source_as_provider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::{cast, col, lit, Aggregate, Expr, LogicalPlan, LogicalPlanBuilder};
+use datafusion::optimizer::{ApplyOrder, OptimizerConfig, OptimizerRule};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::PartitionStream;
+use datafusion::physical_plan::ExecutionPlan;
+use phips_1brc::{Results, STATION_COLUMN, TEMPERATURE_COLUMN};
+use std::any::Any;
+use std::sync::{Arc, OnceLock};
+
+/// Rewrites `Aggregate(TableScan(MeasurementsTable))` into a projection over
+/// the stations aggregated by the library, if
+///
+/// - the only group is the `station` column,
+/// - all aggregates are `min`, `max`, `avg`, or `count` of the `temperature`
+///   column (or `count(*)`), without `DISTINCT`, `FILTER`, or `ORDER BY`,
+/// - and the scan has no filters or limit.
+///
+/// The mean is computed from the exact sum of the measurements, so the results
+/// are equal to those of the regular aggregation, up to floating point
+/// rounding.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AggregatePushdown;
+
+impl OptimizerRule for AggregatePushdown {
+    fn name(&self) -> &str {
+        "phips_1brc_aggregate_pushdown"
+    }
+
+    fn apply_order(&self) -> Option<ApplyOrder> {
+        Some(ApplyOrder::BottomUp)
+    }
+
+    fn rewrite(
+        &self,
+        plan: LogicalPlan,
+        _config: &dyn OptimizerConfig,
+    ) -> Result<Transformed<LogicalPlan>> {
+        let LogicalPlan::Aggregate(aggregate) = &plan else {
+            return Ok(Transformed::no(plan));
+        };
+        match pushdown(aggregate)? {
+            Some(rewritten) => Ok(Transformed::yes(rewritten)),
+            None => Ok(Transformed::no(plan)),
+        }
+    }
+}
+
+fn pushdown(aggregate: &Aggregate) -> Result<Option<LogicalPlan>> {
+    let LogicalPlan::TableScan(scan) = aggregate.input.as_ref() else {
+        return Ok(None);
+    };
+    if !scan.filters.is_empty() || scan.fetch.is_some() {
+        return Ok(None);
+    }
+    let Ok(provider) = source_as_provider(&scan.source) else {
+        return Ok(None);
+    };
+    let Some(table) = provider.as_any().downcast_ref::<MeasurementsTable>() else {
+        return Ok(None);
+    };
+    let [group] = aggregate.group_expr.as_slice() else {
+        return Ok(None);
+    };
+    if !is_column(group, STATION_COLUMN) {
+        return Ok(None);
+    }
+    let Some(statistics) = aggregate
+        .aggr_expr
+        .iter()
+        .map(statistic)
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Ok(None);
+    };
+
+    // Keep the (qualified) names of the output of the aggregate, as the plan
+    // above refers to them.
+    let exprs = std::iter::once(col(STATION_COLUMN))
+        .chain(statistics)
+        .enumerate()
+        .map(|(index, expr)| {
+            let (qualifier, field) = aggregate.schema.qualified_field(index);
+            expr.alias_qualified(qualifier.cloned(), field.name())
+        });
+    let source = provider_as_source(Arc::new(AggregatedTable(table.clone())));
+    LogicalPlanBuilder::scan(scan.table_name.clone(), source, None)?
+        .project(exprs)?
+        .build()
+        .map(Some)
+}
+
+/// The expression over the columns of [`Results::to_record_batch`] that
+/// computes the aggregate, if it is supported.
+fn statistic(expr: &Expr) -> Option<Expr> {
+    let Expr::AggregateFunction(function) = expr else {
+        return None;
+    };
+    let params = &function.params;
+    if params.distinct || params.filter.is_some() || params.order_by.is_some() {
+        return None;
+    }
+    let [argument] = params.args.as_slice() else {
+        return None;
+    };
+    let name = function.func.name();
+    // `count(*)` is planned as `count(1)`. The temperature is never null, so
+    // it counts the same rows.
+    let counts_rows =
+        name == "count" && matches!(argument, Expr::Literal(value) if !value.is_null());
+    if !is_column(argument, TEMPERATURE_COLUMN) && !counts_rows {
+        return None;
+    }
+    match name {
+        "min" => Some(col("min")),
+        "max" => Some(col("max")),
+        // The sum is encoded as integer multiplied by 10.
+        "avg" => Some(
+            cast(col("sum"), DataType::Float64) / lit(10.0) / cast(col("count"), DataType::Float64),
+        ),
+        "count" => Some(cast(col("count"), DataType::Int64)),
+        _ => None,
+    }
+}
+
+fn is_column(expr: &Expr, name: &str) -> bool {
+    matches!(expr, Expr::Column(column) if column.name == name)
+}
+
+/// The stations of a [`MeasurementsTable`], with the columns of
+/// [`Results::to_record_batch`].
+#[derive(Debug)]
+struct AggregatedTable(MeasurementsTable);
+
+#[async_trait]
+impl TableProvider for AggregatedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Results::arrow_schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Temporary
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let partition: Arc<dyn PartitionStream> = Arc::new(AggregatedPartition(self.0.clone()));
+        StreamingTable::try_new(self.schema(), vec![partition])?
+            .scan(state, projection, filters, limit)
+            .await
+    }
+}
+
+/// Aggregates the whole file with the library, with its own threads.
+#[derive(Debug)]
+struct AggregatedPartition(MeasurementsTable);
+
+impl PartitionStream for AggregatedPartition {
+    fn schema(&self) -> &SchemaRef {
+        // `PartitionStream` requires a reference.
+        static SCHEMA: OnceLock<SchemaRef> = OnceLock::new();
+        SCHEMA.get_or_init(Results::arrow_schema)
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let table = self.0.clone();
+        let batch = async move {
+            // Don't block the executor while the threads of the library work.
+            tokio::task::spawn_blocking(move || table.aggregate())
+                .await
+                .map_err(|err| DataFusionError::External(Box::new(err)))
+        };
+        Box::pin(RecordBatchStreamAdapter::new(
+            Results::arrow_schema(),
+            futures::stream::once(batch),
+        ))
+    }
+}
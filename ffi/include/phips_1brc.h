@@ -0,0 +1,102 @@
+#ifndef PHIPS_1BRC_H
+#define PHIPS_1BRC_H
+
+/* Generated by cbindgen from ffi/src/lib.rs. Regenerate it with
+ * `UPDATE_HEADER=1 cargo test -p phips-1brc-ffi --test c_api`. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The aggregated stations of a run, sorted by station name.
+ */
+typedef struct OnebrcResults OnebrcResults;
+
+/**
+ * The statistics of a single station. All temperatures are in degrees
+ * Celsius and rounded to one decimal place, like in the JSON output.
+ */
+typedef struct OnebrcStation {
+  /**
+   * The UTF-8 encoded name, which is *not* null-terminated. Only valid as
+   * long as the results live.
+   */
+  const uint8_t *station;
+  /**
+   * The length of the name in bytes.
+   */
+  size_t station_len;
+  double min;
+  double mean;
+  double max;
+  uint32_t count;
+} OnebrcStation;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Aggregates the measurements file at the path with the given amount of
+ * threads, or with the available parallelism if `threads` is 0.
+ *
+ * Returns null if the file can't be opened. The file must be valid
+ * according to the rules of the challenge; see [`onebrc_process_buffer`].
+ *
+ * # Safety
+ * `path` must be a valid, null-terminated string.
+ */
+struct OnebrcResults *onebrc_process_path(const char *path, size_t threads);
+
+/**
+ * Aggregates the measurements in the buffer with the given amount of
+ * threads, or with the available parallelism if `threads` is 0.
+ *
+ * The buffer is parsed with the same unchecked fast path as files: it must
+ * contain valid lines (`<station>;<measurement>\n`, the measurement with
+ * exactly one decimal place) and end with a newline. Returns null if it
+ * doesn't end with a newline.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes, which must not be modified
+ * during the call.
+ */
+struct OnebrcResults *onebrc_process_buffer(const uint8_t *data, size_t len, size_t threads);
+
+/**
+ * The amount of stations in the results.
+ *
+ * # Safety
+ * `results` must be a valid handle returned by this library.
+ */
+size_t onebrc_results_len(const struct OnebrcResults *results);
+
+/**
+ * Writes the station with the given index (in the order of the station names)
+ * into `out`. Returns false if the index is out of bounds.
+ *
+ * # Safety
+ * `results` must be a valid handle returned by this library and `out` must
+ * be valid for writes.
+ */
+bool onebrc_results_get(const struct OnebrcResults *results,
+                        size_t index,
+                        struct OnebrcStation *out);
+
+/**
+ * Frees the results. Null is ignored.
+ *
+ * # Safety
+ * `results` must be null or a handle returned by this library that wasn't
+ * freed yet. The stations of the results must not be used afterward.
+ */
+void onebrc_results_free(struct OnebrcResults *results);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PHIPS_1BRC_H */
@@ -0,0 +1,235 @@
+//! The C ABI of `phips-1brc`, for embedding it into services written in
+//! other languages. The header is `include/phips_1brc.h`.
+//!
+//! The results are returned as an opaque handle, which must be freed with
+//! [`onebrc_results_free`]. No function unwinds into the caller: failures,
+//! including panics, are reported as a null pointer.
+
+#![deny(
+    clippy::all,
+    clippy::cargo,
+    clippy::nursery,
+    clippy::must_use_candidate,
+    // clippy::restriction,
+    // clippy::pedantic
+)]
+// now allow a few rules which are denied by the above statement
+// --> they are ridiculous and not necessary
+#![allow(
+    clippy::suboptimal_flops,
+    clippy::redundant_pub_crate,
+    clippy::fallible_impl_from,
+    clippy::option_if_let_else
+)]
+// I can't do anything about this; fault of the dependencies
+#![allow(clippy::multiple_crate_versions)]
+// allow: required because of derive macro.. :(
+#![allow(clippy::use_self)]
+#![deny(missing_debug_implementations)]
+#![deny(rustdoc::all)]
+
+use phips_1brc::{
+    aggregate_buffer, aggregate_with_threads, Results, StationRecord, TemperatureUnit,
+};
+use std::ffi::{c_char, CStr};
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::thread::available_parallelism;
+use std::{ptr, slice};
+
+/// The aggregated stations of a run, sorted by station name.
+#[derive(Debug)]
+pub struct OnebrcResults(Results);
+
+/// The statistics of a single station. All temperatures are in degrees
+/// Celsius and rounded to one decimal place, like in the JSON output.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct OnebrcStation {
+    /// The UTF-8 encoded name, which is *not* null-terminated. Only valid as
+    /// long as the results live.
+    pub station: *const u8,
+    /// The length of the name in bytes.
+    pub station_len: usize,
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub count: u32,
+}
+
+fn threads(threads: usize) -> NonZeroUsize {
+    NonZeroUsize::new(threads).unwrap_or_else(|| available_parallelism().unwrap())
+}
+
+/// Runs the closure and moves its results to the heap. Returns null if it
+/// panics.
+fn into_handle(f: impl FnOnce() -> Option<Results>) -> *mut OnebrcResults {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Some(results)) => Box::into_raw(Box::new(OnebrcResults(results))),
+        Ok(None) | Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Aggregates the measurements file at the path with the given amount of
+/// threads, or with the available parallelism if `threads` is 0.
+///
+/// Returns null if the file can't be opened. The file must be valid
+/// according to the rules of the challenge; see [`onebrc_process_buffer`].
+///
+/// # Safety
+/// `path` must be a valid, null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_process_path(
+    path: *const c_char,
+    threads: usize,
+) -> *mut OnebrcResults {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return ptr::null_mut();
+    };
+    let path = Path::new(path);
+    into_handle(|| {
+        // The library panics on I/O errors.
+        File::open(path).ok()?;
+        let empty = path.metadata().ok()?.len() == 0;
+        Some(if empty {
+            Results::default()
+        } else {
+            aggregate_with_threads(path, self::threads(threads), None)
+        })
+    })
+}
+
+/// Aggregates the measurements in the buffer with the given amount of
+/// threads, or with the available parallelism if `threads` is 0.
+///
+/// The buffer is parsed with the same unchecked fast path as files: it must
+/// contain valid lines (`<station>;<measurement>\n`, the measurement with
+/// exactly one decimal place) and end with a newline. Returns null if it
+/// doesn't end with a newline.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, which must not be modified
+/// during the call.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_process_buffer(
+    data: *const u8,
+    len: usize,
+    threads: usize,
+) -> *mut OnebrcResults {
+    if len == 0 {
+        return into_handle(|| Some(Results::default()));
+    }
+    if data.is_null() {
+        return ptr::null_mut();
+    }
+    let bytes = unsafe { slice::from_raw_parts(data, len) };
+    into_handle(|| {
+        (bytes.last() == Some(&b'\n'))
+            .then(|| aggregate_buffer(bytes, self::threads(threads), None))
+    })
+}
+
+/// The amount of stations in the results.
+///
+/// # Safety
+/// `results` must be a valid handle returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_results_len(results: *const OnebrcResults) -> usize {
+    unsafe { (*results).0.stations() }.len()
+}
+
+/// Writes the station with the given index (in the order of the station names)
+/// into `out`. Returns false if the index is out of bounds.
+///
+/// # Safety
+/// `results` must be a valid handle returned by this library and `out` must
+/// be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_results_get(
+    results: *const OnebrcResults,
+    index: usize,
+    out: *mut OnebrcStation,
+) -> bool {
+    let Some((station, data)) = unsafe { (*results).0.stations() }.get(index) else {
+        return false;
+    };
+    let record = StationRecord::new(station, data, TemperatureUnit::Celsius);
+    unsafe {
+        out.write(OnebrcStation {
+            station: station.as_ptr(),
+            station_len: station.len(),
+            min: record.min,
+            mean: record.mean,
+            max: record.max,
+            count: record.count,
+        });
+    }
+    true
+}
+
+/// Frees the results. Null is ignored.
+///
+/// # Safety
+/// `results` must be null or a handle returned by this library that wasn't
+/// freed yet. The stations of the results must not be used afterward.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_results_free(results: *mut OnebrcResults) {
+    if !results.is_null() {
+        drop(unsafe { Box::from_raw(results) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::MaybeUninit;
+
+    #[test]
+    fn test_process_buffer() {
+        let input = b"Berlin;10.0\nHamburg;-12.7\nBerlin;-15.7\n";
+        unsafe {
+            let results = onebrc_process_buffer(input.as_ptr(), input.len(), 2);
+            assert!(!results.is_null());
+            assert_eq!(onebrc_results_len(results), 2);
+
+            let mut station = MaybeUninit::<OnebrcStation>::uninit();
+            assert!(onebrc_results_get(results, 0, station.as_mut_ptr()));
+            let station = station.assume_init();
+            let name = slice::from_raw_parts(station.station, station.station_len);
+            assert_eq!(name, b"Berlin");
+            assert_eq!(
+                (station.min, station.mean, station.max),
+                (-15.7, -2.8, 10.0)
+            );
+            assert_eq!(station.count, 2);
+
+            let mut station = MaybeUninit::<OnebrcStation>::uninit();
+            assert!(!onebrc_results_get(results, 2, station.as_mut_ptr()));
+            onebrc_results_free(results);
+
+            let missing_newline = b"Berlin;10.0";
+            assert!(onebrc_process_buffer(missing_newline.as_ptr(), 11, 1).is_null());
+            let empty = onebrc_process_buffer(ptr::null(), 0, 1);
+            assert_eq!(onebrc_results_len(empty), 0);
+            onebrc_results_free(empty);
+        }
+    }
+
+    #[test]
+    fn test_process_path() {
+        unsafe {
+            let results = onebrc_process_path(c"../measurements_10000.txt".as_ptr(), 0);
+            assert_eq!(onebrc_results_len(results), 413);
+            onebrc_results_free(results);
+
+            assert!(onebrc_process_path(c"does-not-exist.txt".as_ptr(), 0).is_null());
+            assert!(onebrc_process_path(ptr::null(), 0).is_null());
+            onebrc_results_free(ptr::null_mut());
+        }
+    }
+}
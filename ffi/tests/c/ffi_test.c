@@ -0,0 +1,90 @@
+/*
+ * Exercises the C ABI: processes the file given as first argument via its
+ * path and via a memory buffer, prints the stations in the format of the
+ * challenge, and checks that both results are equal.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "phips_1brc.h"
+
+static int fail(const char *message) {
+    fprintf(stderr, "ffi_test: %s\n", message);
+    return EXIT_FAILURE;
+}
+
+static char *read_file(const char *path, size_t *len) {
+    FILE *file = fopen(path, "rb");
+    if (file == NULL) {
+        return NULL;
+    }
+    fseek(file, 0, SEEK_END);
+    *len = (size_t)ftell(file);
+    fseek(file, 0, SEEK_SET);
+    char *data = malloc(*len);
+    if (data != NULL && fread(data, 1, *len, file) != *len) {
+        free(data);
+        data = NULL;
+    }
+    fclose(file);
+    return data;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        return fail("usage: ffi_test <measurements file>");
+    }
+
+    if (onebrc_process_path("does-not-exist.txt", 0) != NULL) {
+        return fail("expected NULL for a missing file");
+    }
+
+    OnebrcResults *from_path = onebrc_process_path(argv[1], 2);
+    if (from_path == NULL) {
+        return fail("failed to process the path");
+    }
+
+    size_t len = 0;
+    char *data = read_file(argv[1], &len);
+    if (data == NULL) {
+        return fail("failed to read the file");
+    }
+    OnebrcResults *from_buffer = onebrc_process_buffer((const uint8_t *)data, len, 0);
+    if (from_buffer == NULL) {
+        return fail("failed to process the buffer");
+    }
+
+    size_t stations = onebrc_results_len(from_path);
+    if (stations != onebrc_results_len(from_buffer)) {
+        return fail("path and buffer differ in the amount of stations");
+    }
+
+    printf("{");
+    for (size_t i = 0; i < stations; i++) {
+        OnebrcStation a;
+        OnebrcStation b;
+        if (!onebrc_results_get(from_path, i, &a) || !onebrc_results_get(from_buffer, i, &b)) {
+            return fail("failed to get a station");
+        }
+        if (a.station_len != b.station_len || memcmp(a.station, b.station, a.station_len) != 0 ||
+            a.min != b.min || a.mean != b.mean || a.max != b.max || a.count != b.count) {
+            return fail("path and buffer differ");
+        }
+        printf("%s%.*s=%.1f/%.1f/%.1f", i == 0 ? "" : ", ", (int)a.station_len,
+               (const char *)a.station, a.min, a.mean, a.max);
+    }
+    printf("}\n");
+
+    OnebrcStation out_of_bounds;
+    if (onebrc_results_get(from_path, stations, &out_of_bounds)) {
+        return fail("expected false for an index out of bounds");
+    }
+
+    onebrc_results_free(from_path);
+    onebrc_results_free(from_buffer);
+    onebrc_results_free(NULL);
+    free(data);
+    return EXIT_SUCCESS;
+}
@@ -0,0 +1,115 @@
+//! Tests of the C ABI: the header must be up to date, and a C program must be
+//! able to use the shared library.
+
+use phips_1brc::{Results, StationRecord, TemperatureUnit};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+const HEADER: &str = "include/phips_1brc.h";
+
+fn generate_header() -> String {
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        include_guard: Some("PHIPS_1BRC_H".to_string()),
+        autogen_warning: Some(
+            "/* Generated by cbindgen from ffi/src/lib.rs. Regenerate it with\n \
+             * `UPDATE_HEADER=1 cargo test -p phips-1brc-ffi --test c_api`. */"
+                .to_string(),
+        ),
+        cpp_compat: true,
+        usize_is_size_t: true,
+        documentation_style: cbindgen::DocumentationStyle::Doxy,
+        export: cbindgen::ExportConfig {
+            item_types: vec![
+                cbindgen::ItemType::Functions,
+                cbindgen::ItemType::Structs,
+                cbindgen::ItemType::OpaqueItems,
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut header = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(env!("CARGO_MANIFEST_DIR"))
+        .with_config(config)
+        .generate()
+        .unwrap()
+        .write(&mut header);
+    String::from_utf8(header).unwrap()
+}
+
+#[test]
+fn test_header_is_up_to_date() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(HEADER);
+    let header = generate_header();
+    if env::var_os("UPDATE_HEADER").is_some() {
+        fs::write(&path, header).unwrap();
+        return;
+    }
+    let committed = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        committed == header,
+        "{HEADER} is outdated, regenerate it with `UPDATE_HEADER=1 cargo test -p phips-1brc-ffi --test c_api`"
+    );
+}
+
+/// The directory of the shared library: the `deps` directory that contains
+/// this test. In contrast to `cargo build`, `cargo test` doesn't copy the
+/// library into its parent.
+fn library_dir() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_c_program() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi_test");
+    let library_dir = library_dir();
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+
+    let status = Command::new(compiler)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror"])
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests/c/ffi_test.c"))
+        .arg("-o")
+        .arg(&program)
+        .arg("-L")
+        .arg(&library_dir)
+        .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+        .arg("-lphips_1brc_ffi")
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to compile the C program");
+
+    let input = manifest_dir.join("../measurements_10000.txt");
+    let output = Command::new(&program).arg(&input).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let results: Results =
+        phips_1brc::aggregate_with_threads(&input, NonZeroUsize::new(1).unwrap(), None);
+    let stations = results
+        .stations()
+        .iter()
+        .map(|(station, data)| {
+            let record = StationRecord::new(station, data, TemperatureUnit::Celsius);
+            format!(
+                "{station}={:.1}/{:.1}/{:.1}",
+                record.min, record.mean, record.max
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{{{}}}\n", stations.join(", "))
+    );
+}
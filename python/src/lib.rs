@@ -0,0 +1,215 @@
+//! Python bindings of the library, built with `maturin`. See
+//! `python/pyproject.toml`.
+//!
+//! ```python
+//! import phips_1brc
+//!
+//! stations = phips_1brc.process("measurements.txt")
+//! table = phips_1brc.process("measurements.txt", threads=4, format="arrow")
+//! ```
+
+#![deny(
+    clippy::all,
+    clippy::cargo,
+    clippy::nursery,
+    clippy::must_use_candidate,
+    // clippy::restriction,
+    // clippy::pedantic
+)]
+// now allow a few rules which are denied by the above statement
+// --> they are ridiculous and not necessary
+#![allow(
+    clippy::suboptimal_flops,
+    clippy::redundant_pub_crate,
+    clippy::fallible_impl_from,
+    clippy::option_if_let_else
+)]
+// I can't do anything about this; fault of the dependencies
+#![allow(clippy::multiple_crate_versions)]
+// allow: required because of derive macro.. :(
+#![allow(clippy::use_self)]
+#![deny(missing_debug_implementations)]
+#![deny(rustdoc::all)]
+
+use phips_1brc::{aggregate_with_threads, Results, StationRecord, TemperatureUnit};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::thread::available_parallelism;
+
+/// The Python representation of the results.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    /// A dict of station name to a dict with `min`, `mean`, `max`, and
+    /// `count`.
+    Dict,
+    /// A `pyarrow.Table` with one row per station.
+    Arrow,
+}
+
+impl Format {
+    fn parse(format: &str) -> PyResult<Self> {
+        match format {
+            "dict" => Ok(Self::Dict),
+            "arrow" => Ok(Self::Arrow),
+            _ => Err(PyValueError::new_err(format!(
+                "unknown format {format:?}, expected \"dict\" or \"arrow\""
+            ))),
+        }
+    }
+}
+
+/// Aggregates the measurements file at the path, with the given amount of
+/// threads or with the available parallelism.
+///
+/// The GIL is released while the file is processed. All temperatures are in
+/// degrees Celsius and rounded to one decimal place, like in the JSON output
+/// of the CLI. The stations are sorted by name.
+#[pyfunction]
+#[pyo3(signature = (path, threads = None, format = "dict"))]
+fn process(
+    py: Python<'_>,
+    path: PathBuf,
+    threads: Option<usize>,
+    format: &str,
+) -> PyResult<Py<PyAny>> {
+    let format = Format::parse(format)?;
+    let threads = match threads {
+        None => available_parallelism()?,
+        Some(threads) => NonZeroUsize::new(threads)
+            .ok_or_else(|| PyValueError::new_err("threads must be at least 1"))?,
+    };
+    // The library panics on I/O errors; raise them as `OSError` instead.
+    let empty = fs::metadata(&path)?.len() == 0;
+    let results = if empty {
+        Results::default()
+    } else {
+        py.detach(|| aggregate_with_threads(&path, threads, None))
+    };
+
+    match format {
+        Format::Dict => to_dict(py, &results),
+        Format::Arrow => to_arrow(py, &results),
+    }
+}
+
+fn records(results: &Results) -> impl Iterator<Item = StationRecord<'_>> {
+    results
+        .stations()
+        .iter()
+        .map(|(station, data)| StationRecord::new(station, data, TemperatureUnit::Celsius))
+}
+
+fn to_dict(py: Python<'_>, results: &Results) -> PyResult<Py<PyAny>> {
+    let stations = PyDict::new(py);
+    for record in records(results) {
+        let stats = PyDict::new(py);
+        stats.set_item("min", record.min)?;
+        stats.set_item("mean", record.mean)?;
+        stats.set_item("max", record.max)?;
+        stats.set_item("count", record.count)?;
+        stations.set_item(record.station, stats)?;
+    }
+    Ok(stations.into_any().unbind())
+}
+
+/// Builds the table with `pyarrow`, which must be installed.
+fn to_arrow(py: Python<'_>, results: &Results) -> PyResult<Py<PyAny>> {
+    let columns = PyDict::new(py);
+    let records = records(results).collect::<Vec<_>>();
+    columns.set_item(
+        "station",
+        PyList::new(py, records.iter().map(|record| record.station))?,
+    )?;
+    columns.set_item(
+        "min",
+        PyList::new(py, records.iter().map(|record| record.min))?,
+    )?;
+    columns.set_item(
+        "mean",
+        PyList::new(py, records.iter().map(|record| record.mean))?,
+    )?;
+    columns.set_item(
+        "max",
+        PyList::new(py, records.iter().map(|record| record.max))?,
+    )?;
+    columns.set_item(
+        "count",
+        PyList::new(py, records.iter().map(|record| record.count))?,
+    )?;
+    let table = py.import("pyarrow")?.call_method1("table", (columns,))?;
+    Ok(table.unbind())
+}
+
+#[pymodule]
+#[pyo3(name = "phips_1brc")]
+fn python_module(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(process, module)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::exceptions::{PyFileNotFoundError, PyValueError};
+
+    const MEASUREMENTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../measurements_10000.txt");
+
+    #[test]
+    fn test_process_dict() {
+        Python::initialize();
+        Python::attach(|py| {
+            let stations = process(py, MEASUREMENTS.into(), Some(2), "dict").unwrap();
+            let stations = stations.cast_bound::<PyDict>(py).unwrap();
+            assert_eq!(stations.len(), 413);
+
+            let expected = aggregate_with_threads(MEASUREMENTS, NonZeroUsize::MIN, None);
+            let (station, data) = &expected.stations()[0];
+            let record = StationRecord::new(station, data, TemperatureUnit::Celsius);
+            let stats = stations.get_item(station).unwrap().unwrap();
+            let mean = stats.get_item("mean").unwrap().extract::<f64>().unwrap();
+            let count = stats.get_item("count").unwrap().extract::<u32>().unwrap();
+            assert_eq!((mean, count), (record.mean, record.count));
+        });
+    }
+
+    #[test]
+    fn test_process_arrow() {
+        Python::initialize();
+        Python::attach(|py| {
+            if py.import("pyarrow").is_err() {
+                eprintln!("pyarrow is not installed, skipping");
+                return;
+            }
+            let table = process(py, MEASUREMENTS.into(), None, "arrow").unwrap();
+            let table = table.bind(py);
+            let rows = table
+                .getattr("num_rows")
+                .unwrap()
+                .extract::<usize>()
+                .unwrap();
+            let columns = table
+                .getattr("column_names")
+                .unwrap()
+                .extract::<Vec<String>>()
+                .unwrap();
+            assert_eq!(rows, 413);
+            assert_eq!(columns, ["station", "min", "mean", "max", "count"]);
+        });
+    }
+
+    #[test]
+    fn test_process_errors() {
+        Python::initialize();
+        Python::attach(|py| {
+            let error = process(py, "does-not-exist.txt".into(), None, "dict").unwrap_err();
+            assert!(error.is_instance_of::<PyFileNotFoundError>(py));
+            let error = process(py, MEASUREMENTS.into(), Some(0), "dict").unwrap_err();
+            assert!(error.is_instance_of::<PyValueError>(py));
+            let error = process(py, MEASUREMENTS.into(), None, "csv").unwrap_err();
+            assert!(error.is_instance_of::<PyValueError>(py));
+        });
+    }
+}
@@ -0,0 +1,111 @@
+//! The extension point for custom per-station accumulators.
+
+use crate::AggregatedData;
+
+/// Accumulates the measurements of a single station.
+///
+/// Each thread owns one instance per station. The instances of all threads
+/// are merged before [`Self::finish`] is called once per station. The parser,
+/// the chunking, and the threading are the same for all implementations.
+///
+/// [`AggregatedData`] is the default implementation. The hot loop is generic
+/// over this trait, so it compiles to the same code as before.
+pub trait Aggregator: Default + Send {
+    /// The final result per station.
+    type Output;
+
+    /// Adds a measurement, encoded as integer multiplied by 10
+    /// (`-15.7 => -157`).
+    fn add(&mut self, measurement: i16);
+
+    /// Merges the accumulator of another thread into this one.
+    fn merge(&mut self, other: Self);
+
+    /// Finishes the accumulation.
+    fn finish(self) -> Self::Output;
+}
+
+impl Aggregator for AggregatedData {
+    type Output = Self;
+
+    #[inline(always)]
+    fn add(&mut self, measurement: i16) {
+        self.add_datapoint(measurement);
+    }
+
+    fn merge(&mut self, other: Self) {
+        Self::merge(self, &other);
+    }
+
+    fn finish(self) -> Self::Output {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aggregate_custom, aggregate_with_threads, process_file_chunk};
+    use std::num::NonZeroUsize;
+
+    /// Counts the measurements per sign.
+    #[derive(Debug, Default)]
+    struct SignCounter {
+        negative: u32,
+        positive: u32,
+    }
+
+    impl Aggregator for SignCounter {
+        type Output = (u32, u32);
+
+        fn add(&mut self, measurement: i16) {
+            if measurement < 0 {
+                self.negative += 1;
+            } else {
+                self.positive += 1;
+            }
+        }
+
+        fn merge(&mut self, other: Self) {
+            self.negative += other.negative;
+            self.positive += other.positive;
+        }
+
+        fn finish(self) -> Self::Output {
+            (self.negative, self.positive)
+        }
+    }
+
+    #[test]
+    fn test_custom_aggregator() {
+        let input = "Berlin;10.0\nHamburg;-12.7\nBerlin;-15.7\nBerlin;0.0\n";
+        let mut berlin = process_file_chunk::<SignCounter>(input.as_bytes())
+            .remove("Berlin")
+            .unwrap();
+        berlin.merge(SignCounter {
+            negative: 1,
+            positive: 0,
+        });
+        assert_eq!(berlin.finish(), (2, 2));
+    }
+
+    #[test]
+    fn test_aggregate_custom() {
+        let path = "measurements_10000.txt";
+        let threads = NonZeroUsize::new(2).unwrap();
+        let counts = aggregate_custom::<SignCounter>(path, threads);
+        let results = aggregate_with_threads(path, threads, None);
+
+        assert_eq!(counts.len(), results.stations().len());
+        for ((station, (negative, positive)), (expected_station, data)) in
+            counts.iter().zip(results.stations())
+        {
+            assert_eq!(station, expected_station);
+            assert_eq!(negative + positive, data.count());
+        }
+        assert_eq!(
+            aggregate_custom::<AggregatedData>(path, threads),
+            results.stations()
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! The routes of the query service, independent of the HTTP server.
+
+use phips_1brc::{
+    QualityOptions, RankBy, Ranking, Results, SortOrder, StationRecord, TemperatureUnit,
+};
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// The default amount of stations of `GET /top`.
+const DEFAULT_TOP: usize = 10;
+
+/// A JSON response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    const fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+/// The live aggregate and how to persist it.
+#[derive(Debug)]
+pub struct State {
+    results: RwLock<Results>,
+    /// Written after each ingest, if set.
+    snapshot: Option<PathBuf>,
+    threads: NonZeroUsize,
+}
+
+impl State {
+    pub const fn new(results: Results, snapshot: Option<PathBuf>, threads: NonZeroUsize) -> Self {
+        Self {
+            results: RwLock::new(results),
+            snapshot,
+            threads,
+        }
+    }
+
+    pub fn stations(&self) -> usize {
+        self.results.read().unwrap().stations().len()
+    }
+
+    /// Answers a request. The path may contain a query string.
+    pub fn handle(&self, method: &str, url: &str, body: &[u8]) -> Response {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .map(|segment| percent_decode(segment, false))
+            .collect::<Option<Vec<_>>>();
+        let Some(segments) = segments else {
+            return Response::error(400, "invalid percent-encoding in the path");
+        };
+        let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+        match (method, segments.as_slice()) {
+            ("GET", ["stations"]) => self.stations_route(),
+            ("GET", ["stations", station]) => self.station_route(station),
+            ("GET", ["top"]) => self.top_route(query),
+            ("POST", ["ingest"]) => self.ingest_route(body),
+            (_, ["stations"] | ["stations", _] | ["top"] | ["ingest"]) => {
+                Response::error(405, format!("method {method} not allowed"))
+            }
+            _ => Response::error(404, format!("no route for {path}")),
+        }
+    }
+
+    fn stations_route(&self) -> Response {
+        let records = self
+            .results
+            .read()
+            .unwrap()
+            .stations()
+            .iter()
+            .map(|(station, data)| record(station, data))
+            .collect::<Vec<_>>();
+        Response::ok(Value::Array(records))
+    }
+
+    fn station_route(&self, station: &str) -> Response {
+        let data = self.results.read().unwrap().get(station).cloned();
+        match data {
+            Some(data) => Response::ok(record(station, &data)),
+            None => Response::error(404, format!("unknown station `{station}`")),
+        }
+    }
+
+    /// `GET /top?by=<statistic>&n=<limit>&order=<asc|desc>`
+    fn top_route(&self, query: &str) -> Response {
+        let mut ranking = Ranking::new(RankBy::Mean).limit(DEFAULT_TOP);
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let Some(value) = percent_decode(value, true) else {
+                return Response::error(400, "invalid percent-encoding in the query");
+            };
+            match key {
+                "by" => match value.parse() {
+                    Ok(by) => ranking.by = by,
+                    Err(err) => return Response::error(400, err),
+                },
+                "n" => match value.parse() {
+                    Ok(n) => ranking.limit = Some(n),
+                    Err(_) => return Response::error(400, format!("invalid n `{value}`")),
+                },
+                "order" => match value.as_str() {
+                    "asc" => ranking.order = SortOrder::Ascending,
+                    "desc" => ranking.order = SortOrder::Descending,
+                    _ => return Response::error(400, format!("invalid order `{value}`")),
+                },
+                _ => return Response::error(400, format!("unknown parameter `{key}`")),
+            }
+        }
+
+        let results = self.results.read().unwrap();
+        match results.rank(&ranking) {
+            Ok(ranked) => Response::ok(Value::Array(
+                ranked
+                    .into_iter()
+                    .map(|(station, data)| record(station, data))
+                    .collect(),
+            )),
+            Err(err) => Response::error(400, err.to_string()),
+        }
+    }
+
+    /// Aggregates the body and merges it into the live state. The body is
+    /// rejected as a whole if it contains an invalid line.
+    fn ingest_route(&self, body: &[u8]) -> Response {
+        let options = QualityOptions {
+            max_errors: Some(0),
+            ..QualityOptions::default()
+        };
+        let ingested =
+            match phips_1brc::aggregate_bytes_validated(body, self.threads, None, &options) {
+                Ok(ingested) => ingested,
+                Err(err) => {
+                    return Response {
+                        status: 400,
+                        body: json!({ "error": "invalid measurements", "quality": err.report }),
+                    }
+                }
+            };
+        let lines = ingested.quality().map_or(0, |report| report.lines);
+        let stations = ingested.stations().len();
+
+        // Merge into a copy and only publish it once it is persisted, so that
+        // a failed snapshot doesn't leave the live state ahead of the file.
+        let mut results = self.results.write().unwrap();
+        let mut merged = results.clone();
+        merged.merge(ingested);
+        if let Err(err) = self.save_snapshot(&merged) {
+            return Response::error(500, format!("failed to save the snapshot: {err}"));
+        }
+        *results = merged;
+        Response::ok(json!({
+            "lines": lines,
+            "stations": stations,
+            "total_stations": results.stations().len(),
+        }))
+    }
+
+    /// Writes the snapshot, if configured. The previous snapshot is only
+    /// replaced once the new one is complete.
+    fn save_snapshot(&self, results: &Results) -> io::Result<()> {
+        let Some(path) = &self.snapshot else {
+            return Ok(());
+        };
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        results.write_snapshot(&mut writer)?;
+        writer.flush()?;
+        fs::rename(temporary, path)
+    }
+}
+
+fn record(station: &str, data: &phips_1brc::AggregatedData) -> Value {
+    serde_json::to_value(StationRecord::new(station, data, TemperatureUnit::Celsius)).unwrap()
+}
+
+/// Decodes `%XX` sequences, and `+` as space in query strings. `None` for
+/// invalid sequences or invalid UTF-8.
+fn percent_decode(input: &str, query: bool) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut iter = input.bytes();
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b'+' if query => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        let body = "Berlin;10.0\nHamburg;-12.7\nBerlin;-15.7\nWashington, D.C.;30.0\n";
+        let results = phips_1brc::aggregate_bytes_validated(
+            body.as_bytes(),
+            NonZeroUsize::new(1).unwrap(),
+            None,
+            &QualityOptions::default(),
+        )
+        .unwrap();
+        State::new(results, None, NonZeroUsize::new(1).unwrap())
+    }
+
+    #[test]
+    fn test_stations() {
+        let state = state();
+        let response = state.handle("GET", "/stations", b"");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body.as_array().unwrap().len(), 3);
+
+        let response = state.handle("GET", "/stations/Washington,%20D.C.", b"");
+        assert_eq!(
+            response.body,
+            json!({
+                "station": "Washington, D.C.",
+                "min": 30.0,
+                "mean": 30.0,
+                "max": 30.0,
+                "count": 1,
+            })
+        );
+        assert_eq!(state.handle("GET", "/stations/Oslo", b"").status, 404);
+        assert_eq!(state.handle("GET", "/stations/%zz", b"").status, 400);
+        assert_eq!(state.handle("DELETE", "/stations", b"").status, 405);
+        assert_eq!(state.handle("GET", "/", b"").status, 404);
+    }
+
+    #[test]
+    fn test_top() {
+        let state = state();
+        let stations = |url| {
+            let response = state.handle("GET", url, b"");
+            assert_eq!(response.status, 200, "{url}: {:?}", response.body);
+            response
+                .body
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|record| record["station"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(stations("/top"), ["Washington, D.C.", "Berlin", "Hamburg"]);
+        assert_eq!(stations("/top?by=min&n=1"), ["Washington, D.C."]);
+        assert_eq!(stations("/top?by=count&n=1&order=asc"), ["Hamburg"]);
+        assert_eq!(state.handle("GET", "/top?by=median", b"").status, 400);
+        assert_eq!(state.handle("GET", "/top?by=stddev", b"").status, 400);
+        assert_eq!(state.handle("GET", "/top?n=-1", b"").status, 400);
+        assert_eq!(state.handle("GET", "/top?limit=1", b"").status, 400);
+    }
+
+    #[test]
+    fn test_ingest() {
+        let state = state();
+        let response = state.handle("POST", "/ingest", b"Oslo;1.0\nBerlin;20.0\n");
+        assert_eq!(
+            response,
+            Response::ok(json!({ "lines": 2, "stations": 2, "total_stations": 4 }))
+        );
+        let berlin = state.handle("GET", "/stations/Berlin", b"");
+        assert_eq!(berlin.body["count"], 3);
+        assert_eq!(berlin.body["max"], 20.0);
+
+        let response = state.handle("POST", "/ingest", b"Oslo;1.0\nOslo;warm\n");
+        assert_eq!(response.status, 400);
+        assert_eq!(response.body["quality"]["skipped_lines"], 1);
+        assert_eq!(state.stations(), 4, "rejected bodies are not merged");
+    }
+
+    #[test]
+    fn test_ingest_with_failing_snapshot() {
+        let snapshot = std::env::temp_dir()
+            .join(format!("1brc-{}-missing", std::process::id()))
+            .join("snapshot.json");
+        let state = State {
+            snapshot: Some(snapshot),
+            ..state()
+        };
+        let response = state.handle("POST", "/ingest", b"Oslo;1.0\n");
+        assert_eq!(response.status, 500);
+        assert_eq!(state.stations(), 3, "unsaved bodies are not merged");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b+c", false).unwrap(), "a b+c");
+        assert_eq!(percent_decode("a%20b+c", true).unwrap(), "a b c");
+        assert_eq!(percent_decode("Z%C3%BCrich", false).unwrap(), "Zürich");
+        assert_eq!(percent_decode("%2", false), None);
+        assert_eq!(percent_decode("%ff", false), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! Keeps the results of a run resident and answers queries over HTTP. See
+//! `api` for the routes.
+
+#![deny(
+    clippy::all,
+    clippy::cargo,
+    clippy::nursery,
+    clippy::must_use_candidate,
+    // clippy::restriction,
+    // clippy::pedantic
+)]
+// now allow a few rules which are denied by the above statement
+// --> they are ridiculous and not necessary
+#![allow(
+    clippy::suboptimal_flops,
+    clippy::redundant_pub_crate,
+    clippy::fallible_impl_from,
+    clippy::option_if_let_else
+)]
+// I can't do anything about this; fault of the dependencies
+#![allow(clippy::multiple_crate_versions)]
+// allow: required because of derive macro.. :(
+#![allow(clippy::use_self)]
+// Not needed here. We only need this for the library!
+// #![deny(missing_docs)]
+#![deny(missing_debug_implementations)]
+#![deny(rustdoc::all)]
+
+mod api;
+
+use api::State;
+use clap::Parser;
+use phips_1brc::Results;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Instant;
+use tiny_http::{Header, Request, Server};
+
+type CliResult<T = ()> = Result<T, Box<dyn Error>>;
+
+/// Serves the aggregate of a measurements file as JSON:
+/// `GET /stations`, `GET /stations/{name}`, `GET /top?by=mean&n=10`, and
+/// `POST /ingest` with a measurements body.
+#[derive(Debug, Parser)]
+#[command(name = "1brc-server", version)]
+struct Args {
+    /// The measurements file to aggregate at startup. Without a file, the
+    /// server starts empty.
+    file: Option<PathBuf>,
+    /// Load the state from the snapshot instead of the file, if it exists,
+    /// and write the state into it after each ingest.
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<PathBuf>,
+    #[arg(long, short = 'l', default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// The amount of threads of the aggregation. Defaults to the available
+    /// parallelism.
+    #[arg(long, short = 't')]
+    threads: Option<NonZeroUsize>,
+    /// The amount of threads that answer requests.
+    #[arg(long, default_value_t = NonZeroUsize::new(4).unwrap())]
+    http_threads: NonZeroUsize,
+    /// The maximum size of an ingested body in MiB.
+    #[arg(long, value_name = "MIB", default_value_t = 1024)]
+    max_body: u64,
+}
+
+fn main() -> ExitCode {
+    run(&Args::parse()).map_or_else(
+        |err| {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        },
+        |()| ExitCode::SUCCESS,
+    )
+}
+
+fn run(args: &Args) -> CliResult {
+    let threads = args
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().unwrap());
+    let begin = Instant::now();
+    let results = load(args, threads)?;
+    let state = State::new(results, args.snapshot.clone(), threads);
+    eprintln!(
+        "loaded {} stations in {:?}",
+        state.stations(),
+        begin.elapsed()
+    );
+
+    let server = Server::http(args.listen)
+        .map_err(|err| format!("failed to listen on {}: {err}", args.listen))?;
+    eprintln!("listening on http://{}", args.listen);
+    let max_body = args.max_body * 1024 * 1024;
+    thread::scope(|scope| {
+        for _ in 0..args.http_threads.get() {
+            scope.spawn(|| {
+                while let Ok(request) = server.recv() {
+                    respond(&state, request, max_body);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Loads the snapshot if it exists, and otherwise aggregates the file.
+fn load(args: &Args, threads: NonZeroUsize) -> CliResult<Results> {
+    if let Some(path) = args.snapshot.as_ref().filter(|path| path.exists()) {
+        let file = File::open(path)?;
+        return Results::read_snapshot(BufReader::new(file))
+            .map_err(|err| format!("failed to load {}: {err}", path.display()).into());
+    }
+    let Some(file) = &args.file else {
+        return Ok(Results::default());
+    };
+    // The library panics on I/O errors, which is not user-friendly.
+    File::open(file).map_err(|err| format!("{}: {err}", file.display()))?;
+    Ok(phips_1brc::aggregate_with_threads(file, threads, None))
+}
+
+fn respond(state: &State, mut request: Request, max_body: u64) {
+    let too_large = request
+        .body_length()
+        .is_some_and(|length| length as u64 > max_body);
+    let mut body = Vec::new();
+    let response = if too_large
+        || request
+            .as_reader()
+            .take(max_body + 1)
+            .read_to_end(&mut body)
+            .is_err()
+        || body.len() as u64 > max_body
+    {
+        api::Response {
+            status: 413,
+            body: serde_json::json!({ "error": "the body is too large" }),
+        }
+    } else {
+        state.handle(request.method().as_str(), request.url(), &body)
+    };
+
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let http_response = tiny_http::Response::from_string(response.body.to_string())
+        .with_status_code(response.status)
+        .with_header(content_type);
+    // The client may have disconnected in the meantime.
+    let _ = request.respond(http_response);
+}
@@ -1,3 +1,5 @@
+//! Thin wrapper around `1brc run --strategy single`.
+
 #![deny(
     clippy::all,
     clippy::cargo,
@@ -11,7 +13,8 @@
 #![allow(
     clippy::suboptimal_flops,
     clippy::redundant_pub_crate,
-    clippy::fallible_impl_from
+    clippy::fallible_impl_from,
+    clippy::option_if_let_else
 )]
 // I can't do anything about this; fault of the dependencies
 #![allow(clippy::multiple_crate_versions)]
@@ -22,13 +25,24 @@
 #![deny(missing_debug_implementations)]
 #![deny(rustdoc::all)]
 
-use std::time::Instant;
+use phips_1brc::cli::{self, TimingOutput};
+use std::ffi::OsString;
+use std::iter;
+use std::process::ExitCode;
 
-fn main() {
-    let begin = Instant::now();
-    let file = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "./measurements.txt".to_string());
-    phips_1brc::process_single_threaded(file, true);
-    println!("took {:?}", begin.elapsed());
+/// Thin wrapper around `1brc run --strategy single`. CLI is:
+/// `[/path/to/measurements.txt] [<options of 1brc run>]`. Prints how long it
+/// took to stdout.
+fn main() -> ExitCode {
+    // The worker process already gets the arguments of `1brc run`.
+    if std::env::var_os(cli::WORKER_ENV).is_some() {
+        return cli::main_from(std::env::args_os(), TimingOutput::Stdout);
+    }
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_default();
+    let prefix = ["run", "--strategy", "single"].map(OsString::from);
+    cli::main_from(
+        iter::once(program).chain(prefix).chain(args),
+        TimingOutput::Stdout,
+    )
 }
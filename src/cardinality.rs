@@ -0,0 +1,252 @@
+//! Counting of lines and distinct stations, without aggregating the
+//! measurements.
+//!
+//! The exact count requires a set of all station names, which grows with the
+//! input. The [`HyperLogLog`] estimate has a fixed size, independent of the
+//! amount of distinct stations.
+
+use crate::hash::HashSet;
+use phips_1brc_core::data_set_properties::STATIONS_IN_DATASET;
+use phips_1brc_core::process_line;
+use serde::Serialize;
+use std::ops::Range;
+
+/// The precision of the [`HyperLogLog`] of [`crate::count_distinct`]: `2^14`
+/// registers, a standard error of roughly 0.8%.
+pub const HYPERLOGLOG_PRECISION: u8 = 14;
+
+/// A cardinality estimator with a fixed memory footprint of `2^precision`
+/// bytes.
+///
+/// See Flajolet et al., "HyperLogLog: the analysis of a near-optimal
+/// cardinality estimation algorithm" (2007).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Creates an empty estimator with `2^precision` registers. The precision
+    /// must be within `4..=18`.
+    #[must_use]
+    pub fn new(precision: u8) -> Self {
+        assert!(
+            (4..=18).contains(&precision),
+            "precision must be within 4..=18"
+        );
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// Adds the (uniformly distributed) hash of an element.
+    pub fn add_hash(&mut self, hash: u64) {
+        let index = (hash >> (64 - self.precision)) as usize;
+        let remaining = hash << self.precision;
+        // The position of the first 1-bit in the remaining bits.
+        let rank = (remaining.leading_zeros() + 1).min(64 - self.precision as u32 + 1) as u8;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    /// Adds a station name.
+    pub fn add(&mut self, station: &str) {
+        self.add_hash(crate::hash::hash64(station.as_bytes()));
+    }
+
+    /// Merges another estimator with the same precision into this one.
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(self.precision, other.precision);
+        self.registers
+            .iter_mut()
+            .zip(&other.registers)
+            .for_each(|(a, &b)| *a = (*a).max(b));
+    }
+
+    /// The standard error of the estimate, relative to the cardinality.
+    #[must_use]
+    pub fn standard_error(&self) -> f64 {
+        1.04 / (self.registers.len() as f64).sqrt()
+    }
+
+    /// The estimated amount of distinct elements.
+    #[must_use]
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = self
+            .registers
+            .iter()
+            .map(|&register| 2.0_f64.powi(-(register as i32)))
+            .sum::<f64>();
+        let estimate = alpha * m * m / sum;
+
+        // Small range correction: linear counting.
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|&&register| register == 0)
+            .count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// The lines and distinct stations of a file or a part of it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Cardinality {
+    /// The byte range within the file.
+    pub range: Range<usize>,
+    pub lines: u64,
+    /// The exact amount of distinct stations, if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distinct: Option<usize>,
+    /// The [`HyperLogLog`] estimate of the distinct stations.
+    pub estimate: f64,
+}
+
+/// The result of [`crate::count_distinct`]: the whole file and each shard (chunk).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CardinalityReport {
+    pub total: Cardinality,
+    pub shards: Vec<Cardinality>,
+    /// The standard error of the estimates, relative to the cardinality.
+    pub standard_error: f64,
+}
+
+/// The intermediate result of a chunk.
+#[derive(Debug)]
+pub(crate) struct ChunkCardinality<'a> {
+    lines: u64,
+    stations: Option<HashSet<&'a str>>,
+    hyper_log_log: HyperLogLog,
+}
+
+/// Counts the lines and distinct stations of a chunk. A chunk begins with the
+/// first byte of a line and ends with a newline.
+pub(crate) fn process_file_chunk(bytes: &[u8], exact: bool) -> ChunkCardinality<'_> {
+    assert!(!bytes.is_empty());
+    let &last_byte = bytes.last().unwrap();
+    assert_eq!(last_byte, b'\n');
+
+    let mut stations =
+        exact.then(|| HashSet::with_capacity_and_hasher(STATIONS_IN_DATASET, Default::default()));
+    let mut hyper_log_log = HyperLogLog::new(HYPERLOGLOG_PRECISION);
+    let mut lines = 0;
+
+    let mut consumed_bytes_count = 0;
+    while consumed_bytes_count < bytes.len() {
+        let remaining_bytes = unsafe { bytes.get_unchecked(consumed_bytes_count..) };
+        let (station, _) = unsafe { process_line(remaining_bytes, &mut consumed_bytes_count) };
+        lines += 1;
+        hyper_log_log.add(station);
+        if let Some(stations) = &mut stations {
+            stations.insert(station);
+        }
+    }
+
+    ChunkCardinality {
+        lines,
+        stations,
+        hyper_log_log,
+    }
+}
+
+/// Combines the results of the chunks, which are given with their byte
+/// ranges, into a report.
+pub(crate) fn report(chunks: Vec<(Range<usize>, ChunkCardinality)>) -> CardinalityReport {
+    let cardinality = |range: Range<usize>, chunk: &ChunkCardinality| Cardinality {
+        range,
+        lines: chunk.lines,
+        distinct: chunk.stations.as_ref().map(HashSet::len),
+        estimate: chunk.hyper_log_log.estimate(),
+    };
+    let shards = chunks
+        .iter()
+        .map(|(range, chunk)| cardinality(range.clone(), chunk))
+        .collect::<Vec<_>>();
+
+    let total = chunks
+        .into_iter()
+        .map(|(_, chunk)| chunk)
+        .reduce(|mut acc, next| {
+            acc.lines += next.lines;
+            acc.hyper_log_log.merge(&next.hyper_log_log);
+            if let (Some(stations), Some(next)) = (&mut acc.stations, next.stations) {
+                stations.extend(next);
+            }
+            acc
+        })
+        .unwrap();
+    let end = shards.last().map_or(0, |shard| shard.range.end);
+    let standard_error = total.hyper_log_log.standard_error();
+
+    CardinalityReport {
+        total: cardinality(0..end, &total),
+        shards,
+        standard_error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::SplitMix64;
+
+    #[test]
+    fn test_hyper_log_log() {
+        let mut rng = SplitMix64::new(1);
+        for cardinality in [10, 1000, 100_000] {
+            let mut hyper_log_log = HyperLogLog::new(HYPERLOGLOG_PRECISION);
+            (0..cardinality).for_each(|_| hyper_log_log.add_hash(rng.next_u64()));
+            let error = (hyper_log_log.estimate() - cardinality as f64).abs() / cardinality as f64;
+            // Three standard errors.
+            assert!(
+                error < 3.0 * hyper_log_log.standard_error(),
+                "{cardinality}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_is_union() {
+        let mut a = HyperLogLog::new(10);
+        let mut b = HyperLogLog::new(10);
+        ["Berlin", "Hamburg"]
+            .iter()
+            .for_each(|station| a.add(station));
+        ["Hamburg", "Oslo"]
+            .iter()
+            .for_each(|station| b.add(station));
+        a.merge(&b);
+        assert_eq!(a.estimate().round(), 3.0);
+    }
+
+    #[test]
+    fn test_report() {
+        let a = "Berlin;10.0\nHamburg;-12.7\nBerlin;-15.7\n";
+        let b = "Oslo;1.0\nHamburg;2.0\n";
+        let report = report(vec![
+            (0..a.len(), process_file_chunk(a.as_bytes(), true)),
+            (
+                a.len()..a.len() + b.len(),
+                process_file_chunk(b.as_bytes(), true),
+            ),
+        ]);
+
+        assert_eq!(report.shards[0].lines, 3);
+        assert_eq!(report.shards[0].distinct, Some(2));
+        assert_eq!(report.shards[1].distinct, Some(2));
+        assert_eq!(report.total.lines, 5);
+        assert_eq!(report.total.distinct, Some(3));
+        assert_eq!(report.total.range, 0..a.len() + b.len());
+        assert_eq!(report.total.estimate.round(), 3.0);
+
+        let report = super::report(vec![(0..a.len(), process_file_chunk(a.as_bytes(), false))]);
+        assert_eq!(report.total.distinct, None);
+    }
+}
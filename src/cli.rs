@@ -0,0 +1,1172 @@
+//! The unified `1brc` command line interface. The `1brc`, `single-threaded`,
+//! and `multi-threaded` binaries are thin wrappers around [`main_from`]; the
+//! latter two around the `run` subcommand.
+
+#[cfg(unix)]
+use crate::Daemon;
+#[cfg(feature = "arrow")]
+use crate::InputFormat;
+use crate::{
+    BucketWidth, Cardinality, CoordinatorOptions, MetadataTable, OutputFormat, OutputOptions,
+    Phase, QualityOptions, QualityReport, QuantileSketch, Quantiles, RankBy, Ranking, Results,
+    RollupKey, SamplingFraction, SamplingOptions, SortOrder, StationFilter, StdDevData,
+    TemperatureUnit, WorkerOptions,
+};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::num::NonZeroUsize;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command as Process, ExitCode, Stdio};
+use std::time::{Duration, Instant};
+
+/// Printed by a worker process after it has written its output. See
+/// [`run_in_worker_process`].
+const WORKER_DONE: &str = "\u{4}";
+
+/// Set in the environment of the worker process of `1brc run`. The worker
+/// gets the complete arguments of [`main_from`], so binaries that add
+/// arguments must pass them through unchanged if this is set.
+pub const WORKER_ENV: &str = "PHIPS_1BRC_WORKER";
+
+type CliResult<T = ()> = Result<T, Box<dyn Error>>;
+
+/// Tools around the 1 billion row challenge.
+#[derive(Debug, Parser)]
+#[command(name = "1brc", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Aggregates min/mean/max per station of a measurements file.
+    Run(RunArgs),
+    /// Generates a measurements file with random data.
+    Generate(GenerateArgs),
+    /// Compares the result of a run with an expected output.
+    Verify(VerifyArgs),
+    /// Runs the aggregation multiple times and reports the timings.
+    Bench(BenchArgs),
+    /// Prints information about a measurements file.
+    Inspect(InspectArgs),
+    /// Prints approximate quantiles per station (1% relative error).
+    Quantiles(QuantilesArgs),
+    /// Counts the lines and distinct stations, in total and per chunk.
+    Distinct(DistinctArgs),
+    /// Estimates the means per station from a random sample of the file.
+    Sample(SampleArgs),
+    /// Aggregates per station and time bucket
+    /// (`<station>;<measurement>;<timestamp>` per line).
+    Windowed(WindowedArgs),
+    /// Serves byte ranges of files to a coordinator over TCP.
+    Worker(WorkerArgs),
+    /// Distributes a file across workers and merges their results.
+    Coordinate(CoordinateArgs),
+    /// Aggregates measurement lines streamed to a Unix socket.
+    #[cfg(unix)]
+    Daemon(DaemonArgs),
+    /// Sends a command to the control socket of a daemon.
+    #[cfg(unix)]
+    Control(ControlArgs),
+}
+
+impl Command {
+    /// The processing arguments of the subcommand, if it has any.
+    const fn processing(&self) -> Option<&ProcessingArgs> {
+        match self {
+            Self::Run(args) => Some(&args.processing),
+            Self::Verify(args) => Some(&args.processing),
+            Self::Bench(args) => Some(&args.processing),
+            Self::Inspect(args) => Some(&args.processing),
+            Self::Quantiles(args) => Some(&args.processing),
+            Self::Distinct(args) => Some(&args.processing),
+            Self::Sample(args) => Some(&args.processing),
+            Self::Windowed(args) => Some(&args.processing),
+            _ => None,
+        }
+    }
+}
+
+/// Where `run` prints how long it took.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TimingOutput {
+    #[default]
+    Stderr,
+    /// After the results, like the original `single-threaded` and
+    /// `multi-threaded` binaries.
+    Stdout,
+}
+
+impl TimingOutput {
+    fn print(self, duration: Duration) {
+        match self {
+            Self::Stderr => eprintln!("took {duration:?}"),
+            Self::Stdout => println!("took {duration:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Strategy {
+    /// Process the whole file on the main thread.
+    Single,
+    /// Split the file into one chunk per thread.
+    Multi,
+}
+
+/// Which file to process, and how.
+#[derive(Debug, Args)]
+struct ProcessingArgs {
+    /// The measurements file (`<station>;<measurement>` per line).
+    #[arg(default_value = "./measurements.txt")]
+    file: PathBuf,
+    #[arg(long, value_enum, default_value_t = Strategy::Multi)]
+    strategy: Strategy,
+    /// The amount of threads of the multi strategy. Defaults to the available
+    /// parallelism.
+    #[arg(long, short = 't')]
+    threads: Option<NonZeroUsize>,
+    /// The format of the file: text, parquet, or arrow (IPC). Defaults to
+    /// the extension of the file.
+    #[cfg(feature = "arrow")]
+    #[arg(long, value_name = "FORMAT")]
+    input_format: Option<InputFormat>,
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+impl ProcessingArgs {
+    #[cfg(feature = "arrow")]
+    fn input_format(&self) -> InputFormat {
+        self.input_format
+            .unwrap_or_else(|| InputFormat::from_path(&self.file))
+    }
+
+    /// Whether the file is a text file rather than columnar input.
+    #[cfg(feature = "arrow")]
+    fn is_text(&self) -> bool {
+        self.input_format() == InputFormat::Text
+    }
+
+    /// Without the `arrow` feature, all input is text.
+    #[cfg(not(feature = "arrow"))]
+    const fn is_text(&self) -> bool {
+        true
+    }
+
+    /// Fails for columnar input, for features that only support text.
+    fn require_text(&self, feature: &str) -> CliResult {
+        if !self.is_text() {
+            return Err(format!("{feature} only supports text input").into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Args)]
+#[command(next_help_heading = "Filters")]
+struct FilterArgs {
+    /// Only aggregate the given station (repeatable).
+    #[arg(long, value_name = "STATION")]
+    include: Vec<String>,
+    /// Never aggregate the given station (repeatable).
+    #[arg(long, value_name = "STATION")]
+    exclude: Vec<String>,
+    /// Only aggregate stations starting with the prefix (repeatable).
+    #[arg(long)]
+    prefix: Vec<String>,
+    /// Only aggregate stations matching the regular expression.
+    #[arg(long)]
+    regex: Option<String>,
+    /// Discard measurements below the value.
+    #[arg(long, allow_negative_numbers = true)]
+    min_value: Option<f32>,
+    /// Discard measurements above the value.
+    #[arg(long, allow_negative_numbers = true)]
+    max_value: Option<f32>,
+}
+
+impl FilterArgs {
+    fn build(&self) -> CliResult<Option<StationFilter>> {
+        let active = !self.include.is_empty()
+            || !self.exclude.is_empty()
+            || !self.prefix.is_empty()
+            || self.regex.is_some()
+            || self.min_value.is_some()
+            || self.max_value.is_some();
+        if !active {
+            return Ok(None);
+        }
+
+        let mut filter = StationFilter::new().exclude(self.exclude.iter().cloned());
+        if !self.include.is_empty() {
+            filter = filter.include(self.include.iter().cloned());
+        }
+        for prefix in &self.prefix {
+            filter = filter.prefix(prefix.clone());
+        }
+        if let Some(regex) = &self.regex {
+            filter = filter.regex(regex)?;
+        }
+        if self.min_value.is_some() || self.max_value.is_some() {
+            let min = self.min_value.unwrap_or(-99.9);
+            let max = self.max_value.unwrap_or(99.9);
+            filter = filter.value_range(min..=max);
+        }
+        Ok(Some(filter))
+    }
+}
+
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Output")]
+struct OutputArgs {
+    #[arg(long, short = 'f', default_value = "text")]
+    format: OutputFormat,
+    /// Write the output into the file instead of stdout.
+    #[arg(long, short = 'o')]
+    output: Option<PathBuf>,
+    /// Write the stations as Parquet file instead (station, min, mean, max,
+    /// count, and the exact sum in tenths of a degree Celsius).
+    #[cfg(feature = "arrow")]
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["format", "output"])]
+    parquet: Option<PathBuf>,
+    /// c (Celsius), f (Fahrenheit), or k (Kelvin).
+    #[arg(long, default_value = "c")]
+    unit: TemperatureUnit,
+    /// Only print the top n stations of the ranking.
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+    /// Rank the stations by: mean, max, min, range, count, or stddev (adds
+    /// the standard deviation to the JSON output).
+    #[arg(long, value_name = "STATISTIC")]
+    by: Option<RankBy>,
+    /// Rank in ascending order.
+    #[arg(long)]
+    asc: bool,
+    /// Station metadata file for rollups
+    /// (`<station>;<latitude>;<longitude>;<country>[;<region>]`).
+    #[arg(long, requires = "rollup")]
+    metadata: Option<PathBuf>,
+    /// Merge the stations per country or region.
+    #[arg(long, requires = "metadata")]
+    rollup: Option<RollupKey>,
+}
+
+impl OutputArgs {
+    fn options(&self) -> OutputOptions {
+        let ranking = (self.by.is_some() || self.top.is_some()).then(|| Ranking {
+            by: self.by.unwrap_or(RankBy::Mean),
+            order: if self.asc {
+                SortOrder::Ascending
+            } else {
+                SortOrder::Descending
+            },
+            limit: self.top,
+        });
+        OutputOptions {
+            format: self.format,
+            unit: self.unit,
+            ranking,
+        }
+    }
+
+    /// Applies the rollup (if requested) and writes the results.
+    fn write(&self, results: Results) -> CliResult {
+        let results = match (&self.metadata, self.rollup) {
+            (Some(metadata), Some(key)) => MetadataTable::load(metadata)
+                .map_err(|err| format!("failed to load {}: {err}", metadata.display()))?
+                .rollup(&results, key),
+            _ => results,
+        };
+        let options = self.options();
+        #[cfg(feature = "arrow")]
+        if let Some(path) = &self.parquet {
+            results.write_parquet(File::create(path)?, &options)?;
+            return Ok(());
+        }
+        match &self.output {
+            None => results.write(&mut io::stdout().lock(), &options)?,
+            Some(path) => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                results.write(&mut writer, &options)?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args)]
+struct RunArgs {
+    #[command(flatten)]
+    processing: ProcessingArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+    /// Don't print how long the run took.
+    #[arg(long, short = 'q')]
+    quiet: bool,
+    /// Print a report with the duration of each phase and the work of each
+    /// thread to stderr (text or json). Implies --no-fork.
+    #[arg(long, value_name = "FORMAT")]
+    metrics: Option<OutputFormat>,
+    /// Write the metrics report into the file instead of stderr.
+    #[arg(long, value_name = "PATH", requires = "metrics")]
+    metrics_output: Option<PathBuf>,
+    /// Add hardware performance counters (IPC, misses per line) of each
+    /// thread to the metrics report. Requires perf_event_open(2).
+    #[arg(long, requires = "metrics")]
+    perf_counters: bool,
+    /// Validate each line, skip invalid ones, and print a data quality report
+    /// to stderr (text or json). Implies --no-fork.
+    #[arg(long, value_name = "FORMAT", conflicts_with_all = ["metrics", "progress"])]
+    quality: Option<OutputFormat>,
+    /// Abort the validation after more than N invalid lines.
+    #[arg(long, value_name = "N", requires = "quality")]
+    max_errors: Option<u64>,
+    /// Print the progress (processed bytes, throughput, ETA) to stderr while
+    /// processing. Implies --no-fork.
+    #[arg(long, conflicts_with = "metrics")]
+    progress: bool,
+    /// Don't delegate the multi strategy to a worker process, which unmaps
+    /// the file in the background after the output was written.
+    #[arg(long)]
+    no_fork: bool,
+    /// Internal: this is the worker process.
+    #[arg(long, hide = true)]
+    worker: bool,
+    #[arg(skip)]
+    timing: TimingOutput,
+    /// The arguments of [`main_from`] without the program, for the worker
+    /// process.
+    #[arg(skip)]
+    argv: Vec<OsString>,
+}
+
+#[derive(Debug, Args)]
+struct GenerateArgs {
+    /// The amount of rows to generate.
+    #[arg(default_value_t = 1_000_000_000)]
+    rows: u64,
+    #[arg(long, short = 'o', default_value = "./measurements.txt")]
+    output: PathBuf,
+    /// The seed of the random number generator.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Debug, Args)]
+struct VerifyArgs {
+    #[command(flatten)]
+    processing: ProcessingArgs,
+    /// The file with the expected output, in the format of the challenge.
+    #[arg(long, short = 'e')]
+    expected: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct BenchArgs {
+    #[command(flatten)]
+    processing: ProcessingArgs,
+    /// The amount of measured runs. One additional warm-up run is performed.
+    #[arg(long, short = 'n', default_value_t = 10)]
+    iterations: usize,
+}
+
+#[derive(Debug, Args)]
+struct InspectArgs {
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+#[derive(Debug, Args)]
+struct QuantilesArgs {
+    #[command(flatten)]
+    processing: ProcessingArgs,
+    /// Comma-separated quantiles within 0..=1.
+    #[arg(long, short = 'q', default_value = "0.5,0.9,0.99")]
+    quantiles: Quantiles,
+    #[arg(long, short = 'f', default_value = "text")]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct DistinctArgs {
+    #[command(flatten)]
+    processing: ProcessingArgs,
+    /// Only estimate the distinct stations, which requires constant memory.
+    #[arg(long)]
+    no_exact: bool,
+    #[arg(long, short = 'f', default_value = "text")]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct SampleArgs {
+    #[command(flatten)]
+    processing: ProcessingArgs,
+    /// The share of the bytes to process, as fraction (`0.01`) or
+    /// percentage (`1%`).
+    #[arg(long, short = 'p', default_value = "1%")]
+    fraction: SamplingFraction,
+    /// The seed of the random selection. The same seed samples the same
+    /// parts of the same file.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// The size of the sampled blocks in KiB.
+    #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u64).range(1..))]
+    block_size: u64,
+    #[arg(long, short = 'f', default_value = "text")]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+struct WindowedArgs {
+    #[command(flatten)]
+    processing: ProcessingArgs,
+    /// The width of the buckets: hour, day, month, or seconds (`900s`).
+    #[arg(long, short = 'b', default_value = "day")]
+    bucket: BucketWidth,
+    #[arg(long, short = 'f', default_value = "text")]
+    format: OutputFormat,
+    /// c (Celsius), f (Fahrenheit), or k (Kelvin).
+    #[arg(long, default_value = "c")]
+    unit: TemperatureUnit,
+}
+
+#[derive(Debug, Args)]
+struct WorkerArgs {
+    /// The address to listen on.
+    #[arg(long, short = 'l', default_value = "127.0.0.1:7878")]
+    listen: SocketAddr,
+    /// The amount of threads per range. Defaults to the available
+    /// parallelism.
+    #[arg(long, short = 't')]
+    threads: Option<NonZeroUsize>,
+    /// Only files within this directory are processed.
+    #[arg(long, default_value = ".")]
+    root: PathBuf,
+    /// Seconds to wait for the next task of a coordinator before the
+    /// connection is closed.
+    #[arg(long, value_name = "SECONDS", default_value_t = 600)]
+    timeout: u64,
+}
+
+#[derive(Debug, Args)]
+struct CoordinateArgs {
+    /// The measurements file. It must be reachable under the same path by all
+    /// workers.
+    #[arg(default_value = "./measurements.txt")]
+    file: PathBuf,
+    /// The address of a worker (repeatable).
+    #[arg(long, short = 'w', value_name = "ADDRESS", required = true)]
+    worker: Vec<SocketAddr>,
+    /// The amount of ranges the file is split into.
+    #[arg(long, default_value_t = NonZeroUsize::new(64).unwrap())]
+    tasks: NonZeroUsize,
+    /// Seconds to wait for a worker before its range is reassigned.
+    #[arg(long, value_name = "SECONDS", default_value_t = 600)]
+    timeout: u64,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[cfg(unix)]
+#[derive(Debug, Args)]
+struct DaemonArgs {
+    /// The socket producers write `<station>;<measurement>` lines to.
+    #[arg(long, default_value = "/tmp/1brc.sock")]
+    socket: PathBuf,
+    /// The socket that answers `snapshot`, `print`, and `stats` commands.
+    #[arg(long, default_value = "/tmp/1brc-control.sock")]
+    control: PathBuf,
+    /// The amount of shards of the shared state.
+    #[arg(long, default_value_t = NonZeroUsize::new(16).unwrap())]
+    shards: NonZeroUsize,
+}
+
+#[cfg(unix)]
+#[derive(Debug, Args)]
+struct ControlArgs {
+    /// snapshot, print, or stats.
+    command: String,
+    /// The control socket of the daemon.
+    #[arg(long, default_value = "/tmp/1brc-control.sock")]
+    control: PathBuf,
+}
+
+/// Parses the arguments, including the checks that clap can't express
+/// declaratively.
+fn parse(args: impl IntoIterator<Item = impl Into<OsString> + Clone>) -> Result<Cli, clap::Error> {
+    let cli = Cli::try_parse_from(args)?;
+    if let Some(processing) = cli.command.processing() {
+        // `conflicts_with` can't depend on the value of `--strategy`.
+        if processing.strategy == Strategy::Single && processing.threads.is_some() {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--threads <THREADS>' cannot be used with '--strategy single'",
+            ));
+        }
+    }
+    Ok(cli)
+}
+
+/// Parses the arguments and runs the command. Exits the process on invalid
+/// arguments.
+pub fn main_from(
+    args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+    timing: TimingOutput,
+) -> ExitCode {
+    let argv = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+    let cli = parse(&argv).unwrap_or_else(|err| err.exit());
+    let result = match cli.command {
+        Command::Run(mut args) => {
+            args.timing = timing;
+            args.argv = argv.into_iter().skip(1).collect();
+            run(&args)
+        }
+        Command::Generate(args) => generate(&args),
+        Command::Verify(args) => verify(&args),
+        Command::Bench(args) => bench(&args),
+        Command::Inspect(args) => inspect(&args),
+        Command::Quantiles(args) => quantiles(&args),
+        Command::Distinct(args) => distinct(&args),
+        Command::Sample(args) => sample(&args),
+        Command::Windowed(args) => windowed(&args),
+        Command::Worker(args) => worker(&args),
+        Command::Coordinate(args) => coordinate(&args),
+        #[cfg(unix)]
+        Command::Daemon(args) => daemon(&args),
+        #[cfg(unix)]
+        Command::Control(args) => control(&args),
+    };
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        ExitCode::FAILURE
+    })
+}
+
+fn aggregate(args: &ProcessingArgs) -> CliResult<Results> {
+    // The library panics on I/O errors, which is not user-friendly.
+    File::open(&args.file).map_err(|err| format!("{}: {err}", args.file.display()))?;
+    let filter = args.filter.build()?;
+    #[cfg(feature = "arrow")]
+    if !args.is_text() {
+        if filter.is_some() {
+            return Err("filters only support text input".into());
+        }
+        let results = match args.input_format() {
+            InputFormat::Parquet => crate::aggregate_parquet(&args.file, threads(args)),
+            _ => crate::aggregate_arrow_ipc(&args.file, threads(args)),
+        };
+        return Ok(results.map_err(|err| format!("{}: {err}", args.file.display()))?);
+    }
+    let results = match (args.strategy, args.threads) {
+        (Strategy::Single, _) => crate::aggregate_single_threaded(&args.file, filter.as_ref()),
+        (Strategy::Multi, None) => crate::aggregate_multi_threaded(&args.file, filter.as_ref()),
+        (Strategy::Multi, Some(threads)) => {
+            crate::aggregate_with_threads(&args.file, threads, filter.as_ref())
+        }
+    };
+    Ok(results)
+}
+
+fn run(args: &RunArgs) -> CliResult<ExitCode> {
+    if args.output.by == Some(RankBy::StdDev) {
+        return run_with_stddev(args);
+    }
+    if let Some(format) = args.metrics {
+        return run_instrumented(args, format);
+    }
+    if args.progress {
+        return run_with_progress(args);
+    }
+    if let Some(format) = args.quality {
+        return run_validated(args, format);
+    }
+
+    let begin = Instant::now();
+    // Columnar input isn't memory-mapped, so there is nothing to unmap in the
+    // background.
+    let fork = args.processing.is_text()
+        && args.processing.strategy == Strategy::Multi
+        && !args.no_fork
+        && !args.worker;
+    if fork {
+        run_in_worker_process(&args.argv)?;
+    } else {
+        args.output.write(aggregate(&args.processing)?)?;
+    }
+    if args.worker {
+        println!("{WORKER_DONE}");
+    } else if !args.quiet {
+        args.timing.print(begin.elapsed());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs in-process with [`crate::aggregate_instrumented`] and writes the
+/// metrics report.
+fn run_instrumented(args: &RunArgs, format: OutputFormat) -> CliResult<ExitCode> {
+    let processing = &args.processing;
+    processing.require_text("--metrics")?;
+    File::open(&processing.file).map_err(|err| format!("{}: {err}", processing.file.display()))?;
+    let filter = processing.filter.build()?;
+    if args.perf_counters {
+        crate::perf_counters_available().map_err(|err| {
+            format!(
+                "performance counters unavailable: {err} \
+                 (see /proc/sys/kernel/perf_event_paranoid)"
+            )
+        })?;
+    }
+    let (results, mut metrics) = crate::aggregate_instrumented(
+        &processing.file,
+        threads(processing),
+        filter.as_ref(),
+        args.perf_counters,
+    );
+
+    let begin = Instant::now();
+    args.output.write(results)?;
+    metrics.record(Phase::Print, begin.elapsed());
+
+    match &args.metrics_output {
+        None => metrics.write(&mut io::stderr().lock(), format)?,
+        Some(path) => metrics.write(&mut File::create(path)?, format)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs in-process with [`crate::aggregate_with_progress`] and updates
+/// the progress line on stderr.
+fn run_with_progress(args: &RunArgs) -> CliResult<ExitCode> {
+    let processing = &args.processing;
+    processing.require_text("--progress")?;
+    File::open(&processing.file).map_err(|err| format!("{}: {err}", processing.file.display()))?;
+    let filter = processing.filter.build()?;
+    let begin = Instant::now();
+    let results = crate::aggregate_with_progress(
+        &processing.file,
+        threads(processing),
+        filter.as_ref(),
+        Duration::from_millis(200),
+        |progress| {
+            // Pad, as the line may become shorter.
+            eprint!("\r{:<60}", progress.to_string());
+        },
+    );
+    eprintln!();
+    args.output.write(results)?;
+    if !args.quiet {
+        args.timing.print(begin.elapsed());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs in-process with [`crate::aggregate_validated`] and writes the
+/// quality report to stderr.
+fn run_validated(args: &RunArgs, format: OutputFormat) -> CliResult<ExitCode> {
+    let processing = &args.processing;
+    processing.require_text("--quality")?;
+    File::open(&processing.file).map_err(|err| format!("{}: {err}", processing.file.display()))?;
+    let filter = processing.filter.build()?;
+    let options = QualityOptions {
+        max_errors: args.max_errors,
+        ..Default::default()
+    };
+    let begin = Instant::now();
+    let result = crate::aggregate_validated(
+        &processing.file,
+        threads(processing),
+        filter.as_ref(),
+        &options,
+    );
+
+    let write_report = |report: &QualityReport| -> CliResult {
+        let mut stderr = io::stderr().lock();
+        match format {
+            OutputFormat::Text => write!(stderr, "{report}")?,
+            OutputFormat::Json => {
+                serde_json::to_writer(&mut stderr, report)?;
+                writeln!(stderr)?;
+            }
+        }
+        Ok(())
+    };
+    match result {
+        Ok(results) => {
+            write_report(results.quality().unwrap())?;
+            args.output.write(results)?;
+        }
+        Err(err) => {
+            write_report(&err.report)?;
+            return Err(err.into());
+        }
+    }
+    if !args.quiet {
+        args.timing.print(begin.elapsed());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Ranks by the standard deviation, which needs [`StdDevData`] instead of the
+/// regular aggregation.
+fn run_with_stddev(args: &RunArgs) -> CliResult<ExitCode> {
+    let processing = &args.processing;
+    let output = &args.output;
+    processing.require_text("--by stddev")?;
+    File::open(&processing.file).map_err(|err| format!("{}: {err}", processing.file.display()))?;
+    if processing.filter.build()?.is_some() {
+        return Err("filters are not supported with --by stddev".into());
+    }
+    if args.metrics.is_some() || args.progress || args.quality.is_some() || output.rollup.is_some()
+    {
+        return Err(
+            "--by stddev doesn't support --metrics, --progress, --quality, or --rollup".into(),
+        );
+    }
+    #[cfg(feature = "arrow")]
+    if output.parquet.is_some() {
+        return Err("--by stddev doesn't support --parquet".into());
+    }
+
+    let begin = Instant::now();
+    let stations = crate::aggregate_custom::<StdDevData>(&processing.file, threads(processing));
+    let options = output.options();
+    match &output.output {
+        None => crate::write_stations(&mut io::stdout().lock(), &stations, &options)?,
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            crate::write_stations(&mut writer, &stations, &options)?;
+            writer.flush()?;
+        }
+    }
+    if !args.quiet {
+        args.timing.print(begin.elapsed());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The amount of threads of the strategy.
+fn threads(args: &ProcessingArgs) -> NonZeroUsize {
+    match args.strategy {
+        Strategy::Single => NonZeroUsize::MIN,
+        Strategy::Multi => args
+            .threads
+            .unwrap_or_else(|| std::thread::available_parallelism().unwrap()),
+    }
+}
+
+/// Unmapping the whole file is expensive (roughly 200ms on my machine). As
+/// unmapping the file from the address space is part of the normal Linux
+/// destruction process, we can't just use `drop(mmaped_file)` and are good
+/// to go. A workaround to prevent the big overhead of unmapping is to use a
+/// child process and do the unmapping there. The main process exits as soon
+/// as the child performed its work.
+///
+/// The child is started with the given arguments plus `--worker` and
+/// [`WORKER_ENV`]. After it wrote its output, it prints [`WORKER_DONE`].
+fn run_in_worker_process(args: &[OsString]) -> CliResult {
+    // Child has no drop implementation, and we don't manually wait for it.
+    // We are not blocked on in.
+    #[allow(clippy::zombie_processes)]
+    let mut child = Process::new(std::env::current_exe()?)
+        .args(args)
+        .arg("--worker")
+        .env(WORKER_ENV, "1")
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let child_stdout = child.stdout.take().unwrap();
+    let mut stdout_reader = BufReader::new(child_stdout);
+    let mut stdout = io::stdout().lock();
+    let mut line = String::new();
+    // Synchronization point.
+    //
+    // We don't read until EOF but until the child signals that it is done.
+    // Then, we don't care about the child, which performs the very expensive
+    // mmap cleanup in background.
+    loop {
+        line.clear();
+        if stdout_reader.read_line(&mut line)? == 0 {
+            // The child failed; it already reported the error on stderr.
+            let status = child.wait()?;
+            return Err(format!("worker process failed: {status}").into());
+        }
+        if line.trim_end() == WORKER_DONE {
+            return Ok(());
+        }
+        stdout.write_all(line.as_bytes())?;
+    }
+}
+
+fn generate(args: &GenerateArgs) -> CliResult<ExitCode> {
+    let mut writer = BufWriter::with_capacity(1 << 20, File::create(&args.output)?);
+    crate::generate(&mut writer, args.rows, args.seed)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Parses the output format of the challenge into `(station, min/mean/max)`.
+fn parse_expected(output: &str) -> Vec<(&str, &str)> {
+    let regex =
+        Regex::new(r"(?:^\{|, )([^=]+)=(-?[0-9]+\.[0-9]/-?[0-9]+\.[0-9]/-?[0-9]+\.[0-9])").unwrap();
+    regex
+        .captures_iter(output.trim())
+        .map(|captures| captures.extract::<2>().1.into())
+        .collect()
+}
+
+fn verify(args: &VerifyArgs) -> CliResult<ExitCode> {
+    let expected = fs::read_to_string(&args.expected)
+        .map_err(|err| format!("{}: {err}", args.expected.display()))?;
+    let mut actual = Vec::new();
+    aggregate(&args.processing)?.write(&mut actual, &OutputOptions::default())?;
+    let actual = String::from_utf8(actual)?;
+
+    if actual.trim() == expected.trim() {
+        println!("OK");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let expected = parse_expected(&expected);
+    let actual = parse_expected(&actual);
+    for (station, values) in &expected {
+        match actual.iter().find(|(name, _)| name == station) {
+            None => println!("missing: {station}"),
+            Some((_, actual)) if actual != values => {
+                println!("different: {station}: expected {values}, got {actual}")
+            }
+            Some(_) => {}
+        }
+    }
+    for (station, _) in &actual {
+        if !expected.iter().any(|(name, _)| name == station) {
+            println!("unexpected: {station}");
+        }
+    }
+    println!("FAILED");
+    Ok(ExitCode::FAILURE)
+}
+
+fn bench(args: &BenchArgs) -> CliResult<ExitCode> {
+    let size = fs::metadata(&args.processing.file)?.len();
+    // Warm-up: fill the page cache.
+    let _ = aggregate(&args.processing)?;
+
+    let mut timings = (0..args.iterations.max(1))
+        .map(|_| {
+            let begin = Instant::now();
+            aggregate(&args.processing).map(|_| begin.elapsed())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    timings.sort_unstable();
+
+    let mean = timings.iter().sum::<Duration>() / timings.len() as u32;
+    let min = timings[0];
+    println!("runs:   {}", timings.len());
+    println!("min:    {min:?}");
+    println!("median: {:?}", timings[timings.len() / 2]);
+    println!("mean:   {mean:?}");
+    println!("max:    {:?}", timings[timings.len() - 1]);
+    println!(
+        "throughput (best run): {:.2} GB/s",
+        size as f64 / min.as_secs_f64() / 1e9
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+fn inspect(args: &InspectArgs) -> CliResult<ExitCode> {
+    let size = fs::metadata(&args.processing.file)?.len();
+    let results = aggregate(&args.processing)?;
+    let stations = results.stations();
+    let lines = stations
+        .iter()
+        .map(|(_, data)| data.count() as u64)
+        .sum::<u64>();
+
+    println!("file:     {}", args.processing.file.display());
+    println!("size:     {size} bytes");
+    println!("lines:    {lines}");
+    println!("stations: {}", stations.len());
+    if let (Some(min), Some(max)) = (
+        stations.iter().map(|(station, _)| station.len()).min(),
+        stations.iter().map(|(station, _)| station.len()).max(),
+    ) {
+        println!("station name length: {min}..={max} bytes");
+    }
+    if let (Some(min), Some(max)) = (
+        stations.iter().map(|(_, data)| data.encoded_min()).min(),
+        stations.iter().map(|(_, data)| data.encoded_max()).max(),
+    ) {
+        println!(
+            "measurements: {:.1}..={:.1}",
+            min as f32 / 10.0,
+            max as f32 / 10.0
+        );
+    }
+
+    if size > 0 && args.processing.is_text() {
+        print_chunks(&args.processing.file, threads(&args.processing));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Checks that the file is a text file that can be opened and that no
+/// filters are given, for commands that don't support them.
+fn check_unfiltered(processing: &ProcessingArgs, command: &str) -> CliResult {
+    processing.require_text(&format!("the {command} command"))?;
+    File::open(&processing.file).map_err(|err| format!("{}: {err}", processing.file.display()))?;
+    if processing.filter.build()?.is_some() {
+        return Err(format!("filters are not supported by the {command} command").into());
+    }
+    Ok(())
+}
+
+fn quantiles(args: &QuantilesArgs) -> CliResult<ExitCode> {
+    let processing = &args.processing;
+    check_unfiltered(processing, "quantiles")?;
+    let sketches = crate::aggregate_custom::<QuantileSketch>(&processing.file, threads(processing));
+
+    let quantiles = &args.quantiles.0;
+    let values = |sketch: &QuantileSketch| {
+        quantiles
+            .iter()
+            .map(|&q| sketch.quantile(q).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let mut stdout = io::stdout().lock();
+    match args.format {
+        OutputFormat::Text => {
+            let stations = sketches
+                .iter()
+                .map(|(station, sketch)| {
+                    let values = values(sketch)
+                        .iter()
+                        .map(|value| format!("{value:.1}"))
+                        .collect::<Vec<_>>();
+                    format!("{station}={}", values.join("/"))
+                })
+                .collect::<Vec<_>>();
+            writeln!(stdout, "{{{}}}", stations.join(", "))?;
+        }
+        OutputFormat::Json => {
+            let stations = sketches
+                .iter()
+                .map(|(station, sketch)| {
+                    let values = quantiles
+                        .iter()
+                        .zip(values(sketch))
+                        .map(|(q, value)| (q.to_string(), value.into()))
+                        .collect::<serde_json::Map<_, _>>();
+                    serde_json::json!({
+                        "station": station,
+                        "count": sketch.count(),
+                        "quantiles": values,
+                    })
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_writer(&mut stdout, &stations)?;
+            writeln!(stdout)?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn distinct(args: &DistinctArgs) -> CliResult<ExitCode> {
+    let processing = &args.processing;
+    check_unfiltered(processing, "distinct")?;
+    let report = crate::count_distinct(&processing.file, threads(processing), !args.no_exact);
+
+    let mut stdout = io::stdout().lock();
+    if args.format == OutputFormat::Json {
+        serde_json::to_writer(&mut stdout, &report)?;
+        writeln!(stdout)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let describe = |cardinality: &Cardinality| {
+        let exact = cardinality
+            .distinct
+            .map(|distinct| format!("{distinct} distinct, "))
+            .unwrap_or_default();
+        format!(
+            "{} lines, {exact}~{:.0} estimated",
+            cardinality.lines, cardinality.estimate
+        )
+    };
+    writeln!(stdout, "total: {}", describe(&report.total))?;
+    writeln!(
+        stdout,
+        "(HyperLogLog standard error: {:.1}%)",
+        report.standard_error * 100.0
+    )?;
+    writeln!(stdout, "shards:")?;
+    for (index, shard) in report.shards.iter().enumerate() {
+        writeln!(
+            stdout,
+            "  #{index}: {}..{}: {}",
+            shard.range.start,
+            shard.range.end,
+            describe(shard)
+        )?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn sample(args: &SampleArgs) -> CliResult<ExitCode> {
+    let processing = &args.processing;
+    check_unfiltered(processing, "sample")?;
+    let options = SamplingOptions {
+        fraction: args.fraction,
+        seed: args.seed,
+        block_size: usize::try_from(args.block_size * 1024)?,
+    };
+    let report = crate::aggregate_sampled(&processing.file, threads(processing), &options);
+
+    let mut stdout = io::stdout().lock();
+    match args.format {
+        OutputFormat::Text => writeln!(stdout, "{report}")?,
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut stdout, &report)?;
+            writeln!(stdout)?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn windowed(args: &WindowedArgs) -> CliResult<ExitCode> {
+    let processing = &args.processing;
+    check_unfiltered(processing, "windowed")?;
+    let results = crate::aggregate_windowed(&processing.file, threads(processing), args.bucket);
+    results.write(&mut io::stdout().lock(), args.format, args.unit)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn worker(args: &WorkerArgs) -> CliResult<ExitCode> {
+    if !args.root.is_dir() {
+        return Err(format!("{}: not a directory", args.root.display()).into());
+    }
+    let listener = TcpListener::bind(args.listen)
+        .map_err(|err| format!("failed to listen on {}: {err}", args.listen))?;
+    let threads = args
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().unwrap());
+    let options = WorkerOptions {
+        root: args.root.clone(),
+        threads,
+        timeout: Duration::from_secs(args.timeout),
+    };
+    eprintln!("worker listening on {}", listener.local_addr()?);
+    crate::serve_worker(&listener, &options)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn coordinate(args: &CoordinateArgs) -> CliResult<ExitCode> {
+    File::open(&args.file).map_err(|err| format!("{}: {err}", args.file.display()))?;
+    let options = CoordinatorOptions {
+        tasks: args.tasks,
+        timeout: Duration::from_secs(args.timeout),
+        ..CoordinatorOptions::default()
+    };
+    let begin = Instant::now();
+    let results = crate::coordinate(&args.file, &args.worker, &options)?;
+    let duration = begin.elapsed();
+    args.output.write(results)?;
+    eprintln!("took {duration:?}");
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(unix)]
+fn daemon(args: &DaemonArgs) -> CliResult<ExitCode> {
+    use std::os::unix::net::UnixListener;
+
+    let bind = |path: &Path| -> CliResult<UnixListener> {
+        // A socket file of a previous run can't be bound again.
+        if path.exists() && UnixStream::connect(path).is_err() {
+            fs::remove_file(path)?;
+        }
+        Ok(UnixListener::bind(path).map_err(|err| format!("{}: {err}", path.display()))?)
+    };
+    let ingest = bind(&args.socket)?;
+    let control = bind(&args.control)?;
+    let daemon = Daemon::new(args.shards);
+    eprintln!(
+        "ingesting on {}, control on {}",
+        args.socket.display(),
+        args.control.display()
+    );
+    std::thread::scope(|scope| {
+        let control = scope.spawn(|| daemon.serve_control(&control));
+        daemon.serve_ingest(&ingest)?;
+        control.join().unwrap()
+    })?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(unix)]
+fn control(args: &ControlArgs) -> CliResult<ExitCode> {
+    let socket = UnixStream::connect(&args.control)
+        .map_err(|err| format!("{}: {err}", args.control.display()))?;
+    let answer = crate::control_request(&socket, &args.command)?;
+    print!("{answer}");
+    Ok(if answer.starts_with("error:") {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn print_chunks(file: &Path, threads: NonZeroUsize) {
+    println!("chunks ({threads} threads):");
+    for (index, range) in crate::chunk_boundaries(file, threads)
+        .into_iter()
+        .enumerate()
+    {
+        println!(
+            "  #{index}: {}..{} ({} bytes)",
+            range.start,
+            range.end,
+            range.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_single_strategy_without_threads() {
+        let err = parse(["1brc", "run", "--strategy", "single", "-t", "4"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        assert!(parse(["1brc", "sample", "--strategy", "single", "--threads", "2"]).is_err());
+        assert!(parse(["1brc", "run", "--strategy", "single"]).is_ok());
+        assert!(parse(["1brc", "run", "--threads", "4"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_expected() {
+        let parsed = parse_expected("{Abha=-1.0/2.5/3.0, Washington, D.C.=1.0/2.0/3.0}\n");
+        assert_eq!(
+            parsed,
+            [
+                ("Abha", "-1.0/2.5/3.0"),
+                ("Washington, D.C.", "1.0/2.0/3.0")
+            ]
+        );
+    }
+}
@@ -0,0 +1,481 @@
+//! Aggregation of columnar input: Parquet and Arrow IPC files with a `station`
+//! column (UTF-8, optionally dictionary-encoded) and a `temperature` column
+//! (`Float32` or `Float64`). Other columns are ignored.
+//!
+//! The row groups (Parquet) or record batches (Arrow IPC) are split across the
+//! threads like the chunks of a text file, and the per-thread results are
+//! merged like in [`crate::reduce`].
+
+use crate::hash::HashMap;
+use crate::results::Results;
+use crate::AggregatedData;
+use crate::{process_chunks, reduce};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, Float64Type};
+use arrow_array::{Array, ArrowPrimitiveType, PrimitiveArray, RecordBatch, StringArray};
+use arrow_ipc::reader::FileReader;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::arrow_reader::{
+    ArrowReaderMetadata, ArrowReaderOptions, ParquetRecordBatchReaderBuilder,
+};
+use parquet::arrow::ProjectionMask;
+use phips_1brc_core::data_set_properties::STATIONS_IN_DATASET;
+use phips_1brc_core::MAX_ABS_MEASUREMENT;
+use std::fs::File;
+use std::io;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The name of the column with the station names.
+pub const STATION_COLUMN: &str = "station";
+/// The name of the column with the measurements, in degrees Celsius.
+pub const TEMPERATURE_COLUMN: &str = "temperature";
+
+/// The format of an input file.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// `<station>;<measurement>` lines, as in the challenge.
+    #[default]
+    Text,
+    /// A Parquet file.
+    Parquet,
+    /// An Arrow IPC file (also known as Feather V2).
+    ArrowIpc,
+}
+
+impl InputFormat {
+    /// Guesses the format from the extension of the path: `.parquet`, or
+    /// `.arrow`, `.feather`, and `.ipc`. Everything else is text.
+    #[must_use]
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str());
+        match extension {
+            Some("parquet") => Self::Parquet,
+            Some("arrow" | "feather" | "ipc") => Self::ArrowIpc,
+            _ => Self::Text,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "parquet" => Ok(Self::Parquet),
+            "arrow" => Ok(Self::ArrowIpc),
+            _ => Err(format!(
+                "unknown input format `{s}`, expected one of: text, parquet, arrow"
+            )),
+        }
+    }
+}
+
+/// The per-thread result. In contrast to the text input, the station names
+/// can't borrow from a memory-mapped file.
+type Stats = HashMap<String, AggregatedData>;
+
+/// Aggregates the Parquet file, with the row groups split across the given
+/// amount of threads.
+///
+/// The station column is read dictionary-encoded, so each batch only looks up
+/// its distinct stations in the hash map. Rows with a null station or
+/// temperature are skipped.
+pub fn aggregate_parquet(path: impl AsRef<Path>, threads: NonZeroUsize) -> io::Result<Results> {
+    let file = File::open(path)?;
+    let metadata = ArrowReaderMetadata::load(&file, ArrowReaderOptions::new()).map_err(invalid)?;
+    let schema = dictionary_encoded(metadata.schema())?;
+    let metadata = ArrowReaderMetadata::try_new(
+        metadata.metadata().clone(),
+        ArrowReaderOptions::new().with_schema(schema),
+    )
+    .map_err(invalid)?;
+    let projection = ProjectionMask::columns(
+        metadata.parquet_schema(),
+        [STATION_COLUMN, TEMPERATURE_COLUMN],
+    );
+
+    let row_groups = metadata.metadata().num_row_groups();
+    aggregate_parts(row_groups, threads, |row_groups| {
+        let reader =
+            ParquetRecordBatchReaderBuilder::new_with_metadata(file.try_clone()?, metadata.clone())
+                .with_row_groups(row_groups.collect())
+                .with_projection(projection.clone())
+                .build()
+                .map_err(invalid)?;
+
+        let mut stats = Stats::with_capacity_and_hasher(STATIONS_IN_DATASET, Default::default());
+        for batch in reader {
+            aggregate_batch(&batch.map_err(invalid)?, &mut stats)?;
+        }
+        Ok(stats)
+    })
+}
+
+/// Aggregates the Arrow IPC file, with the record batches split across the
+/// given amount of threads. Rows with a null station or temperature are
+/// skipped.
+pub fn aggregate_arrow_ipc(path: impl AsRef<Path>, threads: NonZeroUsize) -> io::Result<Results> {
+    let path = path.as_ref();
+    let reader = FileReader::try_new(File::open(path)?, None).map_err(invalid)?;
+    let schema = reader.schema();
+    let projection = vec![
+        column_index(&schema, STATION_COLUMN)?,
+        column_index(&schema, TEMPERATURE_COLUMN)?,
+    ];
+
+    aggregate_parts(reader.num_batches(), threads, |batches| {
+        let file = File::open(path)?;
+        let mut reader = FileReader::try_new(file, Some(projection.clone())).map_err(invalid)?;
+        reader.set_index(batches.start).map_err(invalid)?;
+
+        let mut stats = Stats::with_capacity_and_hasher(STATIONS_IN_DATASET, Default::default());
+        for batch in reader.take(batches.len()) {
+            aggregate_batch(&batch.map_err(invalid)?, &mut stats)?;
+        }
+        Ok(stats)
+    })
+}
+
+/// Splits the parts (row groups or record batches) into contiguous ranges, one
+/// per thread, processes them, and merges the results.
+fn aggregate_parts(
+    parts: usize,
+    threads: NonZeroUsize,
+    process: impl Fn(Range<usize>) -> io::Result<Stats> + Sync,
+) -> io::Result<Results> {
+    if parts == 0 {
+        return Ok(Results::default());
+    }
+    let per_thread = parts.div_ceil(threads.get());
+    let ranges = (0..parts)
+        .step_by(per_thread)
+        .map(|start| start..(start + per_thread).min(parts));
+
+    let thread_results = process_chunks(ranges, process)
+        .into_iter()
+        .collect::<io::Result<Vec<_>>>()?;
+    // The merge only clones one entry per station and thread.
+    let stats = reduce(thread_results.iter().map(|stats| {
+        stats
+            .iter()
+            .map(|(station, data)| (station.as_str(), data.clone()))
+            .collect()
+    }));
+    Ok(Results::from_sorted(stats))
+}
+
+/// Aggregates the rows of the batch into the stats.
+fn aggregate_batch(batch: &RecordBatch, stats: &mut Stats) -> io::Result<()> {
+    let schema = batch.schema();
+    let stations = batch.column(column_index(&schema, STATION_COLUMN)?);
+    let temperatures = batch.column(column_index(&schema, TEMPERATURE_COLUMN)?);
+    if let Some(temperatures) = temperatures.as_primitive_opt::<Float64Type>() {
+        aggregate_columns(stations.as_ref(), temperatures, stats)
+    } else if let Some(temperatures) = temperatures.as_primitive_opt::<Float32Type>() {
+        aggregate_columns(stations.as_ref(), temperatures, stats)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "column `{TEMPERATURE_COLUMN}` has type {}, expected Float32 or Float64",
+                temperatures.data_type()
+            ),
+        ))
+    }
+}
+
+fn aggregate_columns<T: ArrowPrimitiveType>(
+    stations: &dyn Array,
+    temperatures: &PrimitiveArray<T>,
+    stats: &mut Stats,
+) -> io::Result<()>
+where
+    T::Native: Into<f64>,
+{
+    let measurement = |row: usize| {
+        if temperatures.is_valid(row) {
+            encode(temperatures.value(row).into())
+        } else {
+            Ok(None)
+        }
+    };
+
+    if let Some(dictionary) = stations.as_any_dictionary_opt() {
+        // Aggregate per dictionary key first, and only then look up the
+        // (few) distinct stations of the batch in the hash map.
+        let names = utf8(dictionary.values().as_ref())?;
+        let keys = dictionary.normalized_keys();
+        let mut per_key = vec![AggregatedData::default(); names.len()];
+        for (row, &key) in keys.iter().enumerate() {
+            if let (true, Some(measurement)) = (dictionary.is_valid(row), measurement(row)?) {
+                per_key[key].add_datapoint(measurement);
+            }
+        }
+        for (key, data) in per_key.iter().enumerate() {
+            if data.count() > 0 && names.is_valid(key) {
+                add(stats, names.value(key), data);
+            }
+        }
+    } else {
+        let names = utf8(stations)?;
+        for row in 0..names.len() {
+            if let (true, Some(measurement)) = (names.is_valid(row), measurement(row)?) {
+                let mut data = AggregatedData::default();
+                data.add_datapoint(measurement);
+                add(stats, names.value(row), &data);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Merges the data into the entry of the station, without allocating the
+/// name if the station is already known.
+fn add(stats: &mut Stats, station: &str, data: &AggregatedData) {
+    match stats.get_mut(station) {
+        Some(entry) => entry.merge(data),
+        None => {
+            stats.insert(station.to_string(), data.clone());
+        }
+    }
+}
+
+/// Encodes the measurement as integer multiplied by 10, like the text parser.
+/// NaN and infinite values are skipped like nulls, and finite values outside
+/// of `-99.9..=99.9` are an error.
+fn encode(measurement: f64) -> io::Result<Option<i16>> {
+    if !measurement.is_finite() {
+        return Ok(None);
+    }
+    let encoded = (measurement * 10.0).round();
+    if encoded.abs() > f64::from(MAX_ABS_MEASUREMENT) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "column `{TEMPERATURE_COLUMN}` has the value {measurement}, expected -99.9..=99.9"
+            ),
+        ));
+    }
+    Ok(Some(encoded as i16))
+}
+
+fn utf8(array: &dyn Array) -> io::Result<&StringArray> {
+    array.as_string_opt::<i32>().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "column `{STATION_COLUMN}` has type {}, expected Utf8",
+                array.data_type()
+            ),
+        )
+    })
+}
+
+fn column_index(schema: &Schema, name: &str) -> io::Result<usize> {
+    schema.index_of(name).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("missing column `{name}`"),
+        )
+    })
+}
+
+/// Replaces the type of the station column with a dictionary, so that the
+/// Parquet reader keeps the dictionary encoding of the file.
+fn dictionary_encoded(schema: &SchemaRef) -> io::Result<SchemaRef> {
+    let index = column_index(schema, STATION_COLUMN)?;
+    let field = schema.field(index);
+    if *field.data_type() != DataType::Utf8 {
+        return Ok(schema.clone());
+    }
+    let mut fields = schema.fields().iter().cloned().collect::<Vec<_>>();
+    fields[index] = Arc::new(Field::new(
+        STATION_COLUMN,
+        DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+        field.is_nullable(),
+    ));
+    Ok(Arc::new(Schema::new_with_metadata(
+        fields,
+        schema.metadata().clone(),
+    )))
+}
+
+fn invalid(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output;
+    use arrow_array::types::Int32Type;
+    use arrow_array::{DictionaryArray, Float32Array, Float64Array};
+    use arrow_ipc::writer::FileWriter;
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("1brc-{}-{name}", std::process::id()))
+    }
+
+    /// The same measurements as `measurements_10000.txt`, as columns.
+    fn measurements() -> (Vec<String>, Vec<f64>) {
+        std::fs::read_to_string("measurements_10000.txt")
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (station, measurement) = line.split_once(';').unwrap();
+                (station.to_string(), measurement.parse::<f64>().unwrap())
+            })
+            .unzip()
+    }
+
+    fn expected() -> Results {
+        crate::aggregate_with_threads("measurements_10000.txt", NonZeroUsize::MIN, None)
+    }
+
+    #[test]
+    fn test_parquet() {
+        let (stations, temperatures) = measurements();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(STATION_COLUMN, DataType::Utf8, false),
+            Field::new(TEMPERATURE_COLUMN, DataType::Float64, false),
+        ]));
+        let path = temp_path("input.parquet");
+        // Small row groups, so that they are split across the threads.
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(1000)
+            .build();
+        let mut writer = ArrowWriter::try_new(
+            File::create(&path).unwrap(),
+            schema.clone(),
+            Some(properties),
+        )
+        .unwrap();
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(stations)),
+                Arc::new(Float64Array::from(temperatures)),
+            ],
+        )
+        .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let results = aggregate_parquet(&path, NonZeroUsize::new(4).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(results, expected());
+    }
+
+    #[test]
+    fn test_arrow_ipc() {
+        let (stations, temperatures) = measurements();
+        let stations = stations
+            .iter()
+            .map(String::as_str)
+            .collect::<DictionaryArray<Int32Type>>();
+        let temperatures = temperatures
+            .iter()
+            .map(|&temperature| temperature as f32)
+            .collect::<Float32Array>();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(STATION_COLUMN, stations.data_type().clone(), false),
+            Field::new(TEMPERATURE_COLUMN, DataType::Float32, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(stations), Arc::new(temperatures)],
+        )
+        .unwrap();
+        let path = temp_path("input.arrow");
+        let mut writer = FileWriter::try_new(File::create(&path).unwrap(), &schema).unwrap();
+        for offset in (0..batch.num_rows()).step_by(3000) {
+            let len = (batch.num_rows() - offset).min(3000);
+            writer.write(&batch.slice(offset, len)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let results = aggregate_arrow_ipc(&path, NonZeroUsize::new(3).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(results, expected());
+    }
+
+    fn write_temperatures(name: &str, temperatures: Vec<f64>) -> std::path::PathBuf {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(STATION_COLUMN, DataType::Utf8, false),
+            Field::new(TEMPERATURE_COLUMN, DataType::Float64, false),
+        ]));
+        let stations = vec!["Berlin"; temperatures.len()];
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(stations)),
+                Arc::new(Float64Array::from(temperatures)),
+            ],
+        )
+        .unwrap();
+        let path = temp_path(name);
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_non_finite_values_are_skipped() {
+        let path = write_temperatures(
+            "non-finite.parquet",
+            vec![1.5, f64::NAN, f64::INFINITY, -2.5],
+        );
+        let results = aggregate_parquet(&path, NonZeroUsize::MIN).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut data = AggregatedData::default();
+        data.add_datapoint(15);
+        data.add_datapoint(-25);
+        assert_eq!(results.get("Berlin"), Some(&data));
+    }
+
+    #[test]
+    fn test_value_out_of_range() {
+        let path = write_temperatures("out-of-range.parquet", vec![1.5, f64::NAN, 1000.0]);
+        let error = aggregate_parquet(&path, NonZeroUsize::MIN).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let mut data = AggregatedData::default();
+        data.add_datapoint(100);
+        let stats = [("Berlin", data)];
+        let batch = output::record_batch(
+            stats.iter().map(|(station, data)| (*station, data)),
+            crate::TemperatureUnit::Celsius,
+        );
+        let path = temp_path("output.parquet");
+        output::write_parquet(File::create(&path).unwrap(), &batch).unwrap();
+        let error = aggregate_parquet(&path, NonZeroUsize::MIN).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_input_format() {
+        assert_eq!(InputFormat::from_path("a.parquet"), InputFormat::Parquet);
+        assert_eq!(InputFormat::from_path("a.feather"), InputFormat::ArrowIpc);
+        assert_eq!(
+            InputFormat::from_path("measurements.txt"),
+            InputFormat::Text
+        );
+        assert_eq!("arrow".parse(), Ok(InputFormat::ArrowIpc));
+    }
+}
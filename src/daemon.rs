@@ -0,0 +1,367 @@
+//! An ingestion daemon: producers stream `<station>;<measurement>\n` lines
+//! to a Unix socket instead of writing files, and a control socket answers
+//! snapshot requests.
+//!
+//! Each client connection is served by its own thread. A thread aggregates
+//! the lines it has read into a local map and merges it into the shared state
+//! whenever it would have to wait for more input. The shared state is
+//! sharded by the hash of the station name, so that threads merging different
+//! stations don't wait for each other.
+//!
+//! The lines are parsed with the grammar of the regular parser, but
+//! validated: invalid lines of a client are counted and skipped instead of
+//! trusted. Lines longer than [`MAX_LINE_LEN`] are skipped without buffering
+//! them.
+
+use crate::hash::HashMap;
+use crate::quality::{parse_line, MAX_STATION_LEN};
+use crate::results::Results;
+use crate::AggregatedData;
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::num::NonZeroUsize;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// The maximum length of a line including the newline, with some slack for
+/// `\r\n`. Longer lines can't be valid: the station is at most
+/// [`MAX_STATION_LEN`] bytes, followed by `;-99.9`.
+const MAX_LINE_LEN: usize = MAX_STATION_LEN + 8;
+
+/// Counters of a [`Daemon`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DaemonStats {
+    /// The accepted client connections, including closed ones.
+    pub connections: u64,
+    pub lines: u64,
+    /// Skipped lines that are not valid measurements.
+    pub invalid_lines: u64,
+}
+
+/// The shared state of the ingestion daemon.
+#[derive(Debug)]
+pub struct Daemon {
+    shards: Vec<Mutex<HashMap<String, AggregatedData>>>,
+    connections: AtomicU64,
+    lines: AtomicU64,
+    invalid_lines: AtomicU64,
+}
+
+impl Daemon {
+    /// Creates an empty state with the given amount of shards.
+    #[must_use]
+    pub fn new(shards: NonZeroUsize) -> Self {
+        Self {
+            shards: (0..shards.get())
+                .map(|_| Mutex::new(HashMap::default()))
+                .collect(),
+            connections: AtomicU64::new(0),
+            lines: AtomicU64::new(0),
+            invalid_lines: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, station: &str) -> &Mutex<HashMap<String, AggregatedData>> {
+        let hash = crate::hash::hash64(station.as_bytes());
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    /// Accepts producers on the socket, each on its own thread, until
+    /// accepting a connection fails.
+    pub fn serve_ingest(&self, listener: &UnixListener) -> io::Result<()> {
+        thread::scope(|scope| loop {
+            let (stream, _) = listener.accept()?;
+            self.connections.fetch_add(1, Ordering::Relaxed);
+            // A broken connection only loses the lines that weren't
+            // received completely.
+            scope.spawn(move || self.ingest(stream));
+        })
+    }
+
+    /// Reads lines from the stream until it is closed.
+    pub fn ingest(&self, stream: impl io::Read) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut local = HashMap::<String, AggregatedData>::default();
+        let mut line = Vec::new();
+        let (mut lines, mut invalid_lines) = (0, 0);
+        loop {
+            line.clear();
+            let result = (&mut reader)
+                .take(MAX_LINE_LEN as u64)
+                .read_until(b'\n', &mut line);
+            if matches!(result, Ok(0) | Err(_)) {
+                self.flush(&mut local, &mut lines, &mut invalid_lines);
+                return result.map(|_| ());
+            }
+
+            lines += 1;
+            if line.len() == MAX_LINE_LEN && line.last() != Some(&b'\n') {
+                // Longer than any valid line: discard it up to the newline.
+                invalid_lines += 1;
+                if let Err(err) = skip_line(&mut reader) {
+                    self.flush(&mut local, &mut lines, &mut invalid_lines);
+                    return Err(err);
+                }
+            } else {
+                let content = line.strip_suffix(b"\n").unwrap_or(&line);
+                match parse_line(content) {
+                    Ok((station, measurement)) => match local.get_mut(station) {
+                        Some(data) => data.add_datapoint(measurement),
+                        None => {
+                            let mut data = AggregatedData::default();
+                            data.add_datapoint(measurement);
+                            local.insert(station.to_string(), data);
+                        }
+                    },
+                    Err(_) => invalid_lines += 1,
+                }
+            }
+
+            // The next read would wait for the client: publish what we have.
+            if reader.buffer().is_empty() {
+                self.flush(&mut local, &mut lines, &mut invalid_lines);
+            }
+        }
+    }
+
+    /// Merges the local state of a connection into the shared state.
+    fn flush(
+        &self,
+        local: &mut HashMap<String, AggregatedData>,
+        lines: &mut u64,
+        invalid_lines: &mut u64,
+    ) {
+        for (station, data) in local.drain() {
+            self.shard(&station)
+                .lock()
+                .unwrap()
+                .entry(station)
+                .or_default()
+                .merge(&data);
+        }
+        self.lines
+            .fetch_add(std::mem::take(lines), Ordering::Relaxed);
+        self.invalid_lines
+            .fetch_add(std::mem::take(invalid_lines), Ordering::Relaxed);
+    }
+
+    /// Returns a copy of the current state. The shards are copied one after
+    /// another, so lines merged in the meantime may be contained partially.
+    #[must_use]
+    pub fn snapshot(&self) -> Results {
+        let mut stations = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            stations.extend(
+                shard
+                    .iter()
+                    .map(|(station, data)| (station.clone(), data.clone())),
+            );
+        }
+        stations.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Results::from_owned_sorted(stations)
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> DaemonStats {
+        DaemonStats {
+            connections: self.connections.load(Ordering::Relaxed),
+            lines: self.lines.load(Ordering::Relaxed),
+            invalid_lines: self.invalid_lines.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Answers requests on the control socket, one connection after another,
+    /// until accepting a connection fails. See [`Self::control`].
+    pub fn serve_control(&self, listener: &UnixListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept()?;
+            // A broken connection only affects the client.
+            let _ = self.control(&stream, &stream);
+        }
+    }
+
+    /// Answers one command per line until the reader is closed:
+    ///
+    /// - `snapshot`: the state as snapshot (see [`Results::write_snapshot`])
+    /// - `print`: the state in the format of the challenge
+    /// - `stats`: the [`DaemonStats`] as JSON
+    pub fn control(&self, reader: impl io::Read, mut writer: impl Write) -> io::Result<()> {
+        for command in BufReader::new(reader).lines() {
+            match command?.trim() {
+                "snapshot" => self.snapshot().write_snapshot(&mut writer)?,
+                "print" => crate::output::write_text(
+                    &mut writer,
+                    self.snapshot()
+                        .stations()
+                        .iter()
+                        .map(|(station, data)| (station.as_str(), data)),
+                    crate::TemperatureUnit::Celsius,
+                )?,
+                "stats" => {
+                    serde_json::to_writer(&mut writer, &self.stats())?;
+                    writeln!(writer)?;
+                }
+                "" => continue,
+                command => writeln!(
+                    writer,
+                    "error: unknown command `{command}`, expected snapshot, print, or stats"
+                )?,
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Discards the rest of the current line, including the newline, without
+/// buffering it.
+fn skip_line(reader: &mut impl BufRead) -> io::Result<()> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        match memchr::memchr(b'\n', buffer) {
+            Some(newline) => {
+                reader.consume(newline + 1);
+                return Ok(());
+            }
+            None => {
+                let len = buffer.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Sends a command to the control socket of a daemon and returns the answer.
+pub fn control_request(socket: &UnixStream, command: &str) -> io::Result<String> {
+    let mut writer = socket;
+    writeln!(writer, "{command}")?;
+    writer.flush()?;
+    let mut answer = String::new();
+    BufReader::new(socket).read_line(&mut answer)?;
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("1brc-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_ingest() {
+        let daemon = Daemon::new(NonZeroUsize::new(4).unwrap());
+        daemon
+            .ingest("Berlin;10.0\nHamburg;-12.7\nBerlin;warm\n\nBerlin;-15.7".as_bytes())
+            .unwrap();
+        daemon.ingest(b"Hamburg;1.0\n".as_slice()).unwrap();
+
+        let snapshot = daemon.snapshot();
+        assert_eq!(
+            snapshot.get("Berlin"),
+            Some(&AggregatedData::new(-157, 100, -57, 2))
+        );
+        assert_eq!(snapshot.get("Hamburg").unwrap().count(), 2);
+        assert_eq!(
+            daemon.stats(),
+            DaemonStats {
+                connections: 0,
+                lines: 6,
+                invalid_lines: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_ingest_oversized_line() {
+        let daemon = Daemon::new(NonZeroUsize::new(1).unwrap());
+        let mut input = b"Oslo;1.0\n".to_vec();
+        input.extend(std::iter::repeat_n(b'x', 1 << 20));
+        input.extend(b";1.0\nOslo;2.0\n");
+        // Exactly at the limit, without the newline.
+        input.extend(std::iter::repeat_n(b'y', MAX_LINE_LEN));
+        input.extend(b"\nOslo;3.0\n");
+        daemon.ingest(input.as_slice()).unwrap();
+
+        let snapshot = daemon.snapshot();
+        assert_eq!(snapshot.stations().len(), 1);
+        assert_eq!(snapshot.get("Oslo").unwrap().count(), 3);
+        assert_eq!(daemon.stats().lines, 5);
+        assert_eq!(daemon.stats().invalid_lines, 2);
+    }
+
+    #[test]
+    fn test_control() {
+        let daemon = Daemon::new(NonZeroUsize::new(1).unwrap());
+        daemon.ingest(b"Oslo;1.0\nOslo;2.0\n".as_slice()).unwrap();
+        let mut output = Vec::new();
+        daemon
+            .control(b"stats\nprint\nsnapshot\nhelp\n".as_slice(), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"connections\":0,\"lines\":2,\"invalid_lines\":0}\n\
+            {Oslo=1.0/1.5/2.0}\n\
+            [[\"Oslo\",{\"min\":10,\"max\":20,\"sum\":30,\"sample_count\":2}]]\n\
+            error: unknown command `help`, expected snapshot, print, or stats\n"
+        );
+    }
+
+    #[test]
+    fn test_sockets() {
+        let ingest_path = socket_path("ingest");
+        let control_path = socket_path("control");
+        let ingest = UnixListener::bind(&ingest_path).unwrap();
+        let control = UnixListener::bind(&control_path).unwrap();
+        let daemon: &Daemon = Box::leak(Box::new(Daemon::new(NonZeroUsize::new(8).unwrap())));
+        thread::spawn(move || daemon.serve_ingest(&ingest));
+        thread::spawn(move || daemon.serve_control(&control));
+
+        let producers = (0..4)
+            .map(|_| {
+                let path = ingest_path.clone();
+                thread::spawn(move || {
+                    let mut stream = UnixStream::connect(path).unwrap();
+                    for _ in 0..1000 {
+                        stream.write_all(b"Berlin;1.0\nOslo;-1.0\n").unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        let client = UnixStream::connect(&control_path).unwrap();
+        // The producers are done, but the daemon may still be reading.
+        let mut stats = String::new();
+        for _ in 0..100 {
+            stats = control_request(&client, "stats").unwrap();
+            if stats.contains("\"lines\":8000") {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(
+            stats,
+            "{\"connections\":4,\"lines\":8000,\"invalid_lines\":0}\n"
+        );
+        let snapshot = control_request(&client, "snapshot").unwrap();
+        let snapshot = Results::read_snapshot(snapshot.as_bytes()).unwrap();
+        assert_eq!(snapshot.get("Berlin").unwrap().count(), 4000);
+        assert_eq!(snapshot.get("Oslo").unwrap().avg(), -1.0);
+
+        let _ = std::fs::remove_file(ingest_path);
+        let _ = std::fs::remove_file(control_path);
+    }
+}
@@ -0,0 +1,588 @@
+//! Map-reduce across processes: a coordinator distributes line-aligned byte
+//! ranges of a file to workers over TCP and merges their partial results.
+//!
+//! The file must be reachable under the same path by all workers, for
+//! example on a shared file system. The protocol consists of one JSON message
+//! per line. The coordinator sends a [`Task`], the worker answers with a
+//! [`Reply`], over the same connection, until the coordinator closes it.
+//!
+//! If a worker can't be reached, closes the connection, or doesn't answer
+//! within [`CoordinatorOptions::timeout`], it is considered dead and its range
+//! is reassigned to the remaining workers.
+//!
+//! Workers don't authenticate coordinators. They only process files within
+//! their [`WorkerOptions::root`] and validate each line, but should only
+//! listen on trusted networks nevertheless.
+
+use crate::quality::QualityOptions;
+use crate::results::Results;
+use crate::AggregatedData;
+use crate::{aggregate_range_validated, chunk_boundaries};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A byte range of a file to aggregate. Sent from the coordinator to a worker.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Task {
+    pub id: usize,
+    pub path: PathBuf,
+    pub range: Range<usize>,
+}
+
+/// The answer of a worker to a [`Task`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    /// The partial aggregate of the range, sorted by station name. Replies
+    /// with unsorted or duplicate stations are rejected like a broken
+    /// connection.
+    Done {
+        id: usize,
+        stations: Vec<(String, AggregatedData)>,
+    },
+    /// The range couldn't be processed by this worker, e.g., because the file
+    /// doesn't exist on its machine.
+    Failed { id: usize, error: String },
+}
+
+/// Options for [`serve_worker`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerOptions {
+    /// Only files within this directory, after resolving symbolic links, are
+    /// processed. Tasks for other files fail.
+    pub root: PathBuf,
+    /// The amount of threads per range.
+    pub threads: NonZeroUsize,
+    /// How long to wait for the next task of a coordinator, or for a reply to
+    /// be sent, before the connection is closed.
+    pub timeout: Duration,
+}
+
+/// Options for [`coordinate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoordinatorOptions {
+    /// The amount of ranges the file is split into. More ranges than workers
+    /// balance the load and lose less work if a worker fails.
+    pub tasks: NonZeroUsize,
+    /// How long to wait for a connection or a reply before the worker is
+    /// considered dead.
+    pub timeout: Duration,
+    /// How often a range is retried after a worker reported that it failed.
+    pub max_attempts: usize,
+}
+
+impl Default for CoordinatorOptions {
+    fn default() -> Self {
+        Self {
+            tasks: NonZeroUsize::new(64).unwrap(),
+            timeout: Duration::from_secs(600),
+            max_attempts: 3,
+        }
+    }
+}
+
+/// Why [`coordinate`] failed.
+#[derive(Debug)]
+pub enum CoordinatorError {
+    /// All workers are dead, but ranges are left.
+    NoWorkersLeft { remaining_tasks: usize },
+    /// A range failed on workers more often than allowed.
+    TaskFailed { task: Task, error: String },
+    /// The file can't be read, or doesn't end with a newline.
+    Io(io::Error),
+}
+
+impl Display for CoordinatorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoWorkersLeft { remaining_tasks } => {
+                write!(f, "all workers failed, {remaining_tasks} ranges left")
+            }
+            Self::TaskFailed { task, error } => write!(
+                f,
+                "range {}..{} of {} failed: {error}",
+                task.range.start,
+                task.range.end,
+                task.path.display()
+            ),
+            Self::Io(err) => write!(f, "failed to read the file: {err}"),
+        }
+    }
+}
+
+impl Error for CoordinatorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Serves tasks of coordinators on the listener until accepting a connection
+/// fails. Each connection is served by its own thread.
+///
+/// A coordinator may send multiple tasks over a connection.
+pub fn serve_worker(listener: &TcpListener, options: &WorkerOptions) -> io::Result<()> {
+    let root = options.root.canonicalize()?;
+    thread::scope(|scope| loop {
+        let (stream, _) = listener.accept()?;
+        let root = root.as_path();
+        // A broken connection only affects the coordinator, which reassigns
+        // the range.
+        scope.spawn(move || handle_connection(stream, root, options));
+    })
+}
+
+fn handle_connection(stream: TcpStream, root: &Path, options: &WorkerOptions) -> io::Result<()> {
+    stream.set_read_timeout(Some(options.timeout))?;
+    stream.set_write_timeout(Some(options.timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let task = serde_json::from_str::<Task>(&line)?;
+        let reply = process_task(&task, root, options.threads);
+        write_message(&mut writer, &reply)?;
+    }
+}
+
+/// Aggregates the range of the task, if the file is within the root
+/// directory. Errors, including invalid lines and panics of the library, are
+/// reported to the coordinator instead of killing the worker.
+fn process_task(task: &Task, root: &Path, threads: NonZeroUsize) -> Reply {
+    let failed = |error| Reply::Failed { id: task.id, error };
+    let path = match task.path.canonicalize() {
+        Ok(path) if path.starts_with(root) => path,
+        Ok(_) => {
+            return failed(format!(
+                "{}: outside of the root directory of the worker",
+                task.path.display()
+            ))
+        }
+        Err(err) => return failed(format!("{}: {err}", task.path.display())),
+    };
+    // The library panics on I/O errors.
+    if let Err(err) = File::open(&path) {
+        return failed(format!("{}: {err}", task.path.display()));
+    }
+    // Not a single invalid line is accepted, as the coordinator can't report
+    // skipped lines.
+    let options = QualityOptions {
+        max_errors: Some(0),
+        ..QualityOptions::default()
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        aggregate_range_validated(&path, task.range.clone(), threads, None, &options)
+    }));
+    match result {
+        Ok(Ok(results)) => Reply::Done {
+            id: task.id,
+            stations: results.stations().to_vec(),
+        },
+        Ok(Err(err)) => failed(format!("{}: {err}", task.path.display())),
+        Err(_) => Reply::Failed {
+            id: task.id,
+            error: "the worker panicked".to_string(),
+        },
+    }
+}
+
+fn write_message(writer: &mut impl Write, message: &impl Serialize) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+/// The work shared by the connections to the workers.
+#[derive(Debug)]
+struct Queue {
+    pending: VecDeque<(Task, usize)>,
+    in_flight: usize,
+    results: Results,
+    error: Option<CoordinatorError>,
+}
+
+impl Queue {
+    /// Processes the reply of a worker to the task. Returns false if the
+    /// worker is considered dead.
+    fn complete(
+        &mut self,
+        task: Task,
+        attempts: usize,
+        reply: io::Result<Reply>,
+        max_attempts: usize,
+    ) -> bool {
+        self.in_flight -= 1;
+        match reply {
+            Ok(Reply::Done { id, stations }) if id == task.id => {
+                let Some(results) = Results::try_from_owned_sorted(stations) else {
+                    self.pending.push_front((task, attempts));
+                    return false;
+                };
+                self.results.merge(results);
+            }
+            Ok(Reply::Failed { id, error }) if id == task.id => {
+                if attempts + 1 >= max_attempts {
+                    self.error = Some(CoordinatorError::TaskFailed { task, error });
+                } else {
+                    self.pending.push_back((task, attempts + 1));
+                }
+            }
+            // A broken connection, a timeout, or a confused worker.
+            _ => {
+                self.pending.push_front((task, attempts));
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Checks that the file can be opened and, unless it is empty, ends with a
+/// newline, which [`chunk_boundaries`] relies on. Returns the length.
+fn check_file(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len > 0 {
+        let mut last_byte = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last_byte)?;
+        if last_byte != [b'\n'] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the file doesn't end with a newline",
+            ));
+        }
+    }
+    Ok(len)
+}
+
+/// Splits the file into line-aligned ranges, lets the workers aggregate them,
+/// and merges the partial results.
+///
+/// Each worker processes one range at a time. Ranges of dead workers are
+/// reassigned to the others. The coordinator reads the file itself only to
+/// find the line boundaries.
+pub fn coordinate(
+    path: impl AsRef<Path>,
+    workers: &[SocketAddr],
+    options: &CoordinatorOptions,
+) -> Result<Results, CoordinatorError> {
+    let path = path.as_ref();
+    if check_file(path).map_err(CoordinatorError::Io)? == 0 {
+        return Ok(Results::default());
+    }
+    // The ranges of the regular chunking are line-aligned already.
+    let pending = chunk_boundaries(path, options.tasks)
+        .into_iter()
+        .enumerate()
+        .map(|(id, range)| {
+            let task = Task {
+                id,
+                path: path.to_path_buf(),
+                range,
+            };
+            (task, 0)
+        })
+        .collect();
+    let queue = Mutex::new(Queue {
+        pending,
+        in_flight: 0,
+        results: Results::default(),
+        error: None,
+    });
+    let changed = Condvar::new();
+
+    thread::scope(|scope| {
+        for &worker in workers {
+            let (queue, changed) = (&queue, &changed);
+            scope.spawn(move || drive_worker(worker, queue, changed, options));
+        }
+    });
+
+    let queue = queue.into_inner().unwrap();
+    if let Some(error) = queue.error {
+        return Err(error);
+    }
+    if !queue.pending.is_empty() {
+        return Err(CoordinatorError::NoWorkersLeft {
+            remaining_tasks: queue.pending.len(),
+        });
+    }
+    Ok(queue.results)
+}
+
+/// Hands tasks to a single worker until no tasks are left or the worker
+/// dies.
+fn drive_worker(
+    worker: SocketAddr,
+    queue: &Mutex<Queue>,
+    changed: &Condvar,
+    options: &CoordinatorOptions,
+) {
+    let Ok(mut connection) = Connection::open(worker, options.timeout) else {
+        return;
+    };
+    loop {
+        let (task, attempts) = {
+            let mut guard = queue.lock().unwrap();
+            // Wait for ranges of failed workers as long as others are busy.
+            while guard.pending.is_empty() && guard.in_flight > 0 && guard.error.is_none() {
+                guard = changed.wait(guard).unwrap();
+            }
+            if guard.error.is_some() {
+                return;
+            }
+            let Some(next) = guard.pending.pop_front() else {
+                return;
+            };
+            guard.in_flight += 1;
+            next
+        };
+
+        let reply = connection.request(&task);
+
+        let alive = queue
+            .lock()
+            .unwrap()
+            .complete(task, attempts, reply, options.max_attempts);
+        changed.notify_all();
+        if !alive {
+            return;
+        }
+    }
+}
+
+/// A connection from the coordinator to a worker.
+#[derive(Debug)]
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Connection {
+    fn open(worker: SocketAddr, timeout: Duration) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&worker, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    fn request(&mut self, task: &Task) -> io::Result<Reply> {
+        write_message(&mut self.writer, task)?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate_with_threads;
+
+    const PATH: &str = "measurements_10000.txt";
+
+    fn options() -> CoordinatorOptions {
+        CoordinatorOptions {
+            tasks: NonZeroUsize::new(7).unwrap(),
+            timeout: Duration::from_secs(10),
+            max_attempts: 3,
+        }
+    }
+
+    fn expected() -> Results {
+        aggregate_with_threads(PATH, NonZeroUsize::new(1).unwrap(), None)
+    }
+
+    fn worker_options(root: impl Into<PathBuf>) -> WorkerOptions {
+        WorkerOptions {
+            root: root.into(),
+            threads: NonZeroUsize::new(1).unwrap(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Starts a worker in the background and returns its address.
+    fn spawn_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve_worker(&listener, &worker_options(".")));
+        address
+    }
+
+    /// Starts a worker that accepts a connection, reads a task, and dies.
+    fn spawn_failing_worker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+        });
+        address
+    }
+
+    /// An address on which nobody listens.
+    fn unreachable_worker() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_coordinate() {
+        let workers = [spawn_worker(), spawn_worker(), spawn_worker()];
+        let results = coordinate(PATH, &workers, &options()).unwrap();
+        assert_eq!(results, expected());
+    }
+
+    #[test]
+    fn test_reassign_after_worker_failure() {
+        let workers = [
+            spawn_failing_worker(),
+            unreachable_worker(),
+            spawn_worker(),
+            spawn_failing_worker(),
+        ];
+        let results = coordinate(PATH, &workers, &options()).unwrap();
+        assert_eq!(results, expected());
+    }
+
+    #[test]
+    fn test_all_workers_failed() {
+        let workers = [spawn_failing_worker(), unreachable_worker()];
+        let err = coordinate(PATH, &workers, &options()).unwrap_err();
+        assert!(
+            matches!(err, CoordinatorError::NoWorkersLeft { remaining_tasks: 7 }),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_unreadable_file() {
+        let workers = [unreachable_worker()];
+        let err = coordinate("does-not-exist.txt", &workers, &options()).unwrap_err();
+        assert!(
+            matches!(&err, CoordinatorError::Io(err) if err.kind() == io::ErrorKind::NotFound),
+            "{err}"
+        );
+
+        let path = std::env::temp_dir().join(format!("1brc-{}-unterminated", std::process::id()));
+        std::fs::write(&path, "Berlin;1.0\nOslo;2.0").unwrap();
+        let err = coordinate(&path, &workers, &options()).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            matches!(&err, CoordinatorError::Io(err) if err.kind() == io::ErrorKind::InvalidData),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_task_failed() {
+        let task = Task {
+            id: 3,
+            path: PathBuf::from("does-not-exist.txt"),
+            range: 0..10,
+        };
+        let threads = NonZeroUsize::new(1).unwrap();
+        let reply = process_task(&task, &Path::new(".").canonicalize().unwrap(), threads);
+        assert!(matches!(reply, Reply::Failed { id: 3, .. }));
+
+        let reply = serde_json::to_string(&Reply::Done {
+            id: 1,
+            stations: vec![("Berlin".to_string(), AggregatedData::new(1, 2, 3, 2))],
+        })
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Reply>(&reply).unwrap(),
+            Reply::Done {
+                id: 1,
+                stations: vec![("Berlin".to_string(), AggregatedData::new(1, 2, 3, 2))],
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_reply() {
+        let invalid =
+            r#"{"done":{"id":1,"stations":[["Oslo",{"min":1,"max":1,"sum":0,"sample_count":0}]]}}"#;
+        assert!(serde_json::from_str::<Reply>(invalid).is_err());
+
+        let mut queue = Queue {
+            pending: VecDeque::new(),
+            in_flight: 1,
+            results: Results::default(),
+            error: None,
+        };
+        let task = Task {
+            id: 1,
+            path: PathBuf::from(PATH),
+            range: 0..10,
+        };
+        let data = AggregatedData::new(1, 1, 1, 1);
+        let unsorted = Reply::Done {
+            id: 1,
+            stations: vec![
+                ("Oslo".to_string(), data.clone()),
+                ("Berlin".to_string(), data),
+            ],
+        };
+        assert!(!queue.complete(task.clone(), 0, Ok(unsorted), 3));
+        assert_eq!(queue.pending, [(task, 0)]);
+        assert_eq!(queue.results, Results::default());
+    }
+
+    #[test]
+    fn test_task_outside_of_root() {
+        let task = Task {
+            id: 1,
+            path: PathBuf::from(PATH),
+            range: 0..10,
+        };
+        let threads = NonZeroUsize::new(1).unwrap();
+        let reply = process_task(&task, &Path::new("src").canonicalize().unwrap(), threads);
+        let Reply::Failed { id: 1, error } = reply else {
+            panic!("{reply:?}");
+        };
+        assert!(error.contains("outside of the root directory"), "{error}");
+    }
+
+    #[test]
+    fn test_task_with_invalid_lines() {
+        let path = std::env::temp_dir().join(format!("1brc-{}-invalid.txt", std::process::id()));
+        std::fs::write(&path, "Berlin;1.0\nHamburg;abc\n").unwrap();
+        let task = Task {
+            id: 2,
+            path: path.clone(),
+            range: 0..100,
+        };
+        let root = std::env::temp_dir().canonicalize().unwrap();
+        let reply = process_task(&task, &root, NonZeroUsize::new(1).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(reply, Reply::Failed { id: 2, .. }), "{reply:?}");
+    }
+}
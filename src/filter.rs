@@ -0,0 +1,235 @@
+//! Station and value filters that are applied inside the hot loop, before
+//! a measurement is aggregated.
+
+use crate::hash::{HashMap, HashSet};
+use crate::AggregatedData;
+use phips_1brc_core::data_set_properties::STATIONS_IN_DATASET;
+use phips_1brc_core::process_line;
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::ops::RangeInclusive;
+
+/// Selects the stations and measurements that are aggregated.
+///
+/// A station is selected if it
+/// - is in the include list (if one was specified),
+/// - is not in the exclude list,
+/// - starts with one of the prefixes (if some were specified), and
+/// - matches the regex (if one was specified, requires the `regex` feature).
+///
+/// Measurements outside the value range (if one was specified) are discarded.
+///
+/// The (potentially expensive) station checks are evaluated only once per
+/// distinct station and chunk. After that, rejecting a line only costs the
+/// hash lookup that is needed for the aggregation anyway.
+#[derive(Debug, Default, Clone)]
+pub struct StationFilter {
+    include: Option<HashSet<String>>,
+    exclude: HashSet<String>,
+    prefixes: Vec<String>,
+    #[cfg(feature = "regex")]
+    regex: Option<Regex>,
+    /// Encoded as integers multiplied by ten, like all measurements.
+    value_range: Option<RangeInclusive<i16>>,
+}
+
+impl StationFilter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only select the given stations. Can be called multiple times.
+    #[must_use]
+    pub fn include<S: Into<String>>(mut self, stations: impl IntoIterator<Item = S>) -> Self {
+        self.include
+            .get_or_insert_with(HashSet::default)
+            .extend(stations.into_iter().map(Into::into));
+        self
+    }
+
+    /// Never select the given stations. Can be called multiple times.
+    #[must_use]
+    pub fn exclude<S: Into<String>>(mut self, stations: impl IntoIterator<Item = S>) -> Self {
+        self.exclude.extend(stations.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only select stations starting with the given prefix. If called
+    /// multiple times, a station must match one of the prefixes.
+    #[must_use]
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    /// Only select stations matching the given regular expression. The regex
+    /// is not anchored, use `^` and `$` to match whole station names.
+    #[cfg(feature = "regex")]
+    pub fn regex(mut self, regex: &str) -> Result<Self, regex::Error> {
+        self.regex = Some(Regex::new(regex)?);
+        Ok(self)
+    }
+
+    /// Discard all measurements outside the given range, for example
+    /// `-50.0..=60.0`. The bounds are rounded to one decimal place.
+    #[must_use]
+    pub fn value_range(mut self, range: RangeInclusive<f32>) -> Self {
+        let encode = |value: f32| (value * 10.0).round() as i16;
+        self.value_range = Some(encode(*range.start())..=encode(*range.end()));
+        self
+    }
+
+    /// Returns whether measurements of the given station are aggregated.
+    #[must_use]
+    pub fn accepts_station(&self, station: &str) -> bool {
+        let accepted = self
+            .include
+            .as_ref()
+            .is_none_or(|include| include.contains(station))
+            && !self.exclude.contains(station)
+            && (self.prefixes.is_empty()
+                || self
+                    .prefixes
+                    .iter()
+                    .any(|prefix| station.starts_with(prefix.as_str())));
+        #[cfg(feature = "regex")]
+        let accepted = accepted
+            && self
+                .regex
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(station));
+        accepted
+    }
+
+    /// Returns whether the given measurement (encoded as integer multiplied by
+    /// ten) is aggregated.
+    #[inline(always)]
+    pub(crate) fn accepts_measurement(&self, measurement: i16) -> bool {
+        self.value_range
+            .as_ref()
+            .is_none_or(|range| range.contains(&measurement))
+    }
+}
+
+/// Filtered variant of [`crate::process_file_chunk`].
+///
+/// The map caches the decision of [`StationFilter::accepts_station`]: Rejected
+/// stations map to `None`. Stations of which all measurements were discarded
+/// are removed from the result.
+pub fn process_file_chunk<'a>(
+    bytes: &'a [u8],
+    filter: &StationFilter,
+) -> HashMap<&'a str, AggregatedData> {
+    assert!(!bytes.is_empty());
+    let &last_byte = bytes.last().unwrap();
+    assert_eq!(last_byte, b'\n');
+
+    let mut stats: HashMap<&str, Option<AggregatedData>> =
+        HashMap::with_capacity_and_hasher(STATIONS_IN_DATASET, Default::default());
+
+    let mut consumed_bytes_count = 0;
+    while consumed_bytes_count < bytes.len() {
+        let remaining_bytes = unsafe { bytes.get_unchecked(consumed_bytes_count..) };
+        let (station, measurement) =
+            unsafe { process_line(remaining_bytes, &mut consumed_bytes_count) };
+        if !filter.accepts_measurement(measurement) {
+            continue;
+        }
+        let data = stats.entry(station).or_insert_with(|| {
+            filter
+                .accepts_station(station)
+                .then(AggregatedData::default)
+        });
+        if let Some(data) = data {
+            data.add_datapoint(measurement);
+        }
+    }
+
+    stats
+        .into_iter()
+        .filter_map(|(station, data)| Some((station, data?)))
+        .filter(|(_, data)| !data.empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "Berlin;10.0\nHamburg;-12.7\nNew York;21.5\nBerlin;-15.7\n\
+                         Bern;70.3\nHamburg;99.9\n";
+
+    fn process(filter: &StationFilter) -> Vec<(&'static str, AggregatedData)> {
+        let mut stats = process_file_chunk(INPUT.as_bytes(), filter)
+            .into_iter()
+            .collect::<Vec<_>>();
+        stats.sort_unstable_by_key(|(station, _)| *station);
+        stats
+    }
+
+    fn stations(filter: &StationFilter) -> Vec<&'static str> {
+        process(filter)
+            .into_iter()
+            .map(|(station, _)| station)
+            .collect()
+    }
+
+    #[test]
+    fn test_no_filter_accepts_everything() {
+        assert_eq!(
+            stations(&StationFilter::new()),
+            ["Berlin", "Bern", "Hamburg", "New York"]
+        );
+    }
+
+    #[test]
+    fn test_include_exclude() {
+        let filter = StationFilter::new().include(["Berlin", "Hamburg"]);
+        assert_eq!(stations(&filter), ["Berlin", "Hamburg"]);
+
+        let filter = filter.exclude(["Hamburg"]);
+        assert_eq!(stations(&filter), ["Berlin"]);
+
+        let filter = StationFilter::new().exclude(["Berlin", "Bern"]);
+        assert_eq!(stations(&filter), ["Hamburg", "New York"]);
+    }
+
+    #[test]
+    fn test_prefix() {
+        let filter = StationFilter::new().prefix("Ber").prefix("New");
+        assert_eq!(stations(&filter), ["Berlin", "Bern", "New York"]);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex() {
+        let filter = StationFilter::new().prefix("Ber").prefix("New");
+        let filter = filter.regex("n$").unwrap();
+        assert_eq!(stations(&filter), ["Berlin", "Bern"]);
+
+        assert!(StationFilter::new().regex("(").is_err());
+    }
+
+    #[test]
+    fn test_value_range() {
+        let filter = StationFilter::new().value_range(-50.0..=60.0);
+        assert_eq!(
+            process(&filter),
+            [
+                ("Berlin", AggregatedData::new(-157, 100, -57, 2)),
+                ("Hamburg", AggregatedData::new(-127, -127, -127, 1)),
+                ("New York", AggregatedData::new(215, 215, 215, 1)),
+            ]
+        );
+
+        let filter = StationFilter::new().value_range(-12.7..=10.0);
+        assert_eq!(
+            process(&filter),
+            [
+                ("Berlin", AggregatedData::new(100, 100, 100, 1)),
+                ("Hamburg", AggregatedData::new(-127, -127, -127, 1)),
+            ]
+        );
+    }
+}
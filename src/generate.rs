@@ -0,0 +1,78 @@
+//! Generator for measurement files, similar to the upstream
+//! `CreateMeasurements.java`, but without the need for Java and Maven.
+
+use crate::process_file_chunk;
+use crate::rng::SplitMix64;
+use crate::units::EncodedDecimal;
+use crate::AggregatedData;
+use std::io::{self, Write};
+
+/// The sample shipped with this repository. It contains all stations of the
+/// upstream data set, so the mean temperatures per station are derived from
+/// it.
+const SAMPLE: &[u8] = include_bytes!("../measurements_10000.txt");
+
+/// The standard deviation of the measurements of a station, as upstream.
+const STDDEV: f64 = 10.0;
+
+/// Returns all stations of the data set, together with their mean
+/// temperature (encoded as integer multiplied by 10), sorted by name.
+fn stations() -> Vec<(&'static str, i16)> {
+    let mut stations = process_file_chunk::<AggregatedData>(SAMPLE)
+        .into_iter()
+        .map(|(station, data)| (station, (data.avg() * 10.0).round() as i16))
+        .collect::<Vec<_>>();
+    stations.sort_unstable();
+    stations
+}
+
+/// Writes `rows` random measurements in the format of the 1BRC data set.
+///
+/// Each row belongs to a uniformly chosen station. The measurement is normally
+/// distributed around the mean temperature of the station, rounded to one
+/// decimal place and clamped to `-99.9..=99.9`. The output only depends on the
+/// seed.
+///
+/// The writer should be buffered.
+pub fn generate(writer: &mut impl Write, rows: u64, seed: u64) -> io::Result<()> {
+    let stations = stations();
+    let mut rng = SplitMix64::new(seed);
+    for _ in 0..rows {
+        let (station, mean) = stations[rng.next_below(stations.len() as u64) as usize];
+        let measurement = rng.next_gaussian(mean as f64, STDDEV * 10.0).round() as i64;
+        let measurement = EncodedDecimal(measurement.clamp(-999, 999));
+        writeln!(writer, "{station};{measurement}")?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phips_1brc_core::data_set_properties::STATIONS_IN_DATASET;
+
+    #[test]
+    fn test_stations() {
+        let stations = stations();
+        assert_eq!(stations.len(), STATIONS_IN_DATASET);
+        assert!(stations.iter().all(|(_, mean)| (-999..=999).contains(mean)));
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut a = Vec::new();
+        generate(&mut a, 1000, 7).unwrap();
+        let mut b = Vec::new();
+        generate(&mut b, 1000, 7).unwrap();
+        assert_eq!(a, b, "must be deterministic");
+
+        let stats = process_file_chunk::<AggregatedData>(&a);
+        let count = stats.values().map(|data| data.count()).sum::<u32>();
+        assert_eq!(count, 1000);
+        assert!(stats.len() > 300);
+        assert!(stats
+            .values()
+            .all(|data| (-999..=999).contains(&data.encoded_min())
+                && (-999..=999).contains(&data.encoded_max())));
+    }
+}
@@ -0,0 +1,10 @@
+//! The hash maps and the hash function of the crate.
+
+pub use gxhash::{HashMap, HashSet};
+
+/// A stable 64-bit hash of the bytes, for example for sharding and
+/// cardinality estimation: gxhash with a fixed seed, so the hash only changes
+/// with the version of gxhash.
+pub fn hash64(bytes: &[u8]) -> u64 {
+    gxhash::gxhash64(bytes, 0)
+}
@@ -1,5 +1,5 @@
 //! Implements a highly optimized variant for the 1BRC.
-//! Look at [`process_file_chunk`], which is the heart of the implementation.
+//! Look at `process_file_chunk`, which is the heart of the implementation.
 //!
 //! All convenience around it, such as allocating a few helpers, is negligible
 //! from my testing.
@@ -17,7 +17,8 @@
 #![allow(
     clippy::suboptimal_flops,
     clippy::redundant_pub_crate,
-    clippy::fallible_impl_from
+    clippy::fallible_impl_from,
+    clippy::option_if_let_else
 )]
 // I can't do anything about this; fault of the dependencies
 #![allow(clippy::multiple_crate_versions)]
@@ -28,31 +29,85 @@
 #![deny(missing_debug_implementations)]
 #![deny(rustdoc::all)]
 
-mod aggregated_data;
-mod chunk_iter;
-
-use crate::chunk_iter::ChunkIter;
-use crate::data_set_properties::{MIN_MEASUREMENT_LEN, MIN_STATION_LEN, STATIONS_IN_DATASET};
-use aggregated_data::AggregatedData;
-use gxhash::HashMap;
+mod aggregator;
+mod cardinality;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "arrow")]
+mod columnar;
+#[cfg(unix)]
+mod daemon;
+mod distributed;
+mod filter;
+mod generate;
+mod hash;
+mod metadata;
+mod metrics;
+mod output;
+mod perf_counters;
+mod progress;
+mod quality;
+mod quantiles;
+mod ranking;
+mod results;
+mod rng;
+mod sampling;
+mod stddev;
+mod units;
+mod windowed;
+
+pub use aggregator::Aggregator;
+pub use cardinality::{Cardinality, CardinalityReport, HyperLogLog, HYPERLOGLOG_PRECISION};
+#[cfg(feature = "arrow")]
+pub use columnar::{
+    aggregate_arrow_ipc, aggregate_parquet, InputFormat, STATION_COLUMN, TEMPERATURE_COLUMN,
+};
+#[cfg(unix)]
+pub use daemon::{control_request, Daemon, DaemonStats};
+pub use distributed::{
+    coordinate, serve_worker, CoordinatorError, CoordinatorOptions, Reply, Task, WorkerOptions,
+};
+pub use filter::StationFilter;
+pub use generate::generate;
+pub use metadata::{MetadataTable, RollupKey, StationMetadata};
+pub use metrics::{Phase, PhaseTiming, RunMetrics, ThreadMetrics};
+pub use output::{write_stations, OutputFormat, OutputOptions, StationRecord};
+pub use perf_counters::{perf_counters_available, CounterValues, PerfEvent};
+pub use phips_1brc_core::AggregatedData;
+pub use progress::Progress;
+pub use quality::{
+    IssueKind, IssueSummary, Position, QualityError, QualityOptions, QualityReport, MAX_STATION_LEN,
+};
+pub use quantiles::{QuantileSketch, Quantiles, DEFAULT_RELATIVE_ACCURACY};
+pub use ranking::{RankBy, Ranking, SortOrder, UnavailableStatistic};
+pub use results::Results;
+pub use sampling::{
+    SampleReport, SamplingFraction, SamplingOptions, StationEstimate, CONFIDENCE_LEVEL,
+};
+pub use stddev::{StationStats, StdDevData};
+pub use units::{EncodedDecimal, TemperatureUnit};
+pub use windowed::{BucketWidth, WindowedEntry, WindowedResults};
+
+use crate::hash::HashMap;
+use crate::perf_counters::PerfCounters;
+use crate::progress::ProgressCounters;
+use crate::quality::ErrorBudget;
 use memmap2::{Mmap, MmapOptions};
+use phips_1brc_core::data_set_properties::STATIONS_IN_DATASET;
+use phips_1brc_core::{process_line, ChunkIter};
+use std::collections::hash_map::Entry;
 use std::fs::File;
+use std::hash::Hash;
 use std::hint::black_box;
+use std::io;
+use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::path::Path;
-use std::str::from_utf8_unchecked;
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::thread::available_parallelism;
+use std::time::{Duration, Instant};
 use std::{iter, slice, thread};
 
-/// Some characteristics specifically to the [1BRC data set](https://github.com/gunnarmorling/1brc/blob/db064194be375edc02d6dbcd21268ad40f7e2869/src/main/java/dev/morling/onebrc/CreateMeasurements.java).
-mod data_set_properties {
-    /// The amount of distinct weather stations (cities).
-    pub const STATIONS_IN_DATASET: usize = 413;
-    /// The minimum station name length (for example: `Jos`).
-    pub const MIN_STATION_LEN: usize = 3;
-    /// The minimum measurement (str) len (for example: `6.6`).
-    pub const MIN_MEASUREMENT_LEN: usize = 3;
-}
-
 /// Processes all data according to the 1brc challenge by using a
 /// single-threaded implementation.
 pub fn process_single_threaded(path: impl AsRef<Path> + Clone, print: bool) {
@@ -64,41 +119,543 @@ pub fn process_single_threaded(path: impl AsRef<Path> + Clone, print: bool) {
 }
 
 /// Processes all data according to the 1brc challenge by using a
-/// multi-threaded implementation. This spawns `n-1` worker threads. The main
-/// thread also performs one workload and finally collects and combines all
-/// results.
+/// multi-threaded implementation.
+///
+/// This spawns `n-1` worker threads. The main thread also performs one
+/// workload and finally collects and combines all results.
 pub fn process_multi_threaded(path: impl AsRef<Path> + Clone, print: bool) {
     let (_mmap, bytes) = unsafe { open_file(path) };
 
-    let cpus = cpu_count(bytes.len());
+    let thread_results =
+        process_chunks_multi_threaded(bytes, cpu_count(bytes.len()), process_file_chunk);
 
-    let mut thread_handles = Vec::with_capacity(cpus);
+    finalize(thread_results.into_iter(), print);
+}
 
-    let mut iter = ChunkIter::new(bytes, cpus);
-    let main_thread_chunk = iter.next().unwrap();
+/// Like [`process_single_threaded`], but returns the [`Results`] instead of
+/// printing them. The optional [`StationFilter`] is applied while processing.
+pub fn aggregate_single_threaded(
+    path: impl AsRef<Path> + Clone,
+    filter: Option<&StationFilter>,
+) -> Results {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+
+    let stats = match filter {
+        None => process_file_chunk(bytes),
+        Some(filter) => filter::process_file_chunk(bytes, filter),
+    };
 
-    for chunk in iter {
-        // Spawning the threads is negligible cheap.
-        // TODO it surprises me that rustc won't force me to transmute `chunk`
-        //  to a &static lifetime.
-        let handle = thread::spawn(move || process_file_chunk(chunk));
-        thread_handles.push(handle);
+    Results::from_sorted(reduce(iter::once(stats)))
+}
+
+/// Like [`process_multi_threaded`], but returns the [`Results`] instead of
+/// printing them. The optional [`StationFilter`] is applied while processing.
+pub fn aggregate_multi_threaded(
+    path: impl AsRef<Path> + Clone,
+    filter: Option<&StationFilter>,
+) -> Results {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    aggregate_bytes(bytes, cpu_count(bytes.len()), filter)
+}
+
+/// Like [`aggregate_multi_threaded`], but with an explicit amount of threads
+/// (including the main thread).
+pub fn aggregate_with_threads(
+    path: impl AsRef<Path> + Clone,
+    threads: NonZeroUsize,
+    filter: Option<&StationFilter>,
+) -> Results {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    aggregate_bytes(bytes, threads.get(), filter)
+}
+
+/// Like [`aggregate_with_threads`], but for data that is already in memory.
+///
+/// With one thread, this neither memory-maps a file nor spawns threads. The
+/// bytes must be valid according to the rules of the challenge and end with a
+/// newline. See [`aggregate_bytes_validated`] for untrusted data.
+#[must_use]
+pub fn aggregate_buffer(
+    bytes: &[u8],
+    threads: NonZeroUsize,
+    filter: Option<&StationFilter>,
+) -> Results {
+    if bytes.is_empty() {
+        return Results::default();
     }
+    aggregate_bytes(bytes, threads.get(), filter)
+}
 
-    let stats = process_file_chunk(main_thread_chunk);
+fn aggregate_bytes(bytes: &[u8], threads: usize, filter: Option<&StationFilter>) -> Results {
+    let thread_results = match filter {
+        None => process_chunks_multi_threaded(bytes, threads, process_file_chunk),
+        Some(filter) => process_chunks_multi_threaded(bytes, threads, |chunk| {
+            filter::process_file_chunk(chunk, filter)
+        }),
+    };
 
-    debug_assert_eq!(
-        thread_handles.len(),
-        cpus - 1,
-        "must have 1-n worker threads"
-    );
+    Results::from_sorted(reduce(thread_results.into_iter()))
+}
+
+/// Aggregates the file with a custom [`Aggregator`] instead of
+/// [`AggregatedData`], with the given amount of threads.
+///
+/// Returns the finished result per station, sorted by station name.
+pub fn aggregate_custom<A: Aggregator>(
+    path: impl AsRef<Path> + Clone,
+    threads: NonZeroUsize,
+) -> Vec<(String, A::Output)> {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+
+    let thread_results =
+        process_chunks_multi_threaded(bytes, threads.get(), process_file_chunk::<A>);
 
-    let thread_results_iter = thread_handles
+    reduce(thread_results.into_iter())
         .into_iter()
-        .map(|handle| handle.join().unwrap())
-        .chain(core::iter::once(stats));
+        .map(|(station, data)| (station.to_string(), data.finish()))
+        .collect()
+}
+
+/// Like [`aggregate_with_threads`], but additionally measures the phases of
+/// the run and the work of each thread.
+///
+/// The hot loop is the same as without instrumentation. Phases that happen
+/// after this function returns, such as printing, can be added to the
+/// returned [`RunMetrics`] by the caller.
+///
+/// With `perf_counters`, each thread additionally records hardware
+/// performance counters around the processing of its chunk. Threads that
+/// can't open the counters report none; see [`perf_counters_available`].
+pub fn aggregate_instrumented(
+    path: impl AsRef<Path> + Clone,
+    threads: NonZeroUsize,
+    filter: Option<&StationFilter>,
+    perf_counters: bool,
+) -> (Results, RunMetrics) {
+    let mut metrics = RunMetrics::default();
+
+    let begin = Instant::now();
+    let (mmap, bytes) = unsafe { open_file(path) };
+    metrics.record(Phase::Mmap, begin.elapsed());
+    metrics.file_size = bytes.len();
+
+    let begin = Instant::now();
+    let chunks = ChunkIter::new(bytes, threads.get()).collect::<Vec<_>>();
+    metrics.record(Phase::ChunkSplit, begin.elapsed());
+
+    let begin = Instant::now();
+    let (thread_results, thread_metrics): (Vec<_>, Vec<_>) = process_chunks(chunks, |chunk| {
+        process_chunk_instrumented(chunk, filter, perf_counters)
+    })
+    .into_iter()
+    .unzip();
+    metrics.record(Phase::Processing, begin.elapsed());
+    metrics.threads = thread_metrics;
+
+    let begin = Instant::now();
+    let stats = merge(thread_results.into_iter());
+    metrics.record(Phase::Reduce, begin.elapsed());
+
+    let begin = Instant::now();
+    let results = Results::from_sorted(sort(stats));
+    metrics.record(Phase::Sort, begin.elapsed());
+
+    let begin = Instant::now();
+    drop(mmap);
+    metrics.record(Phase::Unmap, begin.elapsed());
+
+    (results, metrics)
+}
+
+/// Processes the chunk and measures the work of the thread.
+fn process_chunk_instrumented<'a>(
+    chunk: &'a [u8],
+    filter: Option<&StationFilter>,
+    perf_counters: bool,
+) -> (HashMap<&'a str, AggregatedData>, ThreadMetrics) {
+    // Opened before the measurement, as this takes a few syscalls.
+    let counters = perf_counters.then(PerfCounters::open).and_then(Result::ok);
+
+    let begin = Instant::now();
+    if let Some(counters) = &counters {
+        counters.enable();
+    }
+    let stats = match filter {
+        None => process_file_chunk(chunk),
+        Some(filter) => filter::process_file_chunk(chunk, filter),
+    };
+    let counters = counters.map(|counters| counters.disable_and_read());
+    let duration = begin.elapsed();
+
+    // Counting the lines here is cheap, compared to counting them in the hot
+    // loop.
+    let thread = ThreadMetrics {
+        bytes: chunk.len(),
+        lines: stats.values().map(|data| data.count() as u64).sum(),
+        duration,
+        counters,
+    };
+    (stats, thread)
+}
+
+/// Like [`aggregate_with_threads`], but reports the [`Progress`] every
+/// `interval` and once more when all chunks are processed.
+///
+/// In contrast to the other functions, all chunks are processed by worker
+/// threads. The main thread samples the progress counters of the workers and
+/// calls `on_progress`.
+pub fn aggregate_with_progress(
+    path: impl AsRef<Path> + Clone,
+    threads: NonZeroUsize,
+    filter: Option<&StationFilter>,
+    interval: Duration,
+    mut on_progress: impl FnMut(&Progress),
+) -> Results {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    let begin = Instant::now();
+    let chunks = ChunkIter::new(bytes, threads.get()).collect::<Vec<_>>();
+    let counters = ProgressCounters::new(chunks.len());
+    let progress = || Progress {
+        processed_bytes: counters.sum(),
+        total_bytes: bytes.len(),
+        elapsed: begin.elapsed(),
+    };
+
+    let thread_results = thread::scope(|scope| {
+        let (done_sender, done_receiver) = mpsc::channel();
+        let thread_handles = chunks
+            .iter()
+            .enumerate()
+            .map(|(thread, chunk)| {
+                let (counters, done_sender) = (&counters, done_sender.clone());
+                scope.spawn(move || {
+                    let stats = process_chunk_with_progress(chunk, filter, |processed_bytes| {
+                        counters.set(thread, processed_bytes);
+                    });
+                    // The receiver lives until all threads are joined.
+                    done_sender.send(()).unwrap();
+                    stats
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Only the workers hold a sender now. If all senders are dropped,
+        // all workers finished (or panicked).
+        drop(done_sender);
+        let mut running = thread_handles.len();
+        while running > 0 {
+            match done_receiver.recv_timeout(interval) {
+                Ok(()) => running -= 1,
+                Err(RecvTimeoutError::Timeout) => on_progress(&progress()),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        thread_handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    on_progress(&progress());
+
+    Results::from_sorted(reduce(thread_results.into_iter()))
+}
+
+/// Processes the chunk in blocks of [`progress::BLOCK_SIZE`] bytes and
+/// reports the processed bytes of the chunk after each block.
+fn process_chunk_with_progress<'a>(
+    chunk: &'a [u8],
+    filter: Option<&StationFilter>,
+    report: impl Fn(usize),
+) -> HashMap<&'a str, AggregatedData> {
+    let blocks = ChunkIter::new(chunk, chunk.len().div_ceil(progress::BLOCK_SIZE));
+    let mut processed_bytes = 0;
+    // Merging the per-block results is negligible compared to processing a
+    // block.
+    merge(blocks.map(|block| {
+        let stats = match filter {
+            None => process_file_chunk(block),
+            Some(filter) => filter::process_file_chunk(block, filter),
+        };
+        processed_bytes += block.len();
+        report(processed_bytes);
+        stats
+    }))
+}
+
+/// Counts the lines and the distinct stations of the file and of each chunk
+/// (shard), without aggregating the measurements.
+///
+/// The distinct stations are always estimated with a [`HyperLogLog`]. With
+/// `exact`, they are additionally counted exactly, which requires memory
+/// proportional to the amount of distinct stations.
+pub fn count_distinct(
+    path: impl AsRef<Path> + Clone,
+    threads: NonZeroUsize,
+    exact: bool,
+) -> CardinalityReport {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    let base = bytes.as_ptr() as usize;
+
+    let chunk_results = process_chunks_multi_threaded(bytes, threads.get(), |chunk| {
+        let start = chunk.as_ptr() as usize - base;
+        (
+            start..start + chunk.len(),
+            cardinality::process_file_chunk(chunk, exact),
+        )
+    });
+
+    cardinality::report(chunk_results)
+}
+
+/// Like [`aggregate_with_threads`], but validates each line and attaches a
+/// [`QualityReport`] to the [`Results`] (see [`Results::quality`]).
+///
+/// Invalid lines are skipped. If there are more invalid lines than
+/// [`QualityOptions::max_errors`], the run is aborted with a
+/// [`QualityError`]. In contrast to the other functions, the file may be empty
+/// or miss its final newline.
+pub fn aggregate_validated(
+    path: impl AsRef<Path> + Clone,
+    threads: NonZeroUsize,
+    filter: Option<&StationFilter>,
+    options: &QualityOptions,
+) -> Result<Results, QualityError> {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    aggregate_bytes_validated(bytes, threads, filter, options)
+}
+
+/// Like [`aggregate_validated`], but for data that is already in memory, such
+/// as an uploaded body. The positions in the [`QualityReport`] are relative to
+/// the beginning of the bytes.
+pub fn aggregate_bytes_validated(
+    bytes: &[u8],
+    threads: NonZeroUsize,
+    filter: Option<&StationFilter>,
+    options: &QualityOptions,
+) -> Result<Results, QualityError> {
+    let base = bytes.as_ptr() as usize;
+
+    // The chunking requires a final newline. A trailing incomplete line is
+    // processed as an extra chunk.
+    let complete_lines = memchr::memrchr(b'\n', bytes).map_or(0, |newline| newline + 1);
+    let (complete, incomplete) = bytes.split_at(complete_lines);
+    let chunks = ChunkIter::new(complete, threads.get())
+        .chain((!incomplete.is_empty()).then_some(incomplete))
+        .collect::<Vec<_>>();
+    if chunks.is_empty() {
+        return Ok(Results::default().with_quality(QualityReport::default()));
+    }
+
+    let budget = ErrorBudget::default();
+    let chunk_results = process_chunks(chunks.iter().copied(), |chunk| {
+        let offset = chunk.as_ptr() as usize - base;
+        let previous_line = quality::line_before(bytes, offset);
+        quality::process_file_chunk(chunk, offset, previous_line, filter, options, &budget)
+    });
+
+    let mut report = QualityReport::default();
+    let mut first_line = 0;
+    let mut thread_results = Vec::with_capacity(chunk_results.len());
+    for (chunk, (stats, chunk_report)) in chunks.iter().zip(chunk_results) {
+        // After an abort, the chunks weren't processed completely, but the
+        // positions of the following chunks must refer to the lines of the
+        // file.
+        let chunk_lines = if budget.exceeded() {
+            quality::count_lines(chunk)
+        } else {
+            chunk_report.lines
+        };
+        report.append(chunk_report, first_line, options.max_samples);
+        first_line += chunk_lines;
+        thread_results.push(stats);
+    }
+    if budget.exceeded() {
+        return Err(QualityError {
+            max_errors: options.max_errors.unwrap_or_default(),
+            report,
+        });
+    }
+
+    Ok(Results::from_sorted(reduce(thread_results.into_iter())).with_quality(report))
+}
+
+/// Processes a random sample of the file instead of all of it, for a quick
+/// approximate answer. See [`SamplingOptions`] and [`SampleReport`].
+///
+/// The sampled blocks are distributed evenly across the threads.
+pub fn aggregate_sampled(
+    path: impl AsRef<Path> + Clone,
+    threads: NonZeroUsize,
+    options: &SamplingOptions,
+) -> SampleReport {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+
+    let blocks = sampling::select_blocks(bytes, options);
+    let sampled_bytes = blocks.iter().map(Range::len).sum();
+    if blocks.is_empty() {
+        return SampleReport::new(&[], 0, bytes.len(), 0);
+    }
+
+    let blocks_per_thread = blocks.len().div_ceil(threads.get());
+    let thread_results = process_chunks(blocks.chunks(blocks_per_thread), |blocks| {
+        merge(
+            blocks
+                .iter()
+                .map(|block| process_file_chunk::<StdDevData>(&bytes[block.clone()])),
+        )
+    });
+    let stations = reduce(thread_results.into_iter());
+
+    SampleReport::new(&stations, sampled_bytes, bytes.len(), blocks.len())
+}
+
+/// Aggregates only the lines of the file that begin within the byte range
+/// `[start, end)`, for processing parts of a shared file in separate
+/// processes or on separate machines.
+///
+/// Both ends of the range are snapped to line boundaries with the rule of the
+/// regular chunking: a line belongs to the range that contains its first
+/// byte. So, the results of adjacent ranges that cover the file can be merged
+/// with [`Results::merge`] into the result of the whole file. The range may
+/// exceed the file; lines beyond the end of the file don't exist.
+///
+/// The file must end with a newline.
+pub fn aggregate_range(
+    path: impl AsRef<Path> + Clone,
+    range: Range<usize>,
+    threads: NonZeroUsize,
+    filter: Option<&StationFilter>,
+) -> Results {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    let range = phips_1brc_core::snap_to_lines(bytes, range);
+    if range.is_empty() {
+        return Results::default();
+    }
+    aggregate_bytes(&bytes[range], threads.get(), filter)
+}
+
+/// Like [`aggregate_range`], but validates each line like
+/// [`aggregate_validated`].
+///
+/// The positions in the [`QualityReport`] are relative to the beginning of
+/// the snapped range. The file may miss its final newline.
+pub fn aggregate_range_validated(
+    path: impl AsRef<Path> + Clone,
+    range: Range<usize>,
+    threads: NonZeroUsize,
+    filter: Option<&StationFilter>,
+    options: &QualityOptions,
+) -> Result<Results, QualityError> {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    let range = phips_1brc_core::snap_to_lines(bytes, range);
+    if range.is_empty() {
+        // Still with an (empty) quality report, like for other ranges.
+        return aggregate_bytes_validated(&[], threads, filter, options);
+    }
+    aggregate_bytes_validated(&bytes[range], threads, filter, options)
+}
+
+/// Returns the byte ranges of the line-aligned chunks the file is split into,
+/// if it is processed with the given amount of threads.
+pub fn chunk_boundaries(path: impl AsRef<Path>, threads: NonZeroUsize) -> Vec<Range<usize>> {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    let base = bytes.as_ptr() as usize;
+    ChunkIter::new(bytes, threads.get())
+        .map(|chunk| {
+            let start = chunk.as_ptr() as usize - base;
+            start..start + chunk.len()
+        })
+        .collect()
+}
+
+/// Aggregates per station and time bucket, with the given amount of threads.
+///
+/// Each line must carry an additional timestamp column
+/// (`<station>;<measurement>;<timestamp>`). The per-thread results are merged
+/// like in [`aggregate_with_threads`], and the results are in time order.
+pub fn aggregate_windowed(
+    path: impl AsRef<Path> + Clone,
+    threads: NonZeroUsize,
+    width: BucketWidth,
+) -> WindowedResults {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    aggregate_windowed_bytes(bytes, threads.get(), width)
+}
+
+fn aggregate_windowed_bytes(bytes: &[u8], threads: usize, width: BucketWidth) -> WindowedResults {
+    let thread_results = process_chunks_multi_threaded(bytes, threads, |chunk| {
+        windowed::process_file_chunk(chunk, width)
+    });
+
+    WindowedResults::from_sorted(windowed::reduce(thread_results.into_iter()))
+}
+
+/// Like [`process_single_threaded`], but aggregates per station and time
+/// bucket. See [`aggregate_windowed`].
+pub fn process_windowed_single_threaded(
+    path: impl AsRef<Path> + Clone,
+    width: BucketWidth,
+    print: bool,
+) {
+    let results = aggregate_windowed(path, NonZeroUsize::MIN, width);
+    finalize_windowed(&results, print);
+}
+
+/// Multi-threaded variant of [`process_windowed_single_threaded`].
+pub fn process_windowed_multi_threaded(
+    path: impl AsRef<Path> + Clone,
+    width: BucketWidth,
+    print: bool,
+) {
+    let (_mmap, bytes) = unsafe { open_file(path) };
+    let results = aggregate_windowed_bytes(bytes, cpu_count(bytes.len()), width);
+    finalize_windowed(&results, print);
+}
+
+/// Splits the bytes into one line-aligned chunk per thread and processes them
+/// in parallel. The main thread processes the first chunk itself. The results
+/// are returned in the order of the chunks.
+fn process_chunks_multi_threaded<'a, T: Send>(
+    bytes: &'a [u8],
+    cpus: usize,
+    process_chunk: impl Fn(&'a [u8]) -> T + Sync,
+) -> Vec<T> {
+    let results = process_chunks(ChunkIter::new(bytes, cpus), process_chunk);
+    debug_assert!(
+        results.len() <= cpus,
+        "must have at most n-1 worker threads"
+    );
+    results
+}
+
+/// Processes each chunk on its own thread. The main thread processes the
+/// first chunk itself. The results are returned in the order of the chunks.
+fn process_chunks<C: Send, T: Send>(
+    chunks: impl IntoIterator<Item = C>,
+    process_chunk: impl Fn(C) -> T + Sync,
+) -> Vec<T> {
+    let mut iter = chunks.into_iter();
+    let main_thread_chunk = iter.next().unwrap();
+
+    thread::scope(|scope| {
+        let process_chunk = &process_chunk;
+        let mut thread_handles = Vec::new();
+        for chunk in iter {
+            // Spawning the threads is negligible cheap.
+            let handle = scope.spawn(move || process_chunk(chunk));
+            thread_handles.push(handle);
+        }
 
-    finalize(thread_results_iter, print);
+        let stats = process_chunk(main_thread_chunk);
+
+        iter::once(stats)
+            .chain(
+                thread_handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap()),
+            )
+            .collect()
+    })
 }
 
 /// Opens the file by mapping it via mmap into the address space of the program.
@@ -122,7 +679,7 @@ unsafe fn open_file<'a>(path: impl AsRef<Path>) -> (Mmap, &'a [u8]) {
 /// unnecessary comparisons, no not-inlined function calls.
 ///
 /// The returned data structure is not sorted.
-fn process_file_chunk(bytes: &[u8]) -> HashMap<&str, AggregatedData> {
+fn process_file_chunk<A: Aggregator>(bytes: &[u8]) -> HashMap<&str, A> {
     assert!(!bytes.is_empty());
     let &last_byte = bytes.last().unwrap();
     assert_eq!(last_byte, b'\n');
@@ -132,43 +689,16 @@ fn process_file_chunk(bytes: &[u8]) -> HashMap<&str, AggregatedData> {
     let mut consumed_bytes_count = 0;
     while consumed_bytes_count < bytes.len() {
         let remaining_bytes = &unsafe { bytes.get_unchecked(consumed_bytes_count..) };
-        let (station, measurement) = process_line(remaining_bytes, &mut consumed_bytes_count);
+        let (station, measurement) =
+            unsafe { process_line(remaining_bytes, &mut consumed_bytes_count) };
         insert_measurement(&mut stats, station, measurement);
     }
     stats
 }
 
-/// Reads a line from the bytes and processes it. This expects that `bytes[0]`
-/// is the beginning of a new line. It returns the processed data and updates
-/// the `consumed_bytes_count` so that the next iteration can begin at the
-/// beginning of a new line.
 #[inline(always)]
-fn process_line<'a>(bytes: &'a [u8], consumed_bytes_count: &mut usize) -> (&'a str, i16) {
-    // Look for ";", and skip irrelevant bytes beforehand.
-    let search_offset = MIN_STATION_LEN;
-    let delimiter = memchr::memchr(b';', &unsafe { bytes.get_unchecked(search_offset..) })
-        .map(|pos| pos + search_offset)
-        .unwrap();
-    // Look for "\n", and skip irrelevant bytes beforehand.
-    let search_offset = delimiter + 1 + MIN_MEASUREMENT_LEN;
-    let newline = memchr::memchr(b'\n', &unsafe { bytes.get_unchecked(search_offset..) })
-        .map(|pos| pos + search_offset)
-        .unwrap();
-
-    let station = unsafe { from_utf8_unchecked(&bytes.get_unchecked(0..delimiter)) };
-    let measurement = unsafe { from_utf8_unchecked(&bytes.get_unchecked(delimiter + 1..newline)) };
-
-    let measurement = fast_f32_parse_encoded(measurement);
-
-    // Ensure the next iteration works on the next line.
-    *consumed_bytes_count += newline + 1;
-
-    (station, measurement)
-}
-
-#[inline(always)]
-fn insert_measurement<'a>(
-    stats: &mut HashMap<&'a str, AggregatedData>,
+fn insert_measurement<'a, A: Aggregator>(
+    stats: &mut HashMap<&'a str, A>,
     station: &'a str,
     measurement: i16,
 ) {
@@ -176,10 +706,10 @@ fn insert_measurement<'a>(
     // most of the time, we take the `and_modify` branch.
     stats
         .entry(station)
-        .and_modify(|data: &mut AggregatedData| data.add_datapoint(measurement))
+        .and_modify(|data: &mut A| data.add(measurement))
         .or_insert_with(|| {
-            let mut data = AggregatedData::default();
-            data.add_datapoint(measurement);
+            let mut data = A::default();
+            data.add(measurement);
             data
         });
 }
@@ -192,94 +722,65 @@ fn cpu_count(size: usize) -> usize {
     }
 }
 
-/// Optimized fast decimal number parsing that encodes a float in an integer,
-/// which is multiplied by 10.
-///
-/// This benefits from the fact that we know that all input data has exactly 1
-/// decimal place.
-///
-/// - `15.5` -> `155`
-/// - `-7.1` -> `-71`
-///
-/// The range of possible values is within `-99.9..=99.9`.
-///
-/// To get back to the actual floating point value, one has to convert the value
-/// to float and divide it by 10.
-fn fast_f32_parse_encoded(input: &str) -> i16 {
-    let mut bytes = input.as_bytes();
-
-    let negative = unsafe { *bytes.get_unchecked(0) } == b'-';
-
-    if negative {
-        // Only parse digits.
-        bytes = &unsafe { bytes.get_unchecked(1..) };
-    }
+/// Aggregates the results and, optionally, prints them.
+fn finalize<'a>(stats: impl Iterator<Item = HashMap<&'a str, AggregatedData>>, print: bool) {
+    let stats = reduce(stats);
 
-    let mut val = 0;
-    for &byte in bytes {
-        if byte == b'.' {
-            continue;
-        }
-        let digit = (byte - b'0') as i16;
-        val = val * 10 + digit;
+    if print {
+        output::write_text(
+            &mut io::stdout().lock(),
+            stats.iter().map(|(station, data)| (*station, data)),
+            TemperatureUnit::Celsius,
+        )
+        .unwrap();
+    } else {
+        // black-box: prevent the compiler from optimizing any calculations away
+        let _x = black_box(stats);
     }
+}
 
-    if negative {
-        -val
+/// Windowed counterpart of [`finalize`].
+fn finalize_windowed(results: &WindowedResults, print: bool) {
+    if print {
+        results.print(OutputFormat::Text, TemperatureUnit::Celsius);
     } else {
-        val
+        let _x = black_box(results);
     }
 }
 
-/// Aggregates the results and, optionally, prints them.
-fn finalize<'a>(stats: impl Iterator<Item = HashMap<&'a str, AggregatedData>>, print: bool) {
+/// Merges the per-thread results and sorts them by station name.
+fn reduce<'a, A: Aggregator>(
+    stats: impl Iterator<Item = HashMap<&'a str, A>>,
+) -> Vec<(&'a str, A)> {
+    sort(merge(stats))
+}
+
+/// Merges the per-thread results into one map. The key is usually the
+/// station, but can be anything, such as `(station, bucket)`.
+fn merge<K: Eq + Hash, A: Aggregator>(stats: impl Iterator<Item = HashMap<K, A>>) -> HashMap<K, A> {
     // This reduce step is surprisingly negligible cheap.
-    let stats = stats
+    stats
         .reduce(|mut acc, next| {
-            next.into_iter().for_each(|(station, new_data)| {
-                acc.entry(station)
-                    .and_modify(|data| {
-                        data.merge(&new_data);
-                    })
-                    .or_insert(new_data);
-            });
+            next.into_iter()
+                .for_each(|(key, new_data)| match acc.entry(key) {
+                    Entry::Occupied(mut entry) => entry.get_mut().merge(new_data),
+                    Entry::Vacant(entry) => {
+                        entry.insert(new_data);
+                    }
+                });
             acc
         })
-        .unwrap();
+        .unwrap()
+}
 
+/// Sorts the stations by name.
+fn sort<A>(stats: HashMap<&str, A>) -> Vec<(&str, A)> {
     // Sort everything into a vector. The costs of this are negligible cheap.
     let mut stats = stats.into_iter().collect::<Vec<_>>();
     stats.sort_unstable_by(|(station_a, _), (station_b, _)| {
         station_a.partial_cmp(station_b).unwrap()
     });
-
-    if print {
-        print_results(stats.into_iter())
-    } else {
-        // black-box: prevent the compiler from optimizing any calculations away
-        let _x = black_box(stats);
-    }
-}
-
-/// Prints the results. The costs of this function are negligible cheap.
-fn print_results<'a>(stats: impl ExactSizeIterator<Item = (&'a str, AggregatedData)>) {
-    print!("{{");
-    let n = stats.len();
     stats
-        .enumerate()
-        .map(|(index, x)| (index == n - 1, x))
-        .for_each(|(is_last, (city, measurements))| {
-            print!(
-                "{city}={:.1}/{:.1}/{:.1}",
-                measurements.min(),
-                measurements.avg(),
-                measurements.max()
-            );
-            if !is_last {
-                print!(", ");
-            }
-        });
-    println!("}}");
 }
 
 #[cfg(test)]
@@ -289,13 +790,13 @@ mod tests {
     #[test]
     fn test_process_file_chunk() {
         let input = "Berlin;10.0\nHamburg;-12.7\nNew York;21.5\nBerlin;-15.7\n";
-        let actual = process_file_chunk(input.as_bytes());
-        let stats = actual.into_iter().collect::<Vec<_>>();
+        let actual = process_file_chunk::<AggregatedData>(input.as_bytes());
+        let mut stats = actual.into_iter().collect::<Vec<_>>();
+        // The order of the HashMap is not stable across hasher versions.
+        stats.sort_unstable_by_key(|(station, _)| *station);
 
-        // Order here is not relevant. I stick to the order from the HashMap
-        // implementation.
-        let hamburg = &stats[0];
-        let berlin = &stats[1];
+        let berlin = &stats[0];
+        let hamburg = &stats[1];
         let new_york = &stats[2];
 
         assert_eq!(hamburg.0, "Hamburg");
@@ -316,11 +817,135 @@ mod tests {
     }
 
     #[test]
-    fn test_fast_f32_parse() {
-        assert_eq!(fast_f32_parse_encoded("0.0"), 00);
-        assert_eq!(fast_f32_parse_encoded("5.0"), 50);
-        assert_eq!(fast_f32_parse_encoded("5.7"), 57);
-        assert_eq!(fast_f32_parse_encoded("-5.7"), -57);
-        assert_eq!(fast_f32_parse_encoded("-99.9"), -999);
+    fn test_aggregate_validated_abort_positions() {
+        // Every 7th line is invalid, across many chunks.
+        let input = (0..1000)
+            .map(|i| {
+                if i % 7 == 3 {
+                    "invalid\n".to_string()
+                } else {
+                    format!("Station{};{}.{}\n", i % 10, i % 50, i % 10)
+                }
+            })
+            .collect::<String>();
+        let bytes = input.as_bytes();
+        let options = QualityOptions {
+            max_errors: Some(5),
+            max_samples: 1000,
+        };
+        let threads = NonZeroUsize::new(8).unwrap();
+        let err = aggregate_bytes_validated(bytes, threads, None, &options).unwrap_err();
+
+        // Regardless of where each thread stopped, the positions match.
+        let malformed = &err.report.issues[&IssueKind::MalformedLine];
+        assert!(malformed.count > 5);
+        for position in &malformed.samples {
+            let line = bytes[..position.offset]
+                .iter()
+                .filter(|&&byte| byte == b'\n')
+                .count() as u64
+                + 1;
+            assert_eq!(position.line, line, "{position:?}");
+            assert!(bytes[position.offset..].starts_with(b"invalid\n"));
+        }
+    }
+
+    #[test]
+    fn test_aggregate_with_progress() {
+        let path = "measurements_10000.txt";
+        let threads = NonZeroUsize::new(3).unwrap();
+        let mut reports = Vec::new();
+        let results =
+            aggregate_with_progress(path, threads, None, Duration::from_secs(60), |progress| {
+                reports.push(*progress);
+            });
+
+        assert_eq!(results, aggregate_with_threads(path, threads, None));
+        let last = reports.last().unwrap();
+        assert_eq!(last.processed_bytes, last.total_bytes);
+        assert_eq!(last.eta(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_aggregate_range() {
+        let path = "measurements_10000.txt";
+        let threads = NonZeroUsize::new(2).unwrap();
+        let size = std::fs::metadata(path).unwrap().len() as usize;
+
+        // Arbitrary boundaries, mostly in the middle of lines.
+        let boundaries = [0, 1, 1000, 1001, 54321, size - 1, size + 100];
+        let mut merged = Results::default();
+        for range in boundaries.windows(2) {
+            merged.merge(aggregate_range(path, range[0]..range[1], threads, None));
+        }
+        assert_eq!(merged, aggregate_with_threads(path, threads, None));
+
+        // No line begins within.
+        assert_eq!(
+            aggregate_range(path, 1..2, threads, None),
+            Results::default()
+        );
+        assert_eq!(
+            aggregate_range(path, size..size + 10, threads, None),
+            Results::default()
+        );
+        let reversed = Range { start: 10, end: 5 };
+        assert_eq!(
+            aggregate_range(path, reversed.clone(), threads, None),
+            Results::default()
+        );
+        let results =
+            aggregate_range_validated(path, reversed, threads, None, &QualityOptions::default())
+                .unwrap();
+        assert!(results.stations().is_empty());
+        assert_eq!(results.quality().unwrap().lines, 0);
+    }
+
+    #[test]
+    fn test_aggregate_sampled() {
+        let path = "measurements_10000.txt";
+        let threads = NonZeroUsize::new(3).unwrap();
+        let exact = aggregate_with_threads(path, threads, None);
+
+        let options = SamplingOptions {
+            fraction: SamplingFraction::new(1.0).unwrap(),
+            block_size: 4096,
+            ..SamplingOptions::default()
+        };
+        let report = aggregate_sampled(path, threads, &options);
+        assert_eq!(report.fraction, 1.0);
+        assert_eq!(report.stations.len(), exact.stations().len());
+        for (estimate, (station, data)) in report.stations.iter().zip(exact.stations()) {
+            assert_eq!(&estimate.station, station);
+            assert_eq!(estimate.sampled, data.count());
+            // Everything was sampled: no uncertainty left, if the spread is
+            // known.
+            let margin = (data.count() > 1).then_some(0.0);
+            assert_eq!(estimate.margin, margin);
+        }
+
+        let options = SamplingOptions {
+            fraction: SamplingFraction::new(0.25).unwrap(),
+            ..options
+        };
+        let report = aggregate_sampled(path, threads, &options);
+        assert!(report.fraction >= 0.25 && report.fraction < 0.3);
+        let sampled = report.stations.iter().map(|e| e.sampled).sum::<u32>();
+        assert!((2000..3000).contains(&sampled), "{sampled}");
+        // Roughly 95% of the intervals must contain the true mean.
+        let intervals = report
+            .stations
+            .iter()
+            .filter_map(|estimate| Some((&estimate.station, estimate.interval()?)))
+            .collect::<Vec<_>>();
+        let hits = intervals
+            .iter()
+            .filter(|(station, interval)| {
+                let mean = exact.get(station).unwrap().avg() as f64;
+                interval.contains(&mean)
+            })
+            .count();
+        let total = intervals.len();
+        assert!(hits as f64 >= 0.85 * total as f64, "{hits}/{total}");
     }
 }
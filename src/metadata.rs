@@ -0,0 +1,308 @@
+//! Station metadata (country, coordinates, region) that can be joined with
+//! the aggregated results, for example to produce rollups per country.
+
+use crate::hash::HashMap;
+use crate::results::Results;
+use crate::AggregatedData;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Mean earth radius in kilometers.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Metadata of a single weather station.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationMetadata {
+    pub country: String,
+    /// In degrees.
+    pub latitude: f64,
+    /// In degrees.
+    pub longitude: f64,
+    pub region: Option<String>,
+}
+
+impl StationMetadata {
+    /// The great-circle distance to the given coordinates in kilometers.
+    #[must_use]
+    pub fn distance_km(&self, latitude: f64, longitude: f64) -> f64 {
+        let (lat_a, lat_b) = (self.latitude.to_radians(), latitude.to_radians());
+        let delta_lat = lat_b - lat_a;
+        let delta_lon = (longitude - self.longitude).to_radians();
+        // Haversine formula
+        let a = (delta_lat / 2.0).sin().powi(2)
+            + lat_a.cos() * lat_b.cos() * (delta_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// The attribute by which stations are grouped in a rollup.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RollupKey {
+    Country,
+    Region,
+}
+
+impl FromStr for RollupKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "country" => Ok(Self::Country),
+            "region" => Ok(Self::Region),
+            _ => Err(format!(
+                "unknown rollup key `{s}`, expected one of: country, region"
+            )),
+        }
+    }
+}
+
+/// A table of station metadata, keyed by station name.
+#[derive(Debug, Default, Clone)]
+pub struct MetadataTable {
+    stations: HashMap<String, StationMetadata>,
+}
+
+impl MetadataTable {
+    /// Loads the table from a file. See [`Self::parse`] for the format.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses the table. This is a format of its own; the upstream
+    /// `weather_stations.csv` lacks the country and the region.
+    ///
+    /// Each line has the form
+    /// `<station>;<latitude>;<longitude>;<country>[;<region>]`:
+    /// - `station` is the exact name of the measurements file (not trimmed),
+    ///   and may appear only once
+    /// - `latitude` and `longitude` are decimal degrees within `-90..=90` and
+    ///   `-180..=180` (surrounding whitespace is allowed)
+    /// - `country` is required and `region` is optional; both must not be
+    ///   empty if present
+    ///
+    /// Line endings may be `\n` or `\r\n`. Empty lines and lines starting with
+    /// `#` are ignored. Any other line that doesn't match the format is an
+    /// error of kind [`io::ErrorKind::InvalidData`] with its line number.
+    pub fn parse(input: &str) -> io::Result<Self> {
+        let mut stations = HashMap::<String, StationMetadata>::default();
+        for (index, line) in input.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid_line = |reason: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {reason}: {line}", index + 1),
+                )
+            };
+            let parse_degrees = |value: Option<&str>, limit: f64| {
+                value
+                    .and_then(|value| value.trim().parse::<f64>().ok())
+                    .filter(|value| value.abs() <= limit)
+                    .ok_or_else(|| invalid_line("invalid coordinate"))
+            };
+
+            let mut columns = line.split(';');
+            let station = columns.next().unwrap_or_default();
+            let latitude = parse_degrees(columns.next(), 90.0)?;
+            let longitude = parse_degrees(columns.next(), 180.0)?;
+            let country = columns
+                .next()
+                .filter(|country| !country.is_empty())
+                .ok_or_else(|| invalid_line("missing country"))?;
+            let region = columns
+                .next()
+                .filter(|region| !region.is_empty())
+                .map(str::to_string);
+            if columns.next().is_some() {
+                return Err(invalid_line("too many columns"));
+            }
+
+            let metadata = StationMetadata {
+                country: country.to_string(),
+                latitude,
+                longitude,
+                region,
+            };
+            if stations.insert(station.to_string(), metadata).is_some() {
+                return Err(invalid_line("duplicate station"));
+            }
+        }
+        Ok(Self { stations })
+    }
+
+    #[must_use]
+    pub fn get(&self, station: &str) -> Option<&StationMetadata> {
+        self.stations.get(station)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    /// Returns the station closest to the given coordinates (in degrees),
+    /// together with its distance in kilometers.
+    #[must_use]
+    pub fn nearest(&self, latitude: f64, longitude: f64) -> Option<(&str, &StationMetadata, f64)> {
+        self.stations
+            .iter()
+            .map(|(station, metadata)| {
+                let distance = metadata.distance_km(latitude, longitude);
+                (station.as_str(), metadata, distance)
+            })
+            .min_by(|(station_a, _, a), (station_b, _, b)| {
+                a.total_cmp(b).then_with(|| station_a.cmp(station_b))
+            })
+    }
+
+    /// Merges the per-station results into one entry per country or region.
+    ///
+    /// Stations without metadata (or without a region, if grouped by region)
+    /// are skipped; use [`Self::unmatched`] to find them.
+    #[must_use]
+    pub fn rollup(&self, results: &Results, key: RollupKey) -> Results {
+        let mut groups: HashMap<&str, AggregatedData> = HashMap::default();
+        results
+            .stations()
+            .iter()
+            .filter_map(|(station, data)| {
+                let metadata = self.get(station)?;
+                let group = match key {
+                    RollupKey::Country => Some(metadata.country.as_str()),
+                    RollupKey::Region => metadata.region.as_deref(),
+                };
+                Some((group?, data))
+            })
+            .for_each(|(group, data)| {
+                groups
+                    .entry(group)
+                    .and_modify(|acc| acc.merge(data))
+                    .or_insert_with(|| data.clone());
+            });
+
+        let mut groups = groups.into_iter().collect::<Vec<_>>();
+        groups.sort_unstable_by_key(|(group, _)| *group);
+        Results::from_sorted(groups)
+    }
+
+    /// Returns all stations of the results without metadata.
+    #[must_use]
+    pub fn unmatched<'a>(&self, results: &'a Results) -> Vec<&'a str> {
+        results
+            .stations()
+            .iter()
+            .map(|(station, _)| station.as_str())
+            .filter(|station| self.get(station).is_none())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "# station;lat;lon;country;region\n\
+                         Berlin;52.5200;13.4050;Germany;Europe\n\
+                         Hamburg;53.5511;9.9937;Germany;Europe\n\
+                         New York;40.7128;-74.0060;United States;North America\n\
+                         \n\
+                         Oslo;59.9139;10.7522;Norway\n";
+
+    fn data(measurements: &[i16]) -> AggregatedData {
+        let mut data = AggregatedData::default();
+        measurements
+            .iter()
+            .for_each(|&measurement| data.add_datapoint(measurement));
+        data
+    }
+
+    fn results() -> Results {
+        Results::from_sorted([
+            ("Berlin", data(&[100, -157])),
+            ("Hamburg", data(&[-127])),
+            ("Lima", data(&[200])),
+            ("New York", data(&[215])),
+            ("Oslo", data(&[-50])),
+        ])
+    }
+
+    #[test]
+    fn test_parse() {
+        let table = MetadataTable::parse(TABLE).unwrap();
+        assert_eq!(table.len(), 4);
+        assert_eq!(
+            table.get("Oslo"),
+            Some(&StationMetadata {
+                country: "Norway".to_string(),
+                latitude: 59.9139,
+                longitude: 10.7522,
+                region: None,
+            })
+        );
+
+        assert!(MetadataTable::parse("Berlin;52.5;13.4\n").is_err());
+        assert!(MetadataTable::parse("Berlin;152.5;13.4;Germany\n").is_err());
+        assert!(MetadataTable::parse("Berlin;52.5;x;Germany\n").is_err());
+        assert!(MetadataTable::parse("Berlin;52.5;13.4;Germany;Europe;x\n").is_err());
+
+        let err = MetadataTable::parse("Berlin;52.5;13.4;Germany\nBerlin;52.5;13.4;France\n")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "line 2: duplicate station: Berlin;52.5;13.4;France"
+        );
+    }
+
+    #[test]
+    fn test_rollup() {
+        let table = MetadataTable::parse(TABLE).unwrap();
+        let results = results();
+
+        let by_country = table.rollup(&results, RollupKey::Country);
+        assert_eq!(
+            by_country.stations(),
+            [
+                ("Germany".to_string(), data(&[100, -157, -127])),
+                ("Norway".to_string(), data(&[-50])),
+                ("United States".to_string(), data(&[215])),
+            ]
+        );
+
+        let by_region = table.rollup(&results, RollupKey::Region);
+        assert_eq!(
+            by_region.stations(),
+            [
+                ("Europe".to_string(), data(&[100, -157, -127])),
+                ("North America".to_string(), data(&[215])),
+            ]
+        );
+
+        assert_eq!(table.unmatched(&results), ["Lima"]);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let table = MetadataTable::parse(TABLE).unwrap();
+
+        // Potsdam
+        let (station, _, distance) = table.nearest(52.3906, 13.0645).unwrap();
+        assert_eq!(station, "Berlin");
+        assert!((25.0..30.0).contains(&distance), "{distance}");
+
+        // Boston
+        let (station, _, _) = table.nearest(42.3601, -71.0589).unwrap();
+        assert_eq!(station, "New York");
+
+        assert!(MetadataTable::default().nearest(0.0, 0.0).is_none());
+    }
+}
@@ -0,0 +1,325 @@
+//! Instrumentation of a run: how long each phase takes and how the work is
+//! distributed across the threads.
+//!
+//! The measurements are taken around the phases, never inside the hot loop.
+//! The per-thread line counts are derived from the aggregated data after a
+//! chunk was processed. Runs without instrumentation don't pay anything.
+
+use crate::output::OutputFormat;
+use crate::perf_counters::{CounterValues, PerfEvent};
+use serde::{Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// A phase of a run, in the order of execution.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Opening and memory-mapping the file.
+    Mmap,
+    /// Splitting the file into line-aligned chunks.
+    ChunkSplit,
+    /// Processing the chunks on all threads, including spawning and joining
+    /// the threads.
+    Processing,
+    /// Merging the per-thread results.
+    Reduce,
+    /// Sorting the stations by name.
+    Sort,
+    /// Unmapping the file.
+    Unmap,
+    /// Writing the output.
+    Print,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Mmap => "mmap",
+            Self::ChunkSplit => "chunk split",
+            Self::Processing => "processing",
+            Self::Reduce => "reduce",
+            Self::Sort => "sort",
+            Self::Unmap => "unmap",
+            Self::Print => "print",
+        };
+        // Supports padding, such as `{:<12}`.
+        f.pad(name)
+    }
+}
+
+/// The duration of a single [`Phase`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+    #[serde(rename = "seconds", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+/// The work of a single thread. Thread `#0` is the main thread.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct ThreadMetrics {
+    /// The size of the processed chunk.
+    pub bytes: usize,
+    /// The amount of aggregated lines. Lines rejected by a filter are not
+    /// included.
+    pub lines: u64,
+    #[serde(rename = "seconds", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    /// Hardware performance counters around the processing of the chunk, if
+    /// requested and available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counters: Option<CounterValues>,
+}
+
+impl ThreadMetrics {
+    #[must_use]
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.duration.as_secs_f64()
+    }
+}
+
+/// The metrics report of a run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct RunMetrics {
+    pub file_size: usize,
+    pub phases: Vec<PhaseTiming>,
+    pub threads: Vec<ThreadMetrics>,
+}
+
+impl RunMetrics {
+    /// Adds the duration of a phase. Phases that happen outside the library,
+    /// such as [`Phase::Print`], are recorded by the caller.
+    pub fn record(&mut self, phase: Phase, duration: Duration) {
+        self.phases.push(PhaseTiming { phase, duration });
+    }
+
+    /// The sum of all recorded phases.
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|timing| timing.duration).sum()
+    }
+
+    /// The sum of the lines of all threads.
+    #[must_use]
+    pub fn lines(&self) -> u64 {
+        self.threads.iter().map(|thread| thread.lines).sum()
+    }
+
+    /// The sum of the performance counters of all threads. `None` if any
+    /// thread has no counters.
+    #[must_use]
+    pub fn counters(&self) -> Option<CounterValues> {
+        self.threads
+            .iter()
+            .map(|thread| thread.counters)
+            .reduce(|acc, next| Some(acc?.sum(&next?)))
+            .flatten()
+    }
+
+    /// The throughput of the whole run, based on [`Self::total`].
+    #[must_use]
+    pub fn bytes_per_second(&self) -> f64 {
+        self.file_size as f64 / self.total().as_secs_f64()
+    }
+
+    /// Writes the report in the given format.
+    pub fn write(&self, writer: &mut impl Write, format: OutputFormat) -> io::Result<()> {
+        match format {
+            OutputFormat::Text => self.write_text(writer),
+            OutputFormat::Json => {
+                #[derive(Serialize)]
+                struct Report<'a> {
+                    #[serde(flatten)]
+                    metrics: &'a RunMetrics,
+                    lines: u64,
+                    #[serde(serialize_with = "serialize_secs")]
+                    total_seconds: Duration,
+                    bytes_per_second: f64,
+                    #[serde(skip_serializing_if = "Option::is_none")]
+                    counters: Option<CounterValues>,
+                }
+                let report = Report {
+                    metrics: self,
+                    lines: self.lines(),
+                    total_seconds: self.total(),
+                    bytes_per_second: self.bytes_per_second(),
+                    counters: self.counters(),
+                };
+                serde_json::to_writer(&mut *writer, &report)?;
+                writeln!(writer)
+            }
+        }
+    }
+
+    fn write_text(&self, writer: &mut impl Write) -> io::Result<()> {
+        let total = self.total();
+        writeln!(writer, "phases:")?;
+        for PhaseTiming { phase, duration } in &self.phases {
+            let share = duration.as_secs_f64() / total.as_secs_f64() * 100.0;
+            writeln!(writer, "  {phase:<12} {duration:>12.3?} {share:>5.1}%")?;
+        }
+        writeln!(writer, "  {:<12} {total:>12.3?}", "total")?;
+
+        writeln!(writer, "threads:")?;
+        for (index, thread) in self.threads.iter().enumerate() {
+            writeln!(
+                writer,
+                "  #{index:<3} {:>12} bytes {:>12} lines {:>12.3?} {:>7.2} GB/s",
+                thread.bytes,
+                thread.lines,
+                thread.duration,
+                thread.bytes_per_second() / 1e9
+            )?;
+        }
+
+        writeln!(
+            writer,
+            "throughput: {:.2} GB/s, {:.1} M lines/s",
+            self.bytes_per_second() / 1e9,
+            self.lines() as f64 / total.as_secs_f64() / 1e6
+        )?;
+
+        if self.threads.iter().any(|thread| thread.counters.is_some()) {
+            writeln!(writer, "counters:")?;
+            writeln!(
+                writer,
+                "  {:<4}  {:>6} {:>21} {:>19} {:>19}",
+                "", "IPC", "branch-misses/line", "L1d-misses/line", "LLC-misses/line"
+            )?;
+            for (index, thread) in self.threads.iter().enumerate() {
+                if let Some(counters) = &thread.counters {
+                    write!(writer, "  #{index:<3}")?;
+                    write_counters(writer, counters, thread.lines)?;
+                }
+            }
+            if let Some(counters) = &self.counters() {
+                write!(writer, "  {:<4}", "all")?;
+                write_counters(writer, counters, self.lines())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes IPC and misses per line. Unsupported counters are printed as `-`.
+fn write_counters(writer: &mut impl Write, counters: &CounterValues, lines: u64) -> io::Result<()> {
+    let format = |value: Option<f64>, width: usize| match value {
+        Some(value) => format!("{value:>width$.4}"),
+        None => format!("{:>width$}", "-"),
+    };
+    writeln!(
+        writer,
+        "  {} {} {} {}",
+        format(counters.ipc(), 6),
+        format(counters.per_line(PerfEvent::BranchMisses, lines), 21),
+        format(counters.per_line(PerfEvent::L1dMisses, lines), 19),
+        format(counters.per_line(PerfEvent::LlcMisses, lines), 19),
+    )
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> RunMetrics {
+        let mut metrics = RunMetrics {
+            file_size: 3000,
+            phases: Vec::new(),
+            threads: vec![
+                ThreadMetrics {
+                    bytes: 2000,
+                    lines: 200,
+                    duration: Duration::from_millis(2),
+                    counters: None,
+                },
+                ThreadMetrics {
+                    bytes: 1000,
+                    lines: 100,
+                    duration: Duration::from_millis(1),
+                    counters: None,
+                },
+            ],
+        };
+        metrics.record(Phase::Mmap, Duration::from_millis(1));
+        metrics.record(Phase::Processing, Duration::from_millis(2));
+        metrics.record(Phase::Print, Duration::from_millis(1));
+        metrics
+    }
+
+    #[test]
+    fn test_totals() {
+        let metrics = metrics();
+        assert_eq!(metrics.total(), Duration::from_millis(4));
+        assert_eq!(metrics.lines(), 300);
+        assert_eq!(metrics.bytes_per_second(), 750_000.0);
+        assert_eq!(metrics.threads[1].bytes_per_second(), 1_000_000.0);
+    }
+
+    #[test]
+    fn test_json() {
+        let mut buf = Vec::new();
+        metrics().write(&mut buf, OutputFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(json["phases"][0]["phase"], "mmap");
+        assert_eq!(json["phases"][0]["seconds"], 0.001);
+        assert_eq!(json["threads"][1]["lines"], 100);
+        assert_eq!(json["lines"], 300);
+        assert_eq!(json["total_seconds"], 0.004);
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut metrics = metrics();
+        assert_eq!(metrics.counters(), None);
+
+        let counters = CounterValues {
+            cycles: Some(1000),
+            instructions: Some(3000),
+            branch_misses: Some(20),
+            l1d_misses: None,
+            llc_misses: Some(2),
+        };
+        metrics.threads[0].counters = Some(counters);
+        // Thread #1 has no counters
+        assert_eq!(metrics.counters(), None);
+
+        metrics.threads[1].counters = Some(counters);
+        let sum = metrics.counters().unwrap();
+        assert_eq!(sum.ipc(), Some(3.0));
+        assert_eq!(
+            sum.per_line(PerfEvent::BranchMisses, metrics.lines()),
+            Some(40.0 / 300.0)
+        );
+
+        let mut buf = Vec::new();
+        metrics.write(&mut buf, OutputFormat::Text).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(
+            text.contains(
+                "  #1    3.0000                0.2000                   -              0.0200\n"
+            ),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        let mut buf = Vec::new();
+        metrics().write(&mut buf, OutputFormat::Text).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(
+            text.contains("  mmap              1.000ms  25.0%\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("throughput: 0.00 GB/s, 0.1 M lines/s\n"),
+            "{text}"
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! Rendering of the results.
+
+use crate::ranking::Ranking;
+use crate::stddev::StationStats;
+use crate::units::TemperatureUnit;
+#[cfg(feature = "arrow")]
+use arrow_array::{Float64Array, Int64Array, RecordBatch, StringArray, UInt64Array};
+#[cfg(feature = "arrow")]
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+#[cfg(feature = "arrow")]
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::str::FromStr;
+#[cfg(feature = "arrow")]
+use std::sync::Arc;
+
+/// The format in which results are written.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The single-line format of the 1BRC challenge:
+    /// `{<station>=<min>/<mean>/<max>, ...}`.
+    #[default]
+    Text,
+    /// A single-line JSON array with one object per station.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown format `{s}`, expected one of: text, json")),
+        }
+    }
+}
+
+/// Describes how results are written.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub unit: TemperatureUnit,
+    /// If set, only the ranked stations are written, in the order of the
+    /// ranking. Otherwise, all stations are written in alphabetical order.
+    pub ranking: Option<Ranking>,
+}
+
+/// The JSON representation of a station.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StationRecord<'a> {
+    pub station: &'a str,
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub count: u32,
+    /// Only present for [`crate::StdDevData`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stddev: Option<f64>,
+}
+
+impl<'a> StationRecord<'a> {
+    /// Converts the data into the given unit. All values except for the
+    /// standard deviation are rounded to one decimal place.
+    #[must_use]
+    pub fn new(station: &'a str, stats: &impl StationStats, unit: TemperatureUnit) -> Self {
+        let data = stats.data();
+        Self {
+            station,
+            min: unit.min(data).to_f64(),
+            mean: unit.mean(data).to_f64(),
+            max: unit.max(data).to_f64(),
+            count: data.count(),
+            stddev: unit.stddev(stats),
+        }
+    }
+}
+
+/// Writes the stations, sorted by name, according to the options. This is
+/// [`crate::Results::write`] for other per-station statistics, such as
+/// [`crate::StdDevData`].
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if the stations don't track the
+/// statistic of the ranking.
+pub fn write_stations<S: AsRef<str>, D: StationStats>(
+    writer: &mut impl Write,
+    stations: &[(S, D)],
+    options: &OutputOptions,
+) -> io::Result<()> {
+    match &options.ranking {
+        None => write(
+            writer,
+            stations
+                .iter()
+                .map(|(station, data)| (station.as_ref(), data)),
+            options.format,
+            options.unit,
+        ),
+        Some(ranking) => write(
+            writer,
+            ranking.apply(stations)?.into_iter(),
+            options.format,
+            options.unit,
+        ),
+    }
+}
+
+/// Writes the stations in the given format. The stations are written in the
+/// order of the iterator.
+pub fn write<'a, D: StationStats + 'a>(
+    writer: &mut impl Write,
+    stats: impl ExactSizeIterator<Item = (&'a str, &'a D)>,
+    format: OutputFormat,
+    unit: TemperatureUnit,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => write_text(writer, stats, unit),
+        OutputFormat::Json => {
+            let records = stats
+                .map(|(station, data)| StationRecord::new(station, data, unit))
+                .collect::<Vec<_>>();
+            serde_json::to_writer(&mut *writer, &records)?;
+            writeln!(writer)
+        }
+    }
+}
+
+/// Writes the results in the format of the challenge. The costs of this
+/// function are negligible cheap.
+///
+/// The values are converted from the exact encoded values and rounded like in
+/// the JSON output, with ties towards positive infinity like in the reference
+/// implementation of the challenge; see [`TemperatureUnit`].
+pub fn write_text<'a, D: StationStats + 'a>(
+    writer: &mut impl Write,
+    stats: impl ExactSizeIterator<Item = (&'a str, &'a D)>,
+    unit: TemperatureUnit,
+) -> io::Result<()> {
+    write!(writer, "{{")?;
+    let n = stats.len();
+    for (index, (city, measurements)) in stats.enumerate() {
+        let measurements = measurements.data();
+        write!(
+            writer,
+            "{city}={}/{}/{}",
+            unit.min(measurements),
+            unit.mean(measurements),
+            unit.max(measurements)
+        )?;
+        if index != n - 1 {
+            write!(writer, ", ")?;
+        }
+    }
+    writeln!(writer, "}}")
+}
+
+/// The schema of [`record_batch`].
+#[cfg(feature = "arrow")]
+#[must_use]
+pub fn arrow_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("station", DataType::Utf8, false),
+        Field::new("min", DataType::Float64, false),
+        Field::new("mean", DataType::Float64, false),
+        Field::new("max", DataType::Float64, false),
+        Field::new("count", DataType::UInt64, false),
+        Field::new("sum", DataType::Int64, false),
+    ]))
+}
+
+/// Converts the stations into an Arrow [`RecordBatch`] with one row per
+/// station, in the order of the iterator.
+///
+/// `min`, `mean`, and `max` are converted into the given unit and rounded like
+/// in [`StationRecord`]. `sum` is the exact sum of the measurements in tenths
+/// of a degree Celsius, as encoded in the input, so that consumers can
+/// re-aggregate the rows without losing precision.
+#[cfg(feature = "arrow")]
+pub fn record_batch<'a, D: StationStats + 'a>(
+    stats: impl ExactSizeIterator<Item = (&'a str, &'a D)>,
+    unit: TemperatureUnit,
+) -> RecordBatch {
+    let n = stats.len();
+    let mut stations = Vec::with_capacity(n);
+    let (mut min, mut mean, mut max) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    let (mut count, mut sum) = (Vec::with_capacity(n), Vec::with_capacity(n));
+    for (station, data) in stats {
+        let data = data.data();
+        stations.push(station);
+        min.push(unit.min(data).to_f64());
+        mean.push(unit.mean(data).to_f64());
+        max.push(unit.max(data).to_f64());
+        count.push(u64::from(data.count()));
+        sum.push(data.encoded_sum());
+    }
+    RecordBatch::try_new(
+        arrow_schema(),
+        vec![
+            Arc::new(StringArray::from(stations)),
+            Arc::new(Float64Array::from(min)),
+            Arc::new(Float64Array::from(mean)),
+            Arc::new(Float64Array::from(max)),
+            Arc::new(UInt64Array::from(count)),
+            Arc::new(Int64Array::from(sum)),
+        ],
+    )
+    // The columns match the schema by construction.
+    .unwrap()
+}
+
+/// Writes the batch as a Snappy-compressed Parquet file.
+#[cfg(feature = "arrow")]
+pub fn write_parquet(writer: impl Write + Send, batch: &RecordBatch) -> io::Result<()> {
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer =
+        ArrowWriter::try_new(writer, batch.schema(), Some(properties)).map_err(io::Error::other)?;
+    writer.write(batch).map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AggregatedData;
+    use crate::{Aggregator, StdDevData};
+
+    fn stats() -> Vec<(&'static str, AggregatedData)> {
+        let mut berlin = AggregatedData::default();
+        berlin.add_datapoint(100);
+        berlin.add_datapoint(-157);
+        let mut hamburg = AggregatedData::default();
+        hamburg.add_datapoint(-127);
+        vec![("Berlin", berlin), ("Hamburg", hamburg)]
+    }
+
+    fn render(format: OutputFormat, unit: TemperatureUnit) -> String {
+        let stats = stats();
+        let mut buf = Vec::new();
+        write(
+            &mut buf,
+            stats.iter().map(|(station, data)| (*station, data)),
+            format,
+            unit,
+        )
+        .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            render(OutputFormat::Text, TemperatureUnit::Celsius),
+            "{Berlin=-15.7/-2.8/10.0, Hamburg=-12.7/-12.7/-12.7}\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Text, TemperatureUnit::Kelvin),
+            "{Berlin=257.5/270.3/283.2, Hamburg=260.5/260.5/260.5}\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            render(OutputFormat::Json, TemperatureUnit::Celsius),
+            r#"[{"station":"Berlin","min":-15.7,"mean":-2.8,"max":10.0,"count":2},{"station":"Hamburg","min":-12.7,"mean":-12.7,"max":-12.7,"count":1}]"#
+                .to_string()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn test_rounding_of_ties() {
+        // Mean: 0.25. Formatting the f32 with `{:.1}` would round to even (0.2).
+        let mut berlin = AggregatedData::default();
+        berlin.add_datapoint(10);
+        berlin.add_datapoint(-5);
+        let render = |format| {
+            let mut buf = Vec::new();
+            write(
+                &mut buf,
+                [("Berlin", &berlin)].into_iter(),
+                format,
+                TemperatureUnit::Celsius,
+            )
+            .unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!(render(OutputFormat::Text), "{Berlin=-0.5/0.3/1.0}\n");
+        assert_eq!(
+            render(OutputFormat::Json),
+            r#"[{"station":"Berlin","min":-0.5,"mean":0.3,"max":1.0,"count":2}]"#.to_string()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn test_json_with_stddev() {
+        let mut berlin = StdDevData::default();
+        berlin.add(100);
+        berlin.add(-157);
+        let mut buf = Vec::new();
+        write(
+            &mut buf,
+            [("Berlin", &berlin)].into_iter(),
+            OutputFormat::Json,
+            TemperatureUnit::Celsius,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            r#"[{"station":"Berlin","min":-15.7,"mean":-2.8,"max":10.0,"count":2,"stddev":12.85}]"#
+                .to_string()
+                + "\n"
+        );
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_record_batch() {
+        let stats = stats();
+        let batch = record_batch(
+            stats.iter().map(|(station, data)| (*station, data)),
+            TemperatureUnit::Celsius,
+        );
+        assert_eq!(batch.schema(), arrow_schema());
+        assert_eq!(batch.num_rows(), 2);
+        let column = |name| batch.column_by_name(name).unwrap();
+        let stations = column("station").as_any().downcast_ref::<StringArray>();
+        assert_eq!(
+            stations.unwrap().iter().collect::<Vec<_>>(),
+            [Some("Berlin"), Some("Hamburg")]
+        );
+        let mean = column("mean").as_any().downcast_ref::<Float64Array>();
+        assert_eq!(mean.unwrap().values(), &[-2.8, -12.7]);
+        let count = column("count").as_any().downcast_ref::<UInt64Array>();
+        assert_eq!(count.unwrap().values(), &[2, 1]);
+        let sum = column("sum").as_any().downcast_ref::<Int64Array>();
+        assert_eq!(sum.unwrap().values(), &[-57, -127]);
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_parquet_round_trip() {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReader;
+
+        let stats = stats();
+        let batch = record_batch(
+            stats.iter().map(|(station, data)| (*station, data)),
+            TemperatureUnit::Kelvin,
+        );
+        let path = std::env::temp_dir().join(format!("1brc-{}-output.parquet", std::process::id()));
+        write_parquet(std::fs::File::create(&path).unwrap(), &batch).unwrap();
+
+        let read = ParquetRecordBatchReader::try_new(std::fs::File::open(&path).unwrap(), 1024)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, [batch]);
+    }
+}
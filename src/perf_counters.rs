@@ -0,0 +1,340 @@
+//! Hardware performance counters via `perf_event_open(2)`, similar to
+//! `perf stat`, but scoped to the processing of a single chunk.
+//!
+//! Each counter is opened for the calling thread only and counts user space
+//! only, so this works with the default `perf_event_paranoid` level of `2`.
+//! Counters the CPU (or hypervisor) doesn't support are reported as missing.
+
+use serde::Serialize;
+use std::io;
+
+/// A hardware event that is counted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PerfEvent {
+    Cycles,
+    Instructions,
+    BranchMisses,
+    /// Read misses of the L1 data cache.
+    L1dMisses,
+    /// Read misses of the last level cache.
+    LlcMisses,
+}
+
+impl PerfEvent {
+    pub const ALL: [Self; 5] = [
+        Self::Cycles,
+        Self::Instructions,
+        Self::BranchMisses,
+        Self::L1dMisses,
+        Self::LlcMisses,
+    ];
+}
+
+/// The counted events. `None` if the event is not supported.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CounterValues {
+    pub cycles: Option<u64>,
+    pub instructions: Option<u64>,
+    pub branch_misses: Option<u64>,
+    pub l1d_misses: Option<u64>,
+    pub llc_misses: Option<u64>,
+}
+
+impl CounterValues {
+    #[must_use]
+    pub const fn get(&self, event: PerfEvent) -> Option<u64> {
+        match event {
+            PerfEvent::Cycles => self.cycles,
+            PerfEvent::Instructions => self.instructions,
+            PerfEvent::BranchMisses => self.branch_misses,
+            PerfEvent::L1dMisses => self.l1d_misses,
+            PerfEvent::LlcMisses => self.llc_misses,
+        }
+    }
+
+    const fn get_mut(&mut self, event: PerfEvent) -> &mut Option<u64> {
+        match event {
+            PerfEvent::Cycles => &mut self.cycles,
+            PerfEvent::Instructions => &mut self.instructions,
+            PerfEvent::BranchMisses => &mut self.branch_misses,
+            PerfEvent::L1dMisses => &mut self.l1d_misses,
+            PerfEvent::LlcMisses => &mut self.llc_misses,
+        }
+    }
+
+    /// Instructions per cycle.
+    #[must_use]
+    pub fn ipc(&self) -> Option<f64> {
+        Some(self.instructions? as f64 / self.cycles? as f64)
+    }
+
+    /// The value of the event divided by the amount of lines.
+    #[must_use]
+    pub fn per_line(&self, event: PerfEvent, lines: u64) -> Option<f64> {
+        Some(self.get(event)? as f64 / lines as f64)
+    }
+
+    /// Sums up the values. An event is only reported if it is reported by
+    /// both sides.
+    #[must_use]
+    pub fn sum(&self, other: &Self) -> Self {
+        let mut sum = Self::default();
+        for event in PerfEvent::ALL {
+            *sum.get_mut(event) = self.get(event).zip(other.get(event)).map(|(a, b)| a + b);
+        }
+        sum
+    }
+}
+
+/// Open counters of the current thread. The counters start disabled.
+#[derive(Debug)]
+pub(crate) struct PerfCounters {
+    #[cfg(target_os = "linux")]
+    counters: Vec<(PerfEvent, sys::Counter)>,
+}
+
+#[cfg(target_os = "linux")]
+impl PerfCounters {
+    /// Opens all supported events. Fails if no event is supported.
+    pub(crate) fn open() -> io::Result<Self> {
+        let mut last_error = None;
+        let counters = PerfEvent::ALL
+            .into_iter()
+            .filter_map(|event| match sys::Counter::open(event) {
+                Ok(counter) => Some((event, counter)),
+                Err(err) => {
+                    last_error = Some(err);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        match last_error {
+            Some(err) if counters.is_empty() => Err(err),
+            _ => Ok(Self { counters }),
+        }
+    }
+
+    pub(crate) fn enable(&self) {
+        self.counters
+            .iter()
+            .for_each(|(_, counter)| counter.enable());
+    }
+
+    /// Disables the counters and reads them. Counters that can't be read are
+    /// reported as missing.
+    pub(crate) fn disable_and_read(&self) -> CounterValues {
+        self.counters
+            .iter()
+            .for_each(|(_, counter)| counter.disable());
+        let mut values = CounterValues::default();
+        for (event, counter) in &self.counters {
+            *values.get_mut(*event) = counter.read().ok();
+        }
+        values
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl PerfCounters {
+    pub(crate) fn open() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "performance counters are only supported on Linux",
+        ))
+    }
+
+    pub(crate) const fn enable(&self) {}
+
+    pub(crate) fn disable_and_read(&self) -> CounterValues {
+        CounterValues::default()
+    }
+}
+
+/// Checks whether performance counters can be opened.
+pub fn perf_counters_available() -> io::Result<()> {
+    PerfCounters::open().map(drop)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    //! The raw `perf_event_open(2)` interface. Only the first version of
+    //! `struct perf_event_attr` is required.
+
+    use super::PerfEvent;
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_TYPE_HW_CACHE: u32 = 3;
+
+    const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+    const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+    const PERF_COUNT_HW_BRANCH_MISSES: u64 = 5;
+
+    const PERF_COUNT_HW_CACHE_L1D: u64 = 0;
+    const PERF_COUNT_HW_CACHE_LL: u64 = 2;
+    const PERF_COUNT_HW_CACHE_OP_READ: u64 = 0;
+    const PERF_COUNT_HW_CACHE_RESULT_MISS: u64 = 1;
+
+    const fn hw_cache_config(id: u64, op: u64, result: u64) -> u64 {
+        id | (op << 8) | (result << 16)
+    }
+
+    const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+    const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+
+    const ATTR_FLAG_DISABLED: u64 = 1 << 0;
+    const ATTR_FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+    const ATTR_FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+    const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+    const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+    const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+
+    /// `PERF_ATTR_SIZE_VER0` of `struct perf_event_attr`.
+    #[repr(C)]
+    #[derive(Debug, Default)]
+    struct PerfEventAttr {
+        type_: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+    }
+
+    const _: () = assert!(size_of::<PerfEventAttr>() == 64);
+
+    /// A single counter of the current thread on any CPU.
+    #[derive(Debug)]
+    pub(super) struct Counter(File);
+
+    impl Counter {
+        pub(super) fn open(event: PerfEvent) -> io::Result<Self> {
+            let (type_, config) = match event {
+                PerfEvent::Cycles => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+                PerfEvent::Instructions => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS),
+                PerfEvent::BranchMisses => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_BRANCH_MISSES),
+                PerfEvent::L1dMisses => (
+                    PERF_TYPE_HW_CACHE,
+                    hw_cache_config(
+                        PERF_COUNT_HW_CACHE_L1D,
+                        PERF_COUNT_HW_CACHE_OP_READ,
+                        PERF_COUNT_HW_CACHE_RESULT_MISS,
+                    ),
+                ),
+                PerfEvent::LlcMisses => (
+                    PERF_TYPE_HW_CACHE,
+                    hw_cache_config(
+                        PERF_COUNT_HW_CACHE_LL,
+                        PERF_COUNT_HW_CACHE_OP_READ,
+                        PERF_COUNT_HW_CACHE_RESULT_MISS,
+                    ),
+                ),
+            };
+            let attr = PerfEventAttr {
+                type_,
+                size: size_of::<PerfEventAttr>() as u32,
+                config,
+                read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+                flags: ATTR_FLAG_DISABLED | ATTR_FLAG_EXCLUDE_KERNEL | ATTR_FLAG_EXCLUDE_HV,
+                ..Default::default()
+            };
+            // pid 0 and cpu -1: the calling thread on any CPU; no group.
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const PerfEventAttr,
+                    0,
+                    -1,
+                    -1,
+                    PERF_FLAG_FD_CLOEXEC,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: The syscall returned a new file descriptor we own.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+            Ok(Self(File::from(fd)))
+        }
+
+        fn ioctl(&self, request: libc::c_ulong) {
+            use std::os::fd::AsRawFd;
+            // Can only fail for invalid file descriptors.
+            let result = unsafe { libc::ioctl(self.0.as_raw_fd(), request, 0) };
+            debug_assert_eq!(result, 0);
+        }
+
+        pub(super) fn enable(&self) {
+            self.ioctl(PERF_EVENT_IOC_ENABLE);
+        }
+
+        pub(super) fn disable(&self) {
+            self.ioctl(PERF_EVENT_IOC_DISABLE);
+        }
+
+        /// Reads the value. If the counter was multiplexed with other
+        /// counters, the value is extrapolated to the whole time.
+        pub(super) fn read(&self) -> io::Result<u64> {
+            let mut buf = [0; 3 * size_of::<u64>()];
+            (&self.0).read_exact(&mut buf)?;
+            let [value, enabled, running] = [0, 1, 2].map(|index| {
+                let bytes = &buf[index * size_of::<u64>()..][..size_of::<u64>()];
+                u64::from_ne_bytes(bytes.try_into().unwrap())
+            });
+            if running == 0 {
+                return Err(io::Error::other("counter never ran"));
+            }
+            Ok((value as u128 * enabled as u128 / running as u128) as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_values() {
+        let a = CounterValues {
+            cycles: Some(100),
+            instructions: Some(250),
+            branch_misses: Some(10),
+            l1d_misses: None,
+            llc_misses: Some(1),
+        };
+        assert_eq!(a.ipc(), Some(2.5));
+        assert_eq!(a.per_line(PerfEvent::BranchMisses, 20), Some(0.5));
+        assert_eq!(a.per_line(PerfEvent::L1dMisses, 20), None);
+
+        let sum = a.sum(&CounterValues {
+            l1d_misses: Some(5),
+            ..a
+        });
+        assert_eq!(sum.cycles, Some(200));
+        assert_eq!(sum.l1d_misses, None);
+        assert_eq!(sum.ipc(), Some(2.5));
+    }
+
+    #[test]
+    fn test_count_instructions() {
+        // Performance counters are often unavailable in containers and VMs.
+        let Ok(counters) = PerfCounters::open() else {
+            return;
+        };
+        counters.enable();
+        let sum = (0..100_000_u64).map(std::hint::black_box).sum::<u64>();
+        let values = counters.disable_and_read();
+        assert_eq!(sum, 4_999_950_000);
+        if let Some(instructions) = values.instructions {
+            assert!(instructions > 100_000, "{instructions}");
+        }
+    }
+}
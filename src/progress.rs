@@ -0,0 +1,139 @@
+//! Progress reporting for long runs.
+//!
+//! Each worker thread publishes how many bytes of its chunk it has processed
+//! after each block of [`BLOCK_SIZE`] bytes. The main thread periodically
+//! sums up these counters. The hot loop itself is not touched.
+
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The granularity in which the worker threads report their progress.
+pub(crate) const BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// A snapshot of the progress of a run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub processed_bytes: usize,
+    pub total_bytes: usize,
+    /// The time since the processing started.
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// The processed share, within `0.0..=1.0`.
+    #[must_use]
+    pub fn fraction(&self) -> f64 {
+        if self.total_bytes == 0 {
+            1.0
+        } else {
+            self.processed_bytes as f64 / self.total_bytes as f64
+        }
+    }
+
+    /// The average throughput so far.
+    #[must_use]
+    pub fn bytes_per_second(&self) -> f64 {
+        self.processed_bytes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The estimated remaining time, assuming the throughput stays the same.
+    /// `None` as long as nothing was processed.
+    #[must_use]
+    pub fn eta(&self) -> Option<Duration> {
+        if self.processed_bytes == 0 {
+            return None;
+        }
+        let remaining_bytes = self.total_bytes - self.processed_bytes;
+        Some(
+            self.elapsed
+                .mul_f64(remaining_bytes as f64 / self.processed_bytes as f64),
+        )
+    }
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:5.1}% ({} / {} MB), {:.2} GB/s, ETA ",
+            self.fraction() * 100.0,
+            self.processed_bytes / 1_000_000,
+            self.total_bytes / 1_000_000,
+            self.bytes_per_second() / 1e9,
+        )?;
+        match self.eta() {
+            Some(eta) => write!(f, "{:.1}s", eta.as_secs_f64()),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+/// A counter on its own cache line, so that the threads don't slow each other
+/// down by writing to the same cache line (false sharing).
+#[derive(Debug, Default)]
+#[repr(align(128))]
+struct PaddedCounter(AtomicUsize);
+
+/// The processed bytes of each thread.
+#[derive(Debug)]
+pub(crate) struct ProgressCounters {
+    counters: Vec<PaddedCounter>,
+}
+
+impl ProgressCounters {
+    pub(crate) fn new(threads: usize) -> Self {
+        Self {
+            counters: (0..threads).map(|_| PaddedCounter::default()).collect(),
+        }
+    }
+
+    /// Sets the processed bytes of the thread. Each thread only writes its own
+    /// counter.
+    pub(crate) fn set(&self, thread: usize, processed_bytes: usize) {
+        self.counters[thread]
+            .0
+            .store(processed_bytes, Ordering::Relaxed);
+    }
+
+    /// The sum of the processed bytes of all threads.
+    pub(crate) fn sum(&self) -> usize {
+        self.counters
+            .iter()
+            .map(|counter| counter.0.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let progress = Progress {
+            processed_bytes: 250,
+            total_bytes: 1000,
+            elapsed: Duration::from_secs(1),
+        };
+        assert_eq!(progress.fraction(), 0.25);
+        assert_eq!(progress.bytes_per_second(), 250.0);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(3)));
+
+        let progress = Progress {
+            processed_bytes: 0,
+            ..progress
+        };
+        assert_eq!(progress.eta(), None);
+        assert_eq!(progress.to_string(), "  0.0% (0 / 0 MB), 0.00 GB/s, ETA -");
+    }
+
+    #[test]
+    fn test_counters() {
+        let counters = ProgressCounters::new(3);
+        counters.set(0, 10);
+        counters.set(2, 5);
+        counters.set(0, 20);
+        assert_eq!(counters.sum(), 25);
+    }
+}
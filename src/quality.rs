@@ -0,0 +1,440 @@
+//! A validating parser with a data quality report.
+//!
+//! In contrast to [`crate::process_file_chunk`], which trusts the input, each
+//! line is checked with [`phips_1brc_core::parse_line`] against the
+//! specification of the challenge: a station name of 1 to 100 bytes of UTF-8
+//! without `;`, and a value within `-99.9..=99.9` with exactly one decimal
+//! place. Invalid lines are skipped and reported. This is considerably slower
+//! than the optimized hot path.
+
+use crate::filter::StationFilter;
+use crate::hash::HashMap;
+use crate::AggregatedData;
+use phips_1brc_core::data_set_properties::STATIONS_IN_DATASET;
+use phips_1brc_core::InvalidLine;
+pub use phips_1brc_core::MAX_STATION_LEN;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// A kind of problem with a line (or the file).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// No `;`, an empty station name, or a value that isn't a number with
+    /// exactly one decimal place.
+    MalformedLine,
+    /// A value outside of `-99.9..=99.9`.
+    ValueOutOfRange,
+    /// A station name longer than [`MAX_STATION_LEN`] bytes.
+    StationTooLong,
+    /// A station name that isn't valid UTF-8.
+    InvalidUtf8,
+    EmptyLine,
+    /// The last line doesn't end with `\n`. It is processed nevertheless.
+    MissingFinalNewline,
+    /// A line that is identical to the line directly before it, such as a
+    /// record that was written twice. Duplicates further apart aren't
+    /// detected, as that would require to keep all lines. It is aggregated
+    /// nevertheless, so this is no error.
+    ConsecutiveDuplicateLine,
+}
+
+impl IssueKind {
+    /// Whether lines with this issue are skipped. Only those count as errors.
+    #[must_use]
+    pub const fn is_error(self) -> bool {
+        !matches!(
+            self,
+            Self::MissingFinalNewline | Self::ConsecutiveDuplicateLine
+        )
+    }
+}
+
+impl From<InvalidLine> for IssueKind {
+    fn from(invalid: InvalidLine) -> Self {
+        match invalid {
+            InvalidLine::Empty => Self::EmptyLine,
+            InvalidLine::Malformed => Self::MalformedLine,
+            InvalidLine::ValueOutOfRange => Self::ValueOutOfRange,
+            InvalidLine::StationTooLong => Self::StationTooLong,
+            InvalidLine::InvalidUtf8 => Self::InvalidUtf8,
+        }
+    }
+}
+
+impl Display for IssueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::MalformedLine => "malformed lines",
+            Self::ValueOutOfRange => "values out of range",
+            Self::StationTooLong => "station names too long",
+            Self::InvalidUtf8 => "station names with invalid UTF-8",
+            Self::EmptyLine => "empty lines",
+            Self::MissingFinalNewline => "missing final newline",
+            Self::ConsecutiveDuplicateLine => "consecutive duplicate lines",
+        };
+        f.write_str(description)
+    }
+}
+
+/// The position of a line with an issue.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Position {
+    /// 1-based.
+    pub line: u64,
+    /// The byte offset of the beginning of the line within the file.
+    pub offset: usize,
+}
+
+/// The occurrences of an [`IssueKind`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct IssueSummary {
+    pub count: u64,
+    /// The first occurrences, in file order.
+    pub samples: Vec<Position>,
+}
+
+/// Describes how invalid input is handled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct QualityOptions {
+    /// Abort after more than this amount of errors (skipped lines). `None`
+    /// skips all invalid lines.
+    pub max_errors: Option<u64>,
+    /// The maximum amount of sample positions per issue kind.
+    pub max_samples: usize,
+}
+
+impl Default for QualityOptions {
+    fn default() -> Self {
+        Self {
+            max_errors: None,
+            max_samples: 10,
+        }
+    }
+}
+
+/// What was wrong with a file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct QualityReport {
+    /// All processed lines, including invalid ones. If the run was aborted,
+    /// the lines that weren't processed anymore are not included. The
+    /// positions refer to the lines of the file nevertheless.
+    pub lines: u64,
+    /// The lines that were skipped.
+    pub skipped_lines: u64,
+    pub issues: BTreeMap<IssueKind, IssueSummary>,
+}
+
+impl QualityReport {
+    /// Whether the file has no issues at all.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn record(&mut self, kind: IssueKind, position: Position, max_samples: usize) {
+        let summary = self.issues.entry(kind).or_default();
+        summary.count += 1;
+        if summary.samples.len() < max_samples {
+            summary.samples.push(position);
+        }
+        if kind.is_error() {
+            self.skipped_lines += 1;
+        }
+    }
+
+    /// Appends the report of the following part of the file, which begins
+    /// after `first_line` lines. The line numbers of `next` are relative to
+    /// its beginning.
+    pub(crate) fn append(&mut self, next: Self, first_line: u64, max_samples: usize) {
+        for (kind, summary) in next.issues {
+            let acc = self.issues.entry(kind).or_default();
+            acc.count += summary.count;
+            let free = max_samples.saturating_sub(acc.samples.len());
+            acc.samples.extend(
+                summary
+                    .samples
+                    .into_iter()
+                    .take(free)
+                    .map(|position| Position {
+                        line: position.line + first_line,
+                        ..position
+                    }),
+            );
+        }
+        self.lines += next.lines;
+        self.skipped_lines += next.skipped_lines;
+    }
+}
+
+impl Display for QualityReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} lines, {} skipped", self.lines, self.skipped_lines)?;
+        for (kind, summary) in &self.issues {
+            let samples = summary
+                .samples
+                .iter()
+                .map(|position| format!("line {} (byte {})", position.line, position.offset))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(f, "  {kind}: {} (first: {samples})", summary.count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The run was aborted, because there were more errors than allowed by
+/// [`QualityOptions::max_errors`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QualityError {
+    pub max_errors: u64,
+    /// The report up to the abort.
+    pub report: QualityReport,
+}
+
+impl Display for QualityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "aborted after more than {} invalid lines",
+            self.max_errors
+        )
+    }
+}
+
+impl Error for QualityError {}
+
+/// Shared state of all threads, to abort as soon as the allowed amount of
+/// errors is exceeded.
+#[derive(Debug, Default)]
+pub(crate) struct ErrorBudget {
+    errors: AtomicU64,
+    exceeded: AtomicBool,
+}
+
+impl ErrorBudget {
+    /// Counts an error. Returns false if the budget is exceeded.
+    fn spend(&self, max_errors: Option<u64>) -> bool {
+        let errors = self.errors.fetch_add(1, Ordering::Relaxed) + 1;
+        if max_errors.is_some_and(|max_errors| errors > max_errors) {
+            self.exceeded.store(true, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Relaxed)
+    }
+}
+
+/// Validates a line without the newline.
+pub(crate) fn parse_line(line: &[u8]) -> Result<(&str, i16), IssueKind> {
+    phips_1brc_core::parse_line(line).map_err(IssueKind::from)
+}
+
+/// Counts the lines of a line-aligned part of the file. The last line may
+/// miss its newline.
+pub(crate) fn count_lines(bytes: &[u8]) -> u64 {
+    let newlines = memchr::memchr_iter(b'\n', bytes).count() as u64;
+    newlines + u64::from(!bytes.is_empty() && !bytes.ends_with(b"\n"))
+}
+
+/// Returns the line before the given byte offset, without the newline. The
+/// offset must be the beginning of a line.
+pub(crate) fn line_before(bytes: &[u8], offset: usize) -> Option<&[u8]> {
+    let before = bytes[..offset].strip_suffix(b"\n")?;
+    Some(memchr::memrchr(b'\n', before).map_or(before, |newline| &before[newline + 1..]))
+}
+
+/// Validates and aggregates a line-aligned part of the file, which starts at
+/// the given byte offset within the file. The last line may miss its newline.
+///
+/// `previous_line` is the line before the part, if any, to detect a
+/// duplicate at the beginning of the part.
+pub(crate) fn process_file_chunk<'a>(
+    bytes: &'a [u8],
+    offset: usize,
+    previous_line: Option<&[u8]>,
+    filter: Option<&StationFilter>,
+    options: &QualityOptions,
+    budget: &ErrorBudget,
+) -> (HashMap<&'a str, AggregatedData>, QualityReport) {
+    let mut stats: HashMap<&str, AggregatedData> =
+        HashMap::with_capacity_and_hasher(STATIONS_IN_DATASET, Default::default());
+    let mut report = QualityReport::default();
+    let mut previous_line = previous_line;
+
+    let mut line_start = 0;
+    while line_start < bytes.len() && !budget.exceeded() {
+        let remaining_bytes = &bytes[line_start..];
+        let (line, line_len) = match memchr::memchr(b'\n', remaining_bytes) {
+            Some(newline) => (&remaining_bytes[..newline], newline + 1),
+            None => (remaining_bytes, remaining_bytes.len()),
+        };
+        report.lines += 1;
+        let position = Position {
+            line: report.lines,
+            offset: offset + line_start,
+        };
+        if line_len == line.len() {
+            report.record(
+                IssueKind::MissingFinalNewline,
+                position,
+                options.max_samples,
+            );
+        }
+
+        match parse_line(line) {
+            Ok((station, measurement)) => {
+                if previous_line == Some(line) {
+                    report.record(
+                        IssueKind::ConsecutiveDuplicateLine,
+                        position,
+                        options.max_samples,
+                    );
+                }
+                let accepted = filter.is_none_or(|filter| {
+                    filter.accepts_station(station) && filter.accepts_measurement(measurement)
+                });
+                if accepted {
+                    stats.entry(station).or_default().add_datapoint(measurement);
+                }
+            }
+            Err(kind) => {
+                report.record(kind, position, options.max_samples);
+                if !budget.spend(options.max_errors) {
+                    break;
+                }
+            }
+        }
+        previous_line = Some(line);
+        line_start += line_len;
+    }
+
+    (stats, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate<'a>(
+        input: &'a [u8],
+        options: &QualityOptions,
+    ) -> (Vec<(&'a str, AggregatedData)>, QualityReport) {
+        let (stats, report) =
+            process_file_chunk(input, 0, None, None, options, &ErrorBudget::default());
+        let mut stats = stats.into_iter().collect::<Vec<_>>();
+        stats.sort_unstable_by_key(|(station, _)| *station);
+        (stats, report)
+    }
+
+    #[test]
+    fn test_report() {
+        let long_station = "x".repeat(MAX_STATION_LEN + 1);
+        let input = [
+            b"Berlin;10.0\n\nHamburg\n".as_slice(),
+            format!("{long_station};1.0\n").as_bytes(),
+            b"Oslo;100.0\nBerlin;10.0\nBerlin;10.0\n\xff;1.0\nHamburg;-12.7",
+        ]
+        .concat();
+        let (stats, report) = validate(&input, &QualityOptions::default());
+
+        assert_eq!(
+            stats,
+            [
+                ("Berlin", AggregatedData::new(100, 100, 300, 3)),
+                ("Hamburg", AggregatedData::new(-127, -127, -127, 1)),
+            ]
+        );
+        assert_eq!(report.lines, 9);
+        assert_eq!(report.skipped_lines, 5);
+        let summary = |kind| report.issues.get(&kind).cloned().unwrap_or_default();
+        assert_eq!(
+            summary(IssueKind::EmptyLine).samples,
+            [Position {
+                line: 2,
+                offset: 12
+            }]
+        );
+        assert_eq!(summary(IssueKind::MalformedLine).count, 1);
+        assert_eq!(summary(IssueKind::StationTooLong).count, 1);
+        assert_eq!(summary(IssueKind::ValueOutOfRange).samples[0].line, 5);
+        assert_eq!(summary(IssueKind::InvalidUtf8).count, 1);
+        assert_eq!(
+            summary(IssueKind::ConsecutiveDuplicateLine).samples[0].line,
+            7
+        );
+        assert_eq!(summary(IssueKind::MissingFinalNewline).samples[0].line, 9);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn test_max_errors() {
+        let options = QualityOptions {
+            max_errors: Some(1),
+            max_samples: 10,
+        };
+        let (stats, report) = validate(b"a;1.0\n\n\nb;2.0\n", &options);
+        assert_eq!(stats.len(), 1);
+        assert_eq!(report.lines, 3);
+        assert_eq!(report.skipped_lines, 2);
+    }
+
+    #[test]
+    fn test_duplicate_at_chunk_start() {
+        let input = b"a;1.0\na;1.0\nb;1.0\n";
+        let offset = 6;
+        let previous_line = line_before(input, offset);
+        assert_eq!(previous_line, Some(b"a;1.0".as_slice()));
+        assert_eq!(line_before(input, 0), None);
+
+        let (_, report) = process_file_chunk(
+            &input[offset..],
+            offset,
+            previous_line,
+            None,
+            &QualityOptions::default(),
+            &ErrorBudget::default(),
+        );
+        assert_eq!(
+            report.issues[&IssueKind::ConsecutiveDuplicateLine].samples,
+            [Position { line: 1, offset }]
+        );
+    }
+
+    #[test]
+    fn test_count_lines() {
+        assert_eq!(count_lines(b""), 0);
+        assert_eq!(count_lines(b"a;1.0\n\n"), 2);
+        assert_eq!(count_lines(b"a;1.0\nb;1.0"), 2);
+    }
+
+    #[test]
+    fn test_append() {
+        let options = QualityOptions {
+            max_errors: None,
+            max_samples: 2,
+        };
+        let mut a = QualityReport::default();
+        let (_, first) = validate(b"a;1.0\n\n", &options);
+        a.append(first, 0, options.max_samples);
+        let (_, second) = validate(b"\n\nb;1.0\n", &options);
+        a.append(second, 2, options.max_samples);
+        assert_eq!(a.lines, 5);
+        let empty = &a.issues[&IssueKind::EmptyLine];
+        assert_eq!(empty.count, 3);
+        assert_eq!(
+            empty
+                .samples
+                .iter()
+                .map(|position| position.line)
+                .collect::<Vec<_>>(),
+            [2, 3]
+        );
+    }
+}
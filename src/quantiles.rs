@@ -0,0 +1,323 @@
+//! Approximate quantiles per station with a mergeable sketch.
+//!
+//! [`QuantileSketch`] is a DDSketch: values are counted in logarithmically
+//! sized buckets, so every reported quantile has a bounded *relative* error,
+//! independent of the range and the precision of the input. Sketches of the
+//! same accuracy can be merged without losing accuracy, which is what the
+//! per-thread maps require.
+//!
+//! See Masson et al., "DDSketch: A Fast and Fully-Mergeable Quantile Sketch
+//! with Relative-Error Guarantees" (VLDB 2019).
+
+use crate::aggregator::Aggregator;
+use std::str::FromStr;
+
+/// The relative accuracy of [`QuantileSketch::default`]: 1%.
+pub const DEFAULT_RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Bucket counts for a contiguous range of bucket keys.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Store {
+    /// The key of `counts[0]`.
+    offset: i32,
+    counts: Vec<u64>,
+}
+
+impl Store {
+    fn add(&mut self, key: i32, count: u64) {
+        if self.counts.is_empty() {
+            self.offset = key;
+            self.counts.push(0);
+        } else if key < self.offset {
+            let grow_by = (self.offset - key) as usize;
+            self.counts.splice(0..0, std::iter::repeat_n(0, grow_by));
+            self.offset = key;
+        } else if key >= self.offset + self.counts.len() as i32 {
+            self.counts.resize((key - self.offset) as usize + 1, 0);
+        }
+        self.counts[(key - self.offset) as usize] += count;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        other.iter().for_each(|(key, count)| self.add(key, count));
+    }
+
+    /// The non-empty buckets in ascending order of their keys.
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (i32, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(index, &count)| (self.offset + index as i32, count))
+    }
+}
+
+/// A mergeable sketch of a distribution that answers quantile queries with a
+/// bounded relative error.
+///
+/// For a relative accuracy `α`, each returned quantile `x̃` of the true
+/// quantile `x` satisfies `|x̃ - x| <= α * |x|`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantileSketch {
+    relative_accuracy: f64,
+    /// `ln(γ)` with `γ = (1 + α) / (1 - α)`, the ratio of the bucket bounds.
+    gamma_ln: f64,
+    positive: Store,
+    /// Buckets of the absolute values of the negative values.
+    negative: Store,
+    zero_count: u64,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for QuantileSketch {
+    fn default() -> Self {
+        Self::new(DEFAULT_RELATIVE_ACCURACY)
+    }
+}
+
+impl QuantileSketch {
+    /// Creates an empty sketch with the given relative accuracy, within
+    /// `0.0..1.0`. Smaller values require more buckets.
+    #[must_use]
+    pub fn new(relative_accuracy: f64) -> Self {
+        assert!(
+            relative_accuracy > 0.0 && relative_accuracy < 1.0,
+            "relative accuracy must be within (0, 1)"
+        );
+        let gamma = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+        Self {
+            relative_accuracy,
+            gamma_ln: gamma.ln(),
+            positive: Store::default(),
+            negative: Store::default(),
+            zero_count: 0,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    #[must_use]
+    pub const fn relative_accuracy(&self) -> f64 {
+        self.relative_accuracy
+    }
+
+    /// The key of the bucket that contains the (positive) value.
+    fn key(&self, value: f64) -> i32 {
+        (value.ln() / self.gamma_ln).ceil() as i32
+    }
+
+    /// The representative value of the bucket: the value with the same
+    /// relative distance to both bounds of the bucket.
+    fn value(&self, key: i32) -> f64 {
+        let gamma = self.gamma_ln.exp();
+        2.0 * (key as f64 * self.gamma_ln).exp() / (gamma + 1.0)
+    }
+
+    /// Adds a value. NaN is ignored.
+    pub fn add_value(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        // Values this small are indistinguishable from zero.
+        if value.abs() < f64::MIN_POSITIVE {
+            self.zero_count += 1;
+        } else if value > 0.0 {
+            self.positive.add(self.key(value), 1);
+        } else {
+            self.negative.add(self.key(-value), 1);
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    #[must_use]
+    pub const fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the approximate `q`-quantile, with `q` within `0.0..=1.0`.
+    /// `None` if the sketch is empty.
+    #[must_use]
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        assert!((0.0..=1.0).contains(&q), "quantile must be within [0, 1]");
+        if self.count == 0 {
+            return None;
+        }
+        // The exact extremes are known.
+        if q == 0.0 {
+            return Some(self.min);
+        } else if q == 1.0 {
+            return Some(self.max);
+        }
+        // The rank of the value, zero-based.
+        let rank = (q * (self.count - 1) as f64).floor() as u64;
+
+        // From the smallest to the largest value: The negative values with
+        // the largest absolute value come first.
+        let negative = self
+            .negative
+            .iter()
+            .rev()
+            .map(|(key, count)| (-self.value(key), count));
+        let zero = std::iter::once((0.0, self.zero_count));
+        let positive = self
+            .positive
+            .iter()
+            .map(|(key, count)| (self.value(key), count));
+
+        let mut seen = 0;
+        let value = negative
+            .chain(zero)
+            .chain(positive)
+            .find(|(_, count)| {
+                seen += count;
+                seen > rank
+            })
+            .map(|(value, _)| value)
+            .unwrap();
+        // The representative value of a bucket may be beyond the extremes.
+        Some(value.clamp(self.min, self.max))
+    }
+
+    /// Merges another sketch into this one.
+    ///
+    /// # Panics
+    /// If the sketches have a different relative accuracy.
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(
+            self.relative_accuracy, other.relative_accuracy,
+            "only sketches with the same accuracy can be merged"
+        );
+        self.positive.merge(&other.positive);
+        self.negative.merge(&other.negative);
+        self.zero_count += other.zero_count;
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+impl Aggregator for QuantileSketch {
+    type Output = Self;
+
+    #[inline(always)]
+    fn add(&mut self, measurement: i16) {
+        self.add_value(measurement as f64 / 10.0);
+    }
+
+    fn merge(&mut self, other: Self) {
+        Self::merge(self, &other);
+    }
+
+    fn finish(self) -> Self::Output {
+        self
+    }
+}
+
+/// A list of quantiles, such as `0.5,0.9,0.99`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantiles(pub Vec<f64>);
+
+impl Default for Quantiles {
+    fn default() -> Self {
+        Self(vec![0.5, 0.9, 0.99])
+    }
+}
+
+impl FromStr for Quantiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|q| match q.trim().parse::<f64>() {
+                Ok(q) if (0.0..=1.0).contains(&q) => Ok(q),
+                _ => Err(format!(
+                    "invalid quantile `{q}`, expected a number within 0..=1"
+                )),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::SplitMix64;
+
+    /// The exact quantile of the sorted values, with the same rank definition
+    /// as the sketch.
+    fn exact_quantile(sorted: &[f64], q: f64) -> f64 {
+        sorted[(q * (sorted.len() - 1) as f64).floor() as usize]
+    }
+
+    #[test]
+    fn test_relative_error() {
+        let mut rng = SplitMix64::new(42);
+        let values = (0..10_000)
+            .map(|_| rng.next_gaussian(0.0, 1000.0))
+            .collect::<Vec<_>>();
+
+        for accuracy in [0.01, 0.05] {
+            let mut sketch = QuantileSketch::new(accuracy);
+            values.iter().for_each(|&value| sketch.add_value(value));
+
+            let mut sorted = values.clone();
+            sorted.sort_by(f64::total_cmp);
+            for q in [0.0, 0.01, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+                let exact = exact_quantile(&sorted, q);
+                let approx = sketch.quantile(q).unwrap();
+                assert!(
+                    (approx - exact).abs() <= accuracy * exact.abs() + 1e-9,
+                    "q={q}: {approx} vs {exact}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut all = QuantileSketch::default();
+        let mut a = QuantileSketch::default();
+        let mut b = QuantileSketch::default();
+        for (index, value) in (-500..500).map(|value| value as f64 / 10.0).enumerate() {
+            all.add_value(value);
+            if index % 3 == 0 {
+                a.add_value(value);
+            } else {
+                b.add_value(value);
+            }
+        }
+        a.merge(&b);
+        assert_eq!(a, all);
+        assert_eq!(a.count(), 1000);
+        assert_eq!(a.quantile(0.0), Some(-50.0));
+        assert_eq!(a.quantile(1.0), Some(49.9));
+        let median = a.quantile(0.5).unwrap();
+        assert!(median.abs() <= 0.1, "{median}");
+    }
+
+    #[test]
+    fn test_empty_and_zero() {
+        let mut sketch = QuantileSketch::default();
+        assert_eq!(sketch.quantile(0.5), None);
+        sketch.add_value(f64::NAN);
+        assert_eq!(sketch.count(), 0);
+        sketch.add(0);
+        sketch.add(0);
+        sketch.add(-157);
+        assert_eq!(sketch.quantile(0.5), Some(0.0));
+        assert_eq!(sketch.quantile(0.0), Some(-15.7));
+    }
+
+    #[test]
+    fn test_parse_quantiles() {
+        assert_eq!("0.5, 0.99".parse(), Ok(Quantiles(vec![0.5, 0.99])));
+        assert!("0.5,1.5".parse::<Quantiles>().is_err());
+        assert!("median".parse::<Quantiles>().is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! Ranking queries over the aggregated stations, such as "the 10 hottest
+//! stations by mean".
+
+use crate::stddev::StationStats;
+use crate::AggregatedData;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::str::FromStr;
+
+/// The statistic by which stations are ranked.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RankBy {
+    Mean,
+    Max,
+    Min,
+    /// The spread between min and max.
+    Range,
+    /// The number of measurements.
+    Count,
+    /// The population standard deviation. It is only available for
+    /// [`crate::StdDevData`]; rankings of other stations fail with
+    /// [`UnavailableStatistic`].
+    StdDev,
+}
+
+impl RankBy {
+    /// Returns the value of the statistic for the given station, or `None` if
+    /// the station doesn't track it.
+    #[must_use]
+    pub fn value(self, stats: &impl StationStats) -> Option<f32> {
+        let data = stats.data();
+        match self {
+            Self::Mean => Some(data.avg()),
+            Self::Max => Some(data.max()),
+            Self::Min => Some(data.min()),
+            Self::Range => Some(data.range()),
+            Self::Count => Some(data.count() as f32),
+            Self::StdDev => stats.stddev().map(|stddev| stddev as f32),
+        }
+    }
+
+    fn compare<D: StationStats>(self, a: &D, b: &D) -> Ordering {
+        let (data_a, data_b) = (a.data(), b.data());
+        match self {
+            // Exact comparisons, where possible.
+            Self::Max => data_a.encoded_max().cmp(&data_b.encoded_max()),
+            Self::Min => data_a.encoded_min().cmp(&data_b.encoded_min()),
+            Self::Range => {
+                let range =
+                    |data: &AggregatedData| data.encoded_max() as i32 - data.encoded_min() as i32;
+                range(data_a).cmp(&range(data_b))
+            }
+            Self::Count => data_a.count().cmp(&data_b.count()),
+            // Cross multiplication: a.sum / a.count <=> b.sum / b.count
+            Self::Mean => (data_a.encoded_sum() as i128 * data_b.count() as i128)
+                .cmp(&(data_b.encoded_sum() as i128 * data_a.count() as i128)),
+            // Availability is checked by `Ranking::apply`.
+            Self::StdDev => a.stddev().unwrap().total_cmp(&b.stddev().unwrap()),
+        }
+    }
+}
+
+impl FromStr for RankBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" | "avg" => Ok(Self::Mean),
+            "max" => Ok(Self::Max),
+            "min" => Ok(Self::Min),
+            "range" => Ok(Self::Range),
+            "count" => Ok(Self::Count),
+            "stddev" => Ok(Self::StdDev),
+            _ => Err(format!(
+                "unknown statistic `{s}`, expected one of: mean, max, min, range, count, stddev"
+            )),
+        }
+    }
+}
+
+impl Display for RankBy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Mean => "mean",
+            Self::Max => "max",
+            Self::Min => "min",
+            Self::Range => "range",
+            Self::Count => "count",
+            Self::StdDev => "stddev",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The statistic of a [`Ranking`] isn't tracked by the stations, such as the
+/// standard deviation of [`AggregatedData`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UnavailableStatistic(pub RankBy);
+
+impl Display for UnavailableStatistic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ranking by {} is not available for these results",
+            self.0
+        )
+    }
+}
+
+impl Error for UnavailableStatistic {}
+
+impl From<UnavailableStatistic> for io::Error {
+    fn from(err: UnavailableStatistic) -> Self {
+        Self::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// The direction of a ranking.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    /// Highest values first. This is the default, as it answers questions such
+    /// as "the hottest stations".
+    #[default]
+    Descending,
+}
+
+/// A ranking query: rank by `by` in the given `order` and return at most
+/// `limit` stations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Ranking {
+    pub by: RankBy,
+    pub order: SortOrder,
+    pub limit: Option<usize>,
+}
+
+impl Ranking {
+    /// Ranks by the given statistic in descending order, without a limit.
+    #[must_use]
+    pub const fn new(by: RankBy) -> Self {
+        Self {
+            by,
+            order: SortOrder::Descending,
+            limit: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn order(mut self, order: SortOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    #[must_use]
+    pub const fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Applies the ranking to the given stations. Ties are broken by the
+    /// station name in ascending order, so that the result is deterministic.
+    ///
+    /// Fails if a station doesn't track the statistic.
+    pub fn apply<'a, S: AsRef<str> + 'a, D: StationStats + 'a>(
+        &self,
+        stations: impl IntoIterator<Item = &'a (S, D)>,
+    ) -> Result<Vec<(&'a str, &'a D)>, UnavailableStatistic> {
+        let mut ranked = stations
+            .into_iter()
+            .map(|(station, data)| (station.as_ref(), data))
+            .collect::<Vec<_>>();
+        if ranked
+            .iter()
+            .any(|(_, data)| self.by.value(*data).is_none())
+        {
+            return Err(UnavailableStatistic(self.by));
+        }
+        let limit = self.limit.unwrap_or(ranked.len()).min(ranked.len());
+
+        let compare = |(station_a, a): &(&str, &D), (station_b, b): &(&str, &D)| {
+            let ordering = self.by.compare(*a, *b);
+            let ordering = match self.order {
+                SortOrder::Ascending => ordering,
+                SortOrder::Descending => ordering.reverse(),
+            };
+            ordering.then_with(|| station_a.cmp(station_b))
+        };
+
+        // Partitioning first is cheaper than sorting everything, if only a few
+        // stations are requested.
+        if limit > 0 && limit < ranked.len() {
+            ranked.select_nth_unstable_by(limit - 1, compare);
+        }
+        ranked.truncate(limit);
+        ranked.sort_unstable_by(compare);
+        Ok(ranked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Aggregator, StdDevData};
+
+    fn data<A: Aggregator>(measurements: &[i16]) -> A {
+        let mut data = A::default();
+        measurements
+            .iter()
+            .for_each(|&measurement| data.add(measurement));
+        data
+    }
+
+    fn stations<A: Aggregator>() -> Vec<(String, A)> {
+        vec![
+            ("Berlin".to_string(), data(&[100, -157])),
+            ("Hamburg".to_string(), data(&[-127, 10, 20])),
+            ("New York".to_string(), data(&[215])),
+            ("Oslo".to_string(), data(&[-300, 300])),
+        ]
+    }
+
+    fn names<D>(ranked: &[(&str, &D)]) -> Vec<String> {
+        ranked
+            .iter()
+            .map(|(station, _)| station.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_rank_by() {
+        let stations = stations::<AggregatedData>();
+        let rank = |by| names(&Ranking::new(by).apply(&stations).unwrap());
+
+        assert_eq!(
+            rank(RankBy::Mean),
+            ["New York", "Oslo", "Berlin", "Hamburg"]
+        );
+        assert_eq!(rank(RankBy::Max), ["Oslo", "New York", "Berlin", "Hamburg"]);
+        assert_eq!(rank(RankBy::Min), ["New York", "Hamburg", "Berlin", "Oslo"]);
+        assert_eq!(
+            rank(RankBy::Range),
+            ["Oslo", "Berlin", "Hamburg", "New York"]
+        );
+        assert_eq!(
+            rank(RankBy::Count),
+            ["Hamburg", "Berlin", "Oslo", "New York"]
+        );
+
+        assert_eq!(
+            Ranking::new(RankBy::StdDev).apply(&stations),
+            Err(UnavailableStatistic(RankBy::StdDev))
+        );
+        let with_stddev = self::stations::<StdDevData>();
+        assert_eq!(
+            names(&Ranking::new(RankBy::StdDev).apply(&with_stddev).unwrap()),
+            ["Oslo", "Berlin", "Hamburg", "New York"]
+        );
+    }
+
+    #[test]
+    fn test_order_limit_and_ties() {
+        let stations = stations::<AggregatedData>();
+
+        let ranked = Ranking::new(RankBy::Mean)
+            .order(SortOrder::Ascending)
+            .limit(2)
+            .apply(&stations)
+            .unwrap();
+        assert_eq!(names(&ranked), ["Hamburg", "Berlin"]);
+
+        // Berlin and Oslo have 2 measurements each: tie broken by name
+        let ranked = Ranking::new(RankBy::Count)
+            .limit(3)
+            .apply(&stations)
+            .unwrap();
+        assert_eq!(names(&ranked), ["Hamburg", "Berlin", "Oslo"]);
+
+        let ranked = Ranking::new(RankBy::Max)
+            .limit(10)
+            .apply(&stations)
+            .unwrap();
+        assert_eq!(ranked.len(), 4);
+
+        let ranked = Ranking::new(RankBy::Max).limit(0).apply(&stations).unwrap();
+        assert!(ranked.is_empty());
+    }
+
+    #[test]
+    fn test_rank_by_from_str() {
+        assert_eq!("mean".parse(), Ok(RankBy::Mean));
+        assert_eq!("stddev".parse(), Ok(RankBy::StdDev));
+        assert!("median".parse::<RankBy>().is_err());
+        assert_eq!(RankBy::Range.to_string(), "range");
+    }
+}
@@ -0,0 +1,280 @@
+//! The owned, sorted result of a run, and everything that is done with it
+//! afterwards: lookups, rankings, merging partial results, writing it in the
+//! output formats, and snapshots.
+
+use crate::output::{self, OutputOptions};
+use crate::quality::QualityReport;
+use crate::ranking::{Ranking, UnavailableStatistic};
+use crate::AggregatedData;
+#[cfg(feature = "arrow")]
+use arrow_array::RecordBatch;
+#[cfg(feature = "arrow")]
+use arrow_schema::SchemaRef;
+use std::cmp::Ordering;
+use std::io::{self, Read, Write};
+
+/// The final result of a run: the aggregated data per station, sorted by
+/// station name.
+///
+/// In contrast to the intermediate per-thread results, this owns the station
+/// names, so it outlives the memory-mapped file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Results {
+    stations: Vec<(String, AggregatedData)>,
+    /// Only set by the validating parser.
+    quality: Option<QualityReport>,
+}
+
+impl Results {
+    /// Creates the results from the reduced and sorted per-station data.
+    pub(crate) fn from_sorted<'a>(
+        stations: impl IntoIterator<Item = (&'a str, AggregatedData)>,
+    ) -> Self {
+        let stations = stations
+            .into_iter()
+            .map(|(station, data)| (station.to_string(), data))
+            .collect::<Vec<_>>();
+        debug_assert!(stations.is_sorted_by(|(a, _), (b, _)| a < b));
+        Self {
+            stations,
+            quality: None,
+        }
+    }
+
+    /// Like [`Self::from_sorted`], with owned station names.
+    pub(crate) fn from_owned_sorted(stations: Vec<(String, AggregatedData)>) -> Self {
+        debug_assert!(stations.is_sorted_by(|(a, _), (b, _)| a < b));
+        Self {
+            stations,
+            quality: None,
+        }
+    }
+
+    /// Like [`Self::from_owned_sorted`], but for stations of untrusted input,
+    /// such as the partial results of a worker. `None` unless the station
+    /// names are sorted and unique.
+    pub(crate) fn try_from_owned_sorted(stations: Vec<(String, AggregatedData)>) -> Option<Self> {
+        stations
+            .is_sorted_by(|(a, _), (b, _)| a < b)
+            .then(|| Self::from_owned_sorted(stations))
+    }
+
+    /// Attaches the report of the validating parser.
+    pub(crate) fn with_quality(mut self, report: QualityReport) -> Self {
+        self.quality = Some(report);
+        self
+    }
+
+    /// The data quality report, if the input was validated. See
+    /// [`crate::aggregate_validated`].
+    #[must_use]
+    pub const fn quality(&self) -> Option<&QualityReport> {
+        self.quality.as_ref()
+    }
+
+    /// All stations, sorted by name.
+    #[must_use]
+    pub fn stations(&self) -> &[(String, AggregatedData)] {
+        &self.stations
+    }
+
+    /// Returns the data of the given station, if it exists.
+    #[must_use]
+    pub fn get(&self, station: &str) -> Option<&AggregatedData> {
+        self.stations
+            .binary_search_by(|(name, _)| name.as_str().cmp(station))
+            .ok()
+            .map(|index| &self.stations[index].1)
+    }
+
+    /// Merges the results of another part of the file into these, as
+    /// returned by [`crate::aggregate_range`].
+    ///
+    /// Data quality reports are not merged; the merged results have none.
+    pub fn merge(&mut self, other: Self) {
+        let mut stations = Vec::with_capacity(self.stations.len().max(other.stations.len()));
+        let mut own = std::mem::take(&mut self.stations).into_iter().peekable();
+        let mut other = other.stations.into_iter().peekable();
+        // Both are sorted by station name: merge them like in a merge sort.
+        loop {
+            let next = match (own.peek(), other.peek()) {
+                (Some((a, _)), Some((b, _))) => match a.cmp(b) {
+                    Ordering::Less => own.next(),
+                    Ordering::Greater => other.next(),
+                    Ordering::Equal => {
+                        let (station, mut data) = own.next().unwrap();
+                        data.merge(&other.next().unwrap().1);
+                        Some((station, data))
+                    }
+                },
+                (Some(_), None) => own.next(),
+                (None, _) => other.next(),
+            };
+            match next {
+                Some(entry) => stations.push(entry),
+                None => break,
+            }
+        }
+        self.stations = stations;
+        self.quality = None;
+    }
+
+    /// Ranks the stations according to the query. Fails for
+    /// [`crate::RankBy::StdDev`], as the results don't track the standard deviation.
+    pub fn rank(
+        &self,
+        ranking: &Ranking,
+    ) -> Result<Vec<(&str, &AggregatedData)>, UnavailableStatistic> {
+        ranking.apply(&self.stations)
+    }
+
+    /// Writes the results according to the options.
+    pub fn write(&self, writer: &mut impl Write, options: &OutputOptions) -> io::Result<()> {
+        output::write_stations(writer, &self.stations, options)
+    }
+
+    /// Converts the results into an Arrow [`RecordBatch`], with the ranking
+    /// and the unit of the options (the format is ignored). The columns are
+    /// `station`, `min`, `mean`, `max`, and `count`; see [`Self::arrow_schema`].
+    /// Fails like [`Self::rank`].
+    #[cfg(feature = "arrow")]
+    pub fn to_record_batch(
+        &self,
+        options: &OutputOptions,
+    ) -> Result<RecordBatch, UnavailableStatistic> {
+        Ok(match &options.ranking {
+            None => output::record_batch(
+                self.stations
+                    .iter()
+                    .map(|(station, data)| (station.as_str(), data)),
+                options.unit,
+            ),
+            Some(ranking) => output::record_batch(self.rank(ranking)?.into_iter(), options.unit),
+        })
+    }
+
+    /// The schema of [`Self::to_record_batch`].
+    #[cfg(feature = "arrow")]
+    #[must_use]
+    pub fn arrow_schema() -> SchemaRef {
+        output::arrow_schema()
+    }
+
+    /// Writes the results as a Parquet file with the columns of
+    /// [`Self::to_record_batch`].
+    #[cfg(feature = "arrow")]
+    pub fn write_parquet(
+        &self,
+        writer: impl Write + Send,
+        options: &OutputOptions,
+    ) -> io::Result<()> {
+        output::write_parquet(writer, &self.to_record_batch(options)?)
+    }
+
+    /// Writes a snapshot of the results that can be loaded again with
+    /// [`Self::read_snapshot`]. In contrast to [`Self::write`], the snapshot
+    /// contains the exact encoded values, so loaded snapshots can be merged
+    /// with further results without losing precision.
+    pub fn write_snapshot(&self, writer: &mut impl Write) -> io::Result<()> {
+        serde_json::to_writer(&mut *writer, &self.stations)?;
+        writeln!(writer)
+    }
+
+    /// Reads a snapshot written by [`Self::write_snapshot`].
+    pub fn read_snapshot(reader: impl Read) -> io::Result<Self> {
+        let mut stations: Vec<(String, AggregatedData)> = serde_json::from_reader(reader)?;
+        stations.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Self::try_from_owned_sorted(stations).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "duplicate station in snapshot")
+        })
+    }
+
+    /// Prints the results according to the options to stdout.
+    pub fn print(&self, options: &OutputOptions) {
+        self.write(&mut io::stdout().lock(), options).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ranking::RankBy;
+
+    #[test]
+    fn test_get_and_rank() {
+        let mut berlin = AggregatedData::default();
+        berlin.add_datapoint(100);
+        let mut hamburg = AggregatedData::default();
+        hamburg.add_datapoint(-127);
+        let results = Results::from_sorted([("Berlin", berlin.clone()), ("Hamburg", hamburg)]);
+
+        assert_eq!(results.get("Berlin"), Some(&berlin));
+        assert_eq!(results.get("Bern"), None);
+
+        let ranked = results.rank(&Ranking::new(RankBy::Min).limit(1));
+        assert_eq!(ranked, Ok(vec![("Berlin", &berlin)]));
+        assert_eq!(
+            results.rank(&Ranking::new(RankBy::StdDev)),
+            Err(UnavailableStatistic(RankBy::StdDev))
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let data = |measurement| {
+            let mut data = AggregatedData::default();
+            data.add_datapoint(measurement);
+            data
+        };
+        let mut results = Results::from_sorted([("Berlin", data(100)), ("Oslo", data(-10))]);
+        results.merge(Results::from_sorted([
+            ("Aachen", data(50)),
+            ("Berlin", data(-100)),
+            ("Zurich", data(0)),
+        ]));
+
+        let stations = results
+            .stations()
+            .iter()
+            .map(|(station, _)| station.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(stations, ["Aachen", "Berlin", "Oslo", "Zurich"]);
+        assert_eq!(
+            results.get("Berlin"),
+            Some(&AggregatedData::new(-100, 100, 0, 2))
+        );
+
+        let mut empty = Results::default();
+        empty.merge(results.clone());
+        assert_eq!(empty, results);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let results = Results::from_sorted([
+            ("Berlin", AggregatedData::new(-100, 100, 0, 2)),
+            ("Oslo", AggregatedData::new(-10, -10, -10, 1)),
+        ]);
+        let mut snapshot = Vec::new();
+        results.write_snapshot(&mut snapshot).unwrap();
+        assert_eq!(
+            Results::read_snapshot(snapshot.as_slice()).unwrap(),
+            results
+        );
+
+        let duplicate = r#"[["Oslo",{"min":1,"max":1,"sum":1,"sample_count":1}],
+            ["Oslo",{"min":1,"max":1,"sum":1,"sample_count":1}]]"#;
+        assert!(Results::read_snapshot(duplicate.as_bytes()).is_err());
+
+        for invalid in [
+            r#"[["Oslo",{"min":1,"max":1,"sum":0,"sample_count":0}]]"#,
+            r#"[["Oslo",{"min":2,"max":1,"sum":3,"sample_count":2}]]"#,
+            r#"[["Oslo",{"min":1,"max":2,"sum":5,"sample_count":2}]]"#,
+        ] {
+            assert!(
+                Results::read_snapshot(invalid.as_bytes()).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+}
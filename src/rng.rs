@@ -0,0 +1,72 @@
+//! A small, seedable pseudo random number generator. The quality is good
+//! enough for generating test data and for sampling, but it is not suited for
+//! cryptographic purposes.
+
+/// [SplitMix64](https://prng.di.unimi.it/splitmix64.c).
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub const fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a uniformly distributed number in `0..bound`.
+    pub const fn next_below(&mut self, bound: u64) -> u64 {
+        // Lemire's multiply-shift method. The bias is negligible for our
+        // purposes.
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+
+    /// Returns a uniformly distributed number in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// Returns a normally distributed number (Box-Muller transform).
+    pub fn next_gaussian(&mut self, mean: f64, stddev: f64) -> f64 {
+        // 1.0 - x: the logarithm must not be applied to 0
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+        mean + z * stddev
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic() {
+        let mut a = SplitMix64::new(42);
+        let mut b = SplitMix64::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn test_distributions() {
+        let mut rng = SplitMix64::new(1);
+        let n = 100_000;
+
+        assert!((0..n).all(|_| rng.next_below(7) < 7));
+        assert!((0..n)
+            .map(|_| rng.next_f64())
+            .all(|x| (0.0..1.0).contains(&x)));
+
+        let mean = (0..n).map(|_| rng.next_gaussian(20.0, 10.0)).sum::<f64>() / n as f64;
+        assert!((mean - 20.0).abs() < 0.2, "{mean}");
+    }
+}
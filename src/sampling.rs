@@ -0,0 +1,323 @@
+//! Approximate results from a random sample of the file.
+//!
+//! The file is divided into blocks of [`SamplingOptions::block_size`] bytes.
+//! A random subset of the blocks is processed, each snapped to line
+//! boundaries like the chunks of [`phips_1brc_core::ChunkIter`]. The means
+//! per station are reported with a confidence interval.
+//!
+//! The confidence intervals assume that the lines are in random order, as in
+//! generated data. If the file is sorted, e.g., by time or by station, the
+//! lines of a block are correlated and the intervals are too narrow.
+//!
+//! As the sample is drawn without replacement, the intervals include the
+//! finite population correction. The share of the lines of a station that
+//! were sampled is unknown without reading the whole file, so the share of
+//! the sampled bytes is used instead. That is exact if the lines of all
+//! stations are similarly long, and the same assumption as for
+//! [`StationEstimate::estimated_count`].
+
+use crate::rng::SplitMix64;
+use crate::stddev::StdDevData;
+use phips_1brc_core::snap_to_lines;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// The confidence level of the intervals of a [`SampleReport`].
+pub const CONFIDENCE_LEVEL: f64 = 0.95;
+
+/// The quantile of the standard normal distribution for [`CONFIDENCE_LEVEL`].
+const Z_SCORE: f64 = 1.959_963_984_540_054;
+
+/// The share of the bytes of a file to sample, within `(0, 1]`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SamplingFraction(f64);
+
+impl SamplingFraction {
+    /// Returns `None` if the fraction is not within `(0, 1]`.
+    #[must_use]
+    pub fn new(fraction: f64) -> Option<Self> {
+        (fraction > 0.0 && fraction <= 1.0).then_some(Self(fraction))
+    }
+
+    #[must_use]
+    pub const fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl Default for SamplingFraction {
+    fn default() -> Self {
+        Self(0.01)
+    }
+}
+
+impl FromStr for SamplingFraction {
+    type Err = String;
+
+    /// Parses a fraction (`0.01`) or a percentage (`1%`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let fraction = match s.strip_suffix('%') {
+            Some(percentage) => percentage.trim().parse::<f64>().map(|p| p / 100.0),
+            None => s.parse::<f64>(),
+        };
+        fraction
+            .ok()
+            .and_then(Self::new)
+            .ok_or_else(|| format!("invalid fraction `{s}`, expected a number within (0, 1]"))
+    }
+}
+
+/// Options for [`crate::aggregate_sampled`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingOptions {
+    /// The share of the bytes to process. Due to the block granularity, the
+    /// actual share may be slightly larger.
+    pub fraction: SamplingFraction,
+    /// The seed of the random selection of the blocks. The same seed selects
+    /// the same blocks of the same file.
+    pub seed: u64,
+    /// The size of the sampled blocks in bytes. Smaller blocks give a more
+    /// even sample, larger blocks make better use of sequential reads.
+    pub block_size: usize,
+}
+
+impl Default for SamplingOptions {
+    fn default() -> Self {
+        Self {
+            fraction: SamplingFraction::default(),
+            seed: 0,
+            block_size: 1024 * 1024,
+        }
+    }
+}
+
+/// The estimate for a single station.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StationEstimate {
+    pub station: String,
+    /// The mean of the sampled measurements.
+    pub mean: f64,
+    /// The half width of the confidence interval of the mean. `None` if less
+    /// than two measurements were sampled, as their spread is unknown.
+    pub margin: Option<f64>,
+    /// The minimum of the sampled measurements. The true minimum may be lower.
+    pub min: f64,
+    /// The maximum of the sampled measurements. The true maximum may be higher.
+    pub max: f64,
+    /// The amount of sampled measurements.
+    pub sampled: u32,
+    /// The estimated amount of measurements in the whole file.
+    pub estimated_count: f64,
+}
+
+impl StationEstimate {
+    fn new(station: String, stats: &StdDevData, fraction: f64) -> Self {
+        let data = stats.data();
+        let sampled = data.count();
+        // The sample is drawn without replacement: finite population
+        // correction, with the byte fraction (see the module documentation).
+        let standard_error = stats
+            .sample_stddev_f64()
+            .map(|stddev| stddev / (sampled as f64).sqrt() * (1.0 - fraction).max(0.0).sqrt());
+        Self {
+            station,
+            mean: data.encoded_sum() as f64 / sampled as f64 / 10.0,
+            margin: standard_error.map(|standard_error| Z_SCORE * standard_error),
+            min: data.min() as f64,
+            max: data.max() as f64,
+            sampled,
+            estimated_count: sampled as f64 / fraction,
+        }
+    }
+
+    /// The confidence interval of the mean, if there is one.
+    #[must_use]
+    pub fn interval(&self) -> Option<Range<f64>> {
+        self.margin
+            .map(|margin| self.mean - margin..self.mean + margin)
+    }
+}
+
+/// The result of [`crate::aggregate_sampled`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SampleReport {
+    /// The share of the bytes that were actually processed.
+    pub fraction: f64,
+    pub sampled_bytes: usize,
+    pub total_bytes: usize,
+    /// The amount of sampled blocks.
+    pub blocks: usize,
+    pub confidence_level: f64,
+    /// The estimates, sorted by station name.
+    pub stations: Vec<StationEstimate>,
+}
+
+impl SampleReport {
+    pub(crate) fn new(
+        stations: &[(&str, StdDevData)],
+        sampled_bytes: usize,
+        total_bytes: usize,
+        blocks: usize,
+    ) -> Self {
+        let fraction = if total_bytes == 0 {
+            1.0
+        } else {
+            sampled_bytes as f64 / total_bytes as f64
+        };
+        let stations = stations
+            .iter()
+            .map(|(station, stats)| StationEstimate::new(station.to_string(), stats, fraction))
+            .collect();
+        Self {
+            fraction,
+            sampled_bytes,
+            total_bytes,
+            blocks,
+            confidence_level: CONFIDENCE_LEVEL,
+            stations,
+        }
+    }
+
+    /// Returns the estimate of the given station, if it was sampled.
+    #[must_use]
+    pub fn get(&self, station: &str) -> Option<&StationEstimate> {
+        self.stations
+            .binary_search_by(|estimate| estimate.station.as_str().cmp(station))
+            .ok()
+            .map(|index| &self.stations[index])
+    }
+}
+
+impl Display for SampleReport {
+    /// Formats the estimates like the regular output, with the margin of the
+    /// mean, if there is one: `{Abha=-23.0/18.0±0.2/59.2, Jos=5.0/5.0/5.0}`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let stations = self
+            .stations
+            .iter()
+            .map(|estimate| {
+                let margin = estimate
+                    .margin
+                    .map(|margin| format!("±{margin:.1}"))
+                    .unwrap_or_default();
+                format!(
+                    "{}={:.1}/{:.1}{margin}/{:.1}",
+                    estimate.station, estimate.min, estimate.mean, estimate.max
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(f, "{{{}}}", stations.join(", "))?;
+        write!(
+            f,
+            "(sampled {:.2}% of {} bytes in {} blocks, {:.0}% confidence intervals)",
+            self.fraction * 100.0,
+            self.total_bytes,
+            self.blocks,
+            self.confidence_level * 100.0
+        )
+    }
+}
+
+/// Selects the blocks to sample, snapped to line boundaries and in file
+/// order. Empty blocks (without a beginning line) are omitted.
+pub(crate) fn select_blocks(bytes: &[u8], options: &SamplingOptions) -> Vec<Range<usize>> {
+    assert!(options.block_size > 0, "block size must not be zero");
+    let block_count = bytes.len().div_ceil(options.block_size);
+    let sampled_count = ((block_count as f64 * options.fraction.get()).ceil() as usize)
+        .clamp(block_count.min(1), block_count);
+
+    // Partial Fisher-Yates shuffle: the first `sampled_count` blocks are a
+    // uniformly random subset.
+    let mut rng = SplitMix64::new(options.seed);
+    let mut indices = (0..block_count).collect::<Vec<_>>();
+    for i in 0..sampled_count {
+        let j = i + rng.next_below((block_count - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+    let mut indices = indices[..sampled_count].to_vec();
+    indices.sort_unstable();
+
+    indices
+        .into_iter()
+        .map(|index| {
+            let start = index * options.block_size;
+            let end = (start + options.block_size).min(bytes.len());
+            snap_to_lines(bytes, start..end)
+        })
+        .filter(|range| !range.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Aggregator;
+
+    #[test]
+    fn test_parse_fraction() {
+        assert_eq!("0.25".parse(), Ok(SamplingFraction(0.25)));
+        assert_eq!("5%".parse(), Ok(SamplingFraction(0.05)));
+        assert_eq!("1".parse(), Ok(SamplingFraction(1.0)));
+        assert!("0".parse::<SamplingFraction>().is_err());
+        assert!("150%".parse::<SamplingFraction>().is_err());
+        assert!("some".parse::<SamplingFraction>().is_err());
+    }
+
+    #[test]
+    fn test_select_blocks() {
+        let data = "Berlin;10.0\n".repeat(100);
+        let options = SamplingOptions {
+            fraction: SamplingFraction(0.1),
+            seed: 1,
+            block_size: 50,
+        };
+        let blocks = select_blocks(data.as_bytes(), &options);
+        // 24 blocks, of which 3 are sampled.
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks.is_sorted_by(|a, b| a.end <= b.start));
+        for block in &blocks {
+            assert_eq!(block.start % 12, 0, "{block:?}");
+            assert_eq!(block.end % 12, 0, "{block:?}");
+        }
+        assert_eq!(blocks, select_blocks(data.as_bytes(), &options));
+
+        // All blocks cover the whole file.
+        let options = SamplingOptions {
+            fraction: SamplingFraction(1.0),
+            ..options
+        };
+        let blocks = select_blocks(data.as_bytes(), &options);
+        assert_eq!(blocks.first().unwrap().start, 0);
+        assert_eq!(blocks.last().unwrap().end, data.len());
+        assert!(blocks.windows(2).all(|pair| pair[0].end == pair[1].start));
+    }
+
+    #[test]
+    fn test_report() {
+        let mut berlin = StdDevData::default();
+        berlin.add(-100);
+        berlin.add(100);
+        let mut oslo = StdDevData::default();
+        oslo.add(50);
+        let report = SampleReport::new(&[("Berlin", berlin), ("Oslo", oslo)], 10, 100, 1);
+        assert_eq!(report.fraction, 0.1);
+        let berlin = report.get("Berlin").unwrap();
+        assert_eq!(berlin.mean, 0.0);
+        assert_eq!(berlin.estimated_count, 20.0);
+        // Sample stddev 10 * sqrt(2), divided by sqrt(2) for two samples,
+        // finite population correction of 0.9.
+        let expected = Z_SCORE * 10.0 * 0.9_f64.sqrt();
+        assert!((berlin.margin.unwrap() - expected).abs() < 1e-9);
+        // A single measurement has no interval.
+        assert_eq!(report.get("Oslo").unwrap().margin, None);
+        assert_eq!(report.get("Oslo").unwrap().interval(), None);
+        assert_eq!(
+            report.to_string(),
+            "{Berlin=-10.0/0.0±18.6/10.0, Oslo=5.0/5.0/5.0}\n\
+            (sampled 10.00% of 100 bytes in 1 blocks, 95% confidence intervals)"
+        );
+    }
+}
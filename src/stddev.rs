@@ -0,0 +1,126 @@
+//! The standard deviation per station.
+//!
+//! It requires the sum of the squared measurements, which [`AggregatedData`]
+//! doesn't track: the hot loop stays as lean as possible and the data per
+//! station fits into 16 bytes. [`StdDevData`] is an opt-in [`Aggregator`]
+//! that additionally tracks the squares, for rankings by the standard
+//! deviation and for the confidence intervals of the sampling mode.
+
+use crate::aggregator::Aggregator;
+use crate::AggregatedData;
+
+/// [`AggregatedData`] plus the sum of the squared measurements.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StdDevData {
+    data: AggregatedData,
+    /// Sum of the squared (encoded) measurements.
+    sum_of_squares: u64,
+}
+
+impl StdDevData {
+    /// The regular statistics (min, mean, max, count).
+    #[must_use]
+    pub const fn data(&self) -> &AggregatedData {
+        &self.data
+    }
+
+    /// The population standard deviation.
+    #[must_use]
+    pub fn stddev(&self) -> f32 {
+        self.stddev_f64() as f32
+    }
+
+    /// Like [`Self::stddev`], but with the full precision of the calculation.
+    #[must_use]
+    pub fn stddev_f64(&self) -> f64 {
+        let count = self.data.count() as f64;
+        let mean = self.data.encoded_sum() as f64 / count;
+        let variance = self.sum_of_squares as f64 / count - mean * mean;
+        // max(0): rounding errors must not lead to NaN
+        variance.max(0.0).sqrt() / 10.0
+    }
+
+    /// The sample standard deviation (with Bessel's correction), which
+    /// estimates the standard deviation of the population the measurements
+    /// were drawn from. `None` for less than two measurements.
+    #[must_use]
+    pub fn sample_stddev_f64(&self) -> Option<f64> {
+        let count = self.data.count() as f64;
+        (count >= 2.0).then(|| self.stddev_f64() * (count / (count - 1.0)).sqrt())
+    }
+}
+
+impl Aggregator for StdDevData {
+    type Output = Self;
+
+    #[inline(always)]
+    fn add(&mut self, measurement: i16) {
+        self.data.add_datapoint(measurement);
+        self.sum_of_squares += (measurement as i32 * measurement as i32) as u64;
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.data.merge(&other.data);
+        self.sum_of_squares += other.sum_of_squares;
+    }
+
+    fn finish(self) -> Self::Output {
+        self
+    }
+}
+
+/// The statistics of a station that can be ranked and written: either
+/// [`AggregatedData`] or [`StdDevData`].
+pub trait StationStats {
+    fn data(&self) -> &AggregatedData;
+
+    /// The population standard deviation in degrees Celsius, if it was
+    /// tracked.
+    fn stddev(&self) -> Option<f64>;
+}
+
+impl StationStats for AggregatedData {
+    fn data(&self) -> &AggregatedData {
+        self
+    }
+
+    fn stddev(&self) -> Option<f64> {
+        None
+    }
+}
+
+impl StationStats for StdDevData {
+    fn data(&self) -> &AggregatedData {
+        &self.data
+    }
+
+    fn stddev(&self) -> Option<f64> {
+        Some(self.stddev_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stddev() {
+        let mut data = StdDevData::default();
+        [20, 40, 40, 40, 50, 50, 70, 90]
+            .into_iter()
+            .for_each(|measurement| data.add(measurement));
+        assert_eq!(data.stddev(), 2.0);
+        assert_eq!(data.data().count(), 8);
+        let sample_stddev = data.sample_stddev_f64().unwrap();
+        assert!((sample_stddev - 2.0 * (8.0_f64 / 7.0).sqrt()).abs() < 1e-9);
+
+        let mut other = StdDevData::default();
+        other.add(-157);
+        assert_eq!(other.stddev(), 0.0);
+        assert_eq!(other.sample_stddev_f64(), None);
+
+        data.merge(other);
+        assert_eq!(data.data().count(), 9);
+        assert!((data.stddev_f64() - 6.773_149_6).abs() < 1e-6);
+    }
+}
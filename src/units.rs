@@ -0,0 +1,206 @@
+//! Conversion of the output into other temperature units.
+//!
+//! All measurements are internally encoded as integers multiplied by ten
+//! (tenths of a degree Celsius). The conversion into another unit is performed
+//! on the exact rational value, which is rounded to one decimal place only
+//! once. Converting the already rounded Celsius value would round twice and
+//! could be off by one in the last digit.
+
+use crate::stddev::StationStats;
+use crate::AggregatedData;
+use phips_1brc_core::round_half_up;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The unit in which temperatures are printed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    /// The unit of the input data.
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    /// Converts the exact temperature `numerator / denominator` (in tenths of a
+    /// degree Celsius) into tenths of this unit and rounds it to an integer.
+    ///
+    /// If `with_offset` is false, only the scale of the unit is applied. This is
+    /// required for differences, such as the range.
+    fn convert(self, numerator: i128, denominator: i128, with_offset: bool) -> EncodedDecimal {
+        debug_assert!(denominator > 0);
+        let (numerator, denominator) = match self {
+            Self::Celsius => (numerator, denominator),
+            // F = C * 9/5 + 32
+            Self::Fahrenheit => {
+                let offset = if with_offset {
+                    320 * 5 * denominator
+                } else {
+                    0
+                };
+                (numerator * 9 + offset, denominator * 5)
+            }
+            // K = C + 273.15
+            Self::Kelvin => {
+                let offset = if with_offset { 5463 * denominator } else { 0 };
+                (numerator * 2 + offset, denominator * 2)
+            }
+        };
+        EncodedDecimal(round_half_up(numerator, denominator) as i64)
+    }
+
+    #[must_use]
+    pub fn min(self, data: &AggregatedData) -> EncodedDecimal {
+        self.convert(data.encoded_min() as i128, 1, true)
+    }
+
+    #[must_use]
+    pub fn max(self, data: &AggregatedData) -> EncodedDecimal {
+        self.convert(data.encoded_max() as i128, 1, true)
+    }
+
+    #[must_use]
+    pub fn mean(self, data: &AggregatedData) -> EncodedDecimal {
+        self.convert(data.encoded_sum() as i128, data.count() as i128, true)
+    }
+
+    /// The difference between max and min.
+    #[must_use]
+    pub fn range(self, data: &AggregatedData) -> EncodedDecimal {
+        let range = data.encoded_max() as i128 - data.encoded_min() as i128;
+        self.convert(range, 1, false)
+    }
+
+    /// The standard deviation, if it was tracked. As it is irrational in
+    /// general, this is not rounded.
+    #[must_use]
+    pub fn stddev(self, stats: &impl StationStats) -> Option<f64> {
+        let scale = match self {
+            Self::Celsius | Self::Kelvin => 1.0,
+            Self::Fahrenheit => 9.0 / 5.0,
+        };
+        stats.stddev().map(|stddev| stddev * scale)
+    }
+
+    /// The symbol of the unit, such as `°C`.
+    #[must_use]
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            Self::Celsius => "°C",
+            Self::Fahrenheit => "°F",
+            Self::Kelvin => "K",
+        }
+    }
+}
+
+impl FromStr for TemperatureUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "c" | "celsius" => Ok(Self::Celsius),
+            "f" | "fahrenheit" => Ok(Self::Fahrenheit),
+            "k" | "kelvin" => Ok(Self::Kelvin),
+            _ => Err(format!(
+                "unknown unit `{s}`, expected one of: celsius, fahrenheit, kelvin"
+            )),
+        }
+    }
+}
+
+/// A number with exactly one decimal place, encoded as integer multiplied
+/// by 10.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EncodedDecimal(pub i64);
+
+impl EncodedDecimal {
+    #[must_use]
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / 10.0
+    }
+}
+
+impl Display for EncodedDecimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{}", abs / 10, abs % 10)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Aggregator, StdDevData};
+
+    fn data(measurements: &[i16]) -> AggregatedData {
+        let mut data = AggregatedData::default();
+        measurements
+            .iter()
+            .for_each(|&measurement| data.add_datapoint(measurement));
+        data
+    }
+
+    #[test]
+    fn test_encoded_decimal_display() {
+        assert_eq!(EncodedDecimal(0).to_string(), "0.0");
+        assert_eq!(EncodedDecimal(5).to_string(), "0.5");
+        assert_eq!(EncodedDecimal(-5).to_string(), "-0.5");
+        assert_eq!(EncodedDecimal(-157).to_string(), "-15.7");
+        assert_eq!(EncodedDecimal(2731).to_string(), "273.1");
+    }
+
+    #[test]
+    fn test_convert() {
+        let data = data(&[-157, 100, 215]);
+
+        let unit = TemperatureUnit::Fahrenheit;
+        // -15.7 °C = 3.74 °F
+        assert_eq!(unit.min(&data).to_string(), "3.7");
+        // 21.5 °C = 70.7 °F
+        assert_eq!(unit.max(&data).to_string(), "70.7");
+        // 5.2666 °C = 41.48 °F
+        assert_eq!(unit.mean(&data).to_string(), "41.5");
+        // A range of 37.2 °C is a range of 66.96 °F
+        assert_eq!(unit.range(&data).to_string(), "67.0");
+
+        let unit = TemperatureUnit::Kelvin;
+        // -15.7 °C = 257.45 K: rounded up
+        assert_eq!(unit.min(&data).to_string(), "257.5");
+        assert_eq!(unit.max(&data).to_string(), "294.7");
+        assert_eq!(unit.mean(&data).to_string(), "278.4");
+        assert_eq!(unit.range(&data).to_string(), "37.2");
+
+        let unit = TemperatureUnit::Celsius;
+        assert_eq!(unit.min(&data).to_string(), "-15.7");
+        assert_eq!(unit.mean(&data).to_string(), "5.3");
+    }
+
+    #[test]
+    fn test_no_double_rounding() {
+        // Mean: 0.05 °C. Rounding it to 0.1 °C first would result in 32.2 °F.
+        let data = data(&[0, 1]);
+        assert_eq!(TemperatureUnit::Fahrenheit.mean(&data).to_string(), "32.1");
+    }
+
+    #[test]
+    fn test_stddev() {
+        let mut stats = StdDevData::default();
+        [20, 40, 40, 40, 50, 50, 70, 90]
+            .into_iter()
+            .for_each(|measurement| stats.add(measurement));
+        assert_eq!(TemperatureUnit::Celsius.stddev(&stats), Some(2.0));
+        assert_eq!(TemperatureUnit::Kelvin.stddev(&stats), Some(2.0));
+        let fahrenheit = TemperatureUnit::Fahrenheit.stddev(&stats).unwrap();
+        assert!((fahrenheit - 3.6).abs() < 1e-9);
+        assert_eq!(TemperatureUnit::Celsius.stddev(stats.data()), None);
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("F".parse(), Ok(TemperatureUnit::Fahrenheit));
+        assert_eq!("kelvin".parse(), Ok(TemperatureUnit::Kelvin));
+        assert!("rankine".parse::<TemperatureUnit>().is_err());
+    }
+}
@@ -0,0 +1,520 @@
+//! Time-window aggregation for data sets where each record carries a
+//! timestamp: `<station>;<measurement>;<timestamp>\n`.
+//!
+//! The timestamp is either a (possibly negative) number of seconds since the
+//! Unix epoch or a timezone-free ISO-8601 date-time such as
+//! `2024-03-01T13:37:00` (an optional trailing `Z` is accepted). All times are
+//! interpreted as UTC.
+
+use crate::hash::HashMap;
+use crate::output::{self, OutputFormat, StationRecord};
+use crate::units::TemperatureUnit;
+use crate::AggregatedData;
+use phips_1brc_core::data_set_properties::{
+    MIN_MEASUREMENT_LEN, MIN_STATION_LEN, STATIONS_IN_DATASET,
+};
+use phips_1brc_core::fast_f32_parse_encoded;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::num::NonZeroU32;
+use std::str::{from_utf8_unchecked, FromStr};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// The width of a time bucket.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BucketWidth {
+    /// Fixed-size buckets with the given width in seconds. Buckets are aligned
+    /// to the Unix epoch.
+    Seconds(NonZeroU32),
+    /// One bucket per hour.
+    Hour,
+    /// One bucket per (UTC) day.
+    Day,
+    /// One bucket per calendar month.
+    Month,
+}
+
+impl BucketWidth {
+    /// Returns the start of the bucket (seconds since the Unix epoch) the
+    /// given timestamp belongs to.
+    #[must_use]
+    pub const fn bucket_start(self, timestamp: i64) -> i64 {
+        let width = match self {
+            Self::Seconds(width) => width.get() as i64,
+            Self::Hour => 60 * 60,
+            Self::Day => SECONDS_PER_DAY,
+            Self::Month => {
+                let (year, month, _) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+                return days_from_civil(year, month, 1) * SECONDS_PER_DAY;
+            }
+        };
+        timestamp.div_euclid(width) * width
+    }
+}
+
+impl FromStr for BucketWidth {
+    type Err = String;
+
+    /// Parses `hour`, `day`, `month`, or a width in seconds such as `900` or
+    /// `900s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(Self::Hour),
+            "day" => Ok(Self::Day),
+            "month" => Ok(Self::Month),
+            _ => s
+                .strip_suffix('s')
+                .unwrap_or(s)
+                .parse()
+                .map(Self::Seconds)
+                .map_err(|_| {
+                    format!("invalid bucket width `{s}`, expected hour, day, month, or seconds")
+                }),
+        }
+    }
+}
+
+/// Per-thread result of [`process_file_chunk`]: the aggregated data per
+/// `(station, bucket start)`.
+pub type WindowedStats<'a> = HashMap<(&'a str, i64), AggregatedData>;
+
+/// Windowed counterpart of [`crate::process_file_chunk`]. Each line must have
+/// the form `<station>;<measurement>;<timestamp>\n`.
+///
+/// The returned data structure is not sorted.
+pub fn process_file_chunk(bytes: &[u8], width: BucketWidth) -> WindowedStats<'_> {
+    assert!(!bytes.is_empty());
+    let &last_byte = bytes.last().unwrap();
+    assert_eq!(last_byte, b'\n');
+
+    let mut stats = HashMap::with_capacity_and_hasher(STATIONS_IN_DATASET, Default::default());
+
+    let mut consumed_bytes_count = 0;
+    while consumed_bytes_count < bytes.len() {
+        let remaining_bytes = unsafe { bytes.get_unchecked(consumed_bytes_count..) };
+        let (station, measurement, timestamp) =
+            process_line(remaining_bytes, &mut consumed_bytes_count);
+        let bucket = width.bucket_start(timestamp);
+        stats
+            .entry((station, bucket))
+            .or_insert_with(AggregatedData::default)
+            .add_datapoint(measurement);
+    }
+    stats
+}
+
+/// Reads a line of the form `<station>;<measurement>;<timestamp>\n`. Works
+/// like [`phips_1brc_core::process_line`].
+#[inline(always)]
+fn process_line<'a>(bytes: &'a [u8], consumed_bytes_count: &mut usize) -> (&'a str, i16, i64) {
+    let search_offset = MIN_STATION_LEN;
+    let station_delimiter = memchr::memchr(b';', unsafe { bytes.get_unchecked(search_offset..) })
+        .map(|pos| pos + search_offset)
+        .unwrap();
+    let search_offset = station_delimiter + 1 + MIN_MEASUREMENT_LEN;
+    let measurement_delimiter =
+        memchr::memchr(b';', unsafe { bytes.get_unchecked(search_offset..) })
+            .map(|pos| pos + search_offset)
+            .expect("line must contain a timestamp column");
+    let search_offset = measurement_delimiter + 1;
+    let newline = memchr::memchr(b'\n', unsafe { bytes.get_unchecked(search_offset..) })
+        .map(|pos| pos + search_offset)
+        .unwrap();
+
+    let station = unsafe { from_utf8_unchecked(bytes.get_unchecked(0..station_delimiter)) };
+    let measurement = unsafe {
+        from_utf8_unchecked(bytes.get_unchecked(station_delimiter + 1..measurement_delimiter))
+    };
+    let timestamp = unsafe { bytes.get_unchecked(measurement_delimiter + 1..newline) };
+
+    let measurement = unsafe { fast_f32_parse_encoded(measurement) };
+    let timestamp = parse_timestamp(timestamp).unwrap_or_else(|| {
+        panic!(
+            "invalid timestamp: {}",
+            String::from_utf8_lossy(timestamp).escape_debug()
+        )
+    });
+
+    *consumed_bytes_count += newline + 1;
+
+    (station, measurement, timestamp)
+}
+
+/// Parses a timestamp into seconds since the Unix epoch. Accepts either an
+/// integer (`1709300220`, `-42`) or an ISO-8601 date-time without timezone
+/// offset: `YYYY-MM-DD`, optionally followed by `T` (or a space) and
+/// `hh:mm`, `hh:mm:ss` or `hh:mm:ss.fff`, optionally followed by `Z`.
+/// Fractional seconds are truncated.
+///
+/// A trailing `\r` is ignored.
+pub fn parse_timestamp(bytes: &[u8]) -> Option<i64> {
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    if bytes.len() >= 10 && bytes[4] == b'-' {
+        parse_iso8601(bytes)
+    } else {
+        parse_epoch(bytes)
+    }
+}
+
+fn parse_epoch(bytes: &[u8]) -> Option<i64> {
+    let (negative, digits) = match bytes.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, bytes),
+    };
+    let value = parse_digits(digits)?;
+    Some(if negative { -value } else { value })
+}
+
+fn parse_iso8601(bytes: &[u8]) -> Option<i64> {
+    let bytes = bytes.strip_suffix(b"Z").unwrap_or(bytes);
+    let (date, time) = match bytes.len() {
+        10 => (bytes, &[][..]),
+        11.. if matches!(bytes[10], b'T' | b' ') => (&bytes[..10], &bytes[11..]),
+        _ => return None,
+    };
+
+    if date[7] != b'-' {
+        return None;
+    }
+    let year = parse_digits(&date[0..4])?;
+    let month = parse_digits(&date[5..7])?;
+    let day = parse_digits(&date[8..10])?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+
+    let (hour, minute, second) = match time.len() {
+        0 => (0, 0, 0),
+        5 if time[2] == b':' => (parse_digits(&time[0..2])?, parse_digits(&time[3..5])?, 0),
+        8.. if time[2] == b':' && time[5] == b':' => {
+            if time.len() > 8 && (time[8] != b'.' || parse_digits(&time[9..]).is_none()) {
+                return None;
+            }
+            (
+                parse_digits(&time[0..2])?,
+                parse_digits(&time[3..5])?,
+                parse_digits(&time[6..8])?,
+            )
+        }
+        _ => return None,
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    Some(days * SECONDS_PER_DAY + hour * 60 * 60 + minute * 60 + second)
+}
+
+/// Parses a non-empty sequence of ASCII digits.
+fn parse_digits(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() || bytes.len() > 18 {
+        return None;
+    }
+    bytes.iter().try_fold(0, |acc, &byte| {
+        byte.is_ascii_digit()
+            .then(|| acc * 10 + (byte - b'0') as i64)
+    })
+}
+
+const fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since the Unix epoch for the given proleptic Gregorian date.
+/// Algorithm from <https://howardhinnant.github.io/date_algorithms.html>.
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of [`days_from_civil`].
+const fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats seconds since the Unix epoch as ISO-8601 date-time in UTC.
+fn format_timestamp(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+    let seconds_of_day = timestamp.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Merges the per-thread results with [`crate::merge`] and sorts them by
+/// bucket and then by station.
+pub(crate) fn reduce<'a>(
+    stats: impl Iterator<Item = WindowedStats<'a>>,
+) -> Vec<((&'a str, i64), AggregatedData)> {
+    let mut stats = crate::merge(stats).into_iter().collect::<Vec<_>>();
+    stats.sort_unstable_by(|((station_a, bucket_a), _), ((station_b, bucket_b), _)| {
+        bucket_a.cmp(bucket_b).then(station_a.cmp(station_b))
+    });
+    stats
+}
+
+/// The aggregated data of a station in the bucket starting at the timestamp.
+pub type WindowedEntry = ((String, i64), AggregatedData);
+
+/// The result of [`crate::aggregate_windowed`]: the aggregated data per
+/// station and time bucket, sorted by the start of the bucket and then by
+/// station name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WindowedResults {
+    stations: Vec<WindowedEntry>,
+}
+
+/// A station of a bucket in the JSON output.
+#[derive(Serialize)]
+struct WindowedRecord<'a> {
+    bucket: String,
+    #[serde(flatten)]
+    station: StationRecord<'a>,
+}
+
+impl WindowedResults {
+    pub(crate) fn from_sorted(stations: Vec<((&str, i64), AggregatedData)>) -> Self {
+        let stations = stations
+            .into_iter()
+            .map(|((station, bucket), data)| ((station.to_string(), bucket), data))
+            .collect();
+        Self { stations }
+    }
+
+    /// All `((station, bucket start), data)` entries, in time order. The
+    /// bucket start is in seconds since the Unix epoch.
+    #[must_use]
+    pub fn stations(&self) -> &[WindowedEntry] {
+        &self.stations
+    }
+
+    /// The stations grouped by bucket, in time order.
+    pub fn buckets(&self) -> impl Iterator<Item = (i64, &[WindowedEntry])> {
+        self.stations
+            .chunk_by(|((_, bucket_a), _), ((_, bucket_b), _)| bucket_a == bucket_b)
+            .map(|bucket| (bucket[0].0 .1, bucket))
+    }
+
+    /// Writes the results. The text format has one line per bucket:
+    /// `<bucket start> {<station>=<min>/<mean>/<max>, ...}`. The JSON format
+    /// is an array with one object per station and bucket, like in
+    /// [`crate::OutputFormat::Json`] with an additional `bucket` field.
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        format: OutputFormat,
+        unit: TemperatureUnit,
+    ) -> io::Result<()> {
+        match format {
+            OutputFormat::Text => {
+                for (start, stations) in self.buckets() {
+                    write!(writer, "{} ", format_timestamp(start))?;
+                    output::write_text(
+                        writer,
+                        stations
+                            .iter()
+                            .map(|((station, _), data)| (station.as_str(), data)),
+                        unit,
+                    )?;
+                }
+                Ok(())
+            }
+            OutputFormat::Json => {
+                let records = self
+                    .stations
+                    .iter()
+                    .map(|((station, bucket), data)| WindowedRecord {
+                        bucket: format_timestamp(*bucket),
+                        station: StationRecord::new(station, data, unit),
+                    })
+                    .collect::<Vec<_>>();
+                serde_json::to_writer(&mut *writer, &records)?;
+                writeln!(writer)
+            }
+        }
+    }
+
+    /// Prints the results to stdout. See [`Self::write`].
+    pub fn print(&self, format: OutputFormat, unit: TemperatureUnit) {
+        self.write(&mut io::stdout().lock(), format, unit).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp(b"0"), Some(0));
+        assert_eq!(parse_timestamp(b"1709300220"), Some(1709300220));
+        assert_eq!(parse_timestamp(b"-42"), Some(-42));
+        assert_eq!(parse_timestamp(b"1970-01-01"), Some(0));
+        assert_eq!(parse_timestamp(b"2024-03-01T13:37"), Some(1709300220));
+        assert_eq!(parse_timestamp(b"2024-03-01T13:37:00"), Some(1709300220));
+        assert_eq!(parse_timestamp(b"2024-03-01 13:37:00Z"), Some(1709300220));
+        assert_eq!(
+            parse_timestamp(b"2024-03-01T13:37:00.999Z"),
+            Some(1709300220)
+        );
+        assert_eq!(parse_timestamp(b"1969-12-31T23:59:59"), Some(-1));
+        assert_eq!(parse_timestamp(b"2024-02-29"), Some(1709164800));
+
+        assert_eq!(parse_timestamp(b""), None);
+        assert_eq!(parse_timestamp(b"12a"), None);
+        assert_eq!(parse_timestamp(b"2023-02-29"), None);
+        assert_eq!(parse_timestamp(b"2024-13-01"), None);
+        assert_eq!(parse_timestamp(b"2024-03-01T25:00"), None);
+        assert_eq!(parse_timestamp(b"2024-03-01T13:37:00+01:00"), None);
+    }
+
+    #[test]
+    fn test_civil_roundtrip() {
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_bucket_start() {
+        let ts = parse_timestamp(b"2024-03-15T13:37:42").unwrap();
+        let bucket = |width: BucketWidth| format_timestamp(width.bucket_start(ts));
+
+        assert_eq!(bucket(BucketWidth::Hour), "2024-03-15T13:00:00Z");
+        assert_eq!(bucket(BucketWidth::Day), "2024-03-15T00:00:00Z");
+        assert_eq!(bucket(BucketWidth::Month), "2024-03-01T00:00:00Z");
+        assert_eq!(
+            bucket(BucketWidth::Seconds(NonZeroU32::new(15 * 60).unwrap())),
+            "2024-03-15T13:30:00Z"
+        );
+        assert_eq!(BucketWidth::Day.bucket_start(-1), -SECONDS_PER_DAY);
+        assert_eq!(
+            format_timestamp(BucketWidth::Month.bucket_start(-1)),
+            "1969-12-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_process_file_chunk() {
+        let input = "Berlin;10.0;2024-01-01T10:00:00\n\
+                     Hamburg;-12.7;2024-01-01T23:59:59\n\
+                     Berlin;-15.7;2024-01-02T00:00:00\n\
+                     Berlin;12.0;1704103200\n";
+        let actual = process_file_chunk(input.as_bytes(), BucketWidth::Day);
+        let stats = reduce([actual].into_iter());
+
+        let day_1 = parse_timestamp(b"2024-01-01").unwrap();
+        let day_2 = parse_timestamp(b"2024-01-02").unwrap();
+        assert_eq!(
+            stats,
+            vec![
+                (("Berlin", day_1), AggregatedData::new(100, 120, 220, 2)),
+                (("Hamburg", day_1), AggregatedData::new(-127, -127, -127, 1)),
+                (("Berlin", day_2), AggregatedData::new(-157, -157, -157, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bucket_width_from_str() {
+        assert_eq!("day".parse(), Ok(BucketWidth::Day));
+        let seconds = BucketWidth::Seconds(NonZeroU32::new(900).unwrap());
+        assert_eq!("900".parse(), Ok(seconds));
+        assert_eq!("900s".parse(), Ok(seconds));
+        assert!("0".parse::<BucketWidth>().is_err());
+        assert!("week".parse::<BucketWidth>().is_err());
+        assert!("99999999999".parse::<BucketWidth>().is_err());
+    }
+
+    #[test]
+    fn test_write() {
+        let stats = process_file_chunk(
+            b"Oslo;-2.0;10\nBerlin;1.0;0\nBerlin;2.0;3600\n",
+            BucketWidth::Hour,
+        );
+        let results = WindowedResults::from_sorted(reduce([stats].into_iter()));
+        let render = |format, unit| {
+            let mut buf = Vec::new();
+            results.write(&mut buf, format, unit).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+
+        assert_eq!(
+            render(OutputFormat::Text, TemperatureUnit::Celsius),
+            "1970-01-01T00:00:00Z {Berlin=1.0/1.0/1.0, Oslo=-2.0/-2.0/-2.0}\n\
+             1970-01-01T01:00:00Z {Berlin=2.0/2.0/2.0}\n"
+        );
+        let json = render(OutputFormat::Json, TemperatureUnit::Kelvin);
+        assert!(json
+            .starts_with(r#"[{"bucket":"1970-01-01T00:00:00Z","station":"Berlin","min":274.2,"#));
+        assert_eq!(json.matches("\"bucket\"").count(), 3);
+    }
+
+    #[test]
+    fn test_aggregate_windowed() {
+        let input = (0..1000)
+            .map(|i| format!("Station{};{}.{};{}\n", i % 7, i % 50, i % 10, i * 97))
+            .collect::<String>();
+        let path = std::env::temp_dir().join(format!("1brc-{}-windowed.txt", std::process::id()));
+        std::fs::write(&path, input).unwrap();
+        let single = crate::aggregate_windowed(&path, NonZeroUsize::MIN, BucketWidth::Day);
+        let multi =
+            crate::aggregate_windowed(&path, NonZeroUsize::new(3).unwrap(), BucketWidth::Day);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(single, multi);
+        let count = single
+            .stations()
+            .iter()
+            .map(|(_, data)| data.count())
+            .sum::<u32>();
+        assert_eq!(count, 1000);
+        assert!(single.buckets().is_sorted_by_key(|(start, _)| start));
+    }
+
+    #[test]
+    fn test_reduce_merges_threads() {
+        let a = process_file_chunk(b"Berlin;1.0;0\nBerlin;2.0;3600\n", BucketWidth::Hour);
+        let b = process_file_chunk(b"Berlin;3.0;10\n", BucketWidth::Hour);
+        let stats = reduce([a, b].into_iter());
+
+        assert_eq!(
+            stats,
+            vec![
+                (("Berlin", 0), AggregatedData::new(10, 30, 40, 2)),
+                (("Berlin", 3600), AggregatedData::new(20, 20, 20, 1)),
+            ]
+        );
+    }
+}
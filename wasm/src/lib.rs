@@ -0,0 +1,244 @@
+//! WebAssembly bindings of the `no_std` core for the browser, built with
+//! `wasm-pack build --target web wasm`.
+//!
+//! The measurements are pushed in chunks of arbitrary size, so that large
+//! uploads don't have to be copied into the memory of the module at once:
+//!
+//! ```js
+//! const aggregator = new Aggregator(true);
+//! for (let offset = 0; offset < buffer.byteLength; offset += CHUNK_SIZE) {
+//!     aggregator.push(new Uint8Array(buffer, offset, Math.min(CHUNK_SIZE, buffer.byteLength - offset)));
+//! }
+//! const stations = JSON.parse(aggregator.finish());
+//! ```
+//!
+//! Everything runs on the calling thread.
+
+#![deny(
+    clippy::all,
+    clippy::cargo,
+    clippy::nursery,
+    clippy::must_use_candidate,
+    // clippy::restriction,
+    // clippy::pedantic
+)]
+// now allow a few rules which are denied by the above statement
+// --> they are ridiculous and not necessary
+#![allow(
+    clippy::suboptimal_flops,
+    clippy::redundant_pub_crate,
+    clippy::fallible_impl_from,
+    clippy::option_if_let_else
+)]
+// I can't do anything about this; fault of the dependencies
+#![allow(clippy::multiple_crate_versions)]
+// allow: required because of derive macro.. :(
+#![allow(clippy::use_self)]
+#![deny(missing_debug_implementations)]
+#![deny(rustdoc::all)]
+
+use phips_1brc_core::{parse_line, round_half_up, AggregatedData};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::mem;
+use wasm_bindgen::prelude::*;
+
+/// A station in the format of `1brc run --format json`: the temperatures in
+/// degrees Celsius, rounded to one decimal place with ties towards positive
+/// infinity.
+#[derive(Debug, Serialize)]
+struct StationRecord<'a> {
+    station: &'a str,
+    min: f64,
+    mean: f64,
+    max: f64,
+    count: u32,
+}
+
+impl<'a> StationRecord<'a> {
+    fn new(station: &'a str, data: &AggregatedData) -> Self {
+        let mean = round_half_up(i128::from(data.encoded_sum()), i128::from(data.count()));
+        Self {
+            station,
+            min: f64::from(data.encoded_min()) / 10.0,
+            mean: mean as f64 / 10.0,
+            max: f64::from(data.encoded_max()) / 10.0,
+            count: data.count(),
+        }
+    }
+}
+
+/// Aggregates measurements that are pushed in chunks. Lines may span chunks.
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct Aggregator {
+    validate: bool,
+    /// The incomplete last line of the previous chunks.
+    pending: Vec<u8>,
+    stations: BTreeMap<String, AggregatedData>,
+    skipped_lines: u64,
+}
+
+#[wasm_bindgen]
+impl Aggregator {
+    /// With `validate`, invalid lines are skipped and counted (see
+    /// `skippedLines`). Otherwise, the input must be valid according to the
+    /// rules of the challenge, which is faster.
+    #[wasm_bindgen(constructor)]
+    #[must_use]
+    pub fn new(validate: bool) -> Self {
+        Self {
+            validate,
+            ..Default::default()
+        }
+    }
+
+    /// Aggregates the complete lines of the chunk and keeps the incomplete
+    /// last line for the next chunk.
+    pub fn push(&mut self, chunk: &[u8]) {
+        let Some(last_newline) = memchr::memrchr(b'\n', chunk) else {
+            self.pending.extend_from_slice(chunk);
+            return;
+        };
+        let (mut lines, rest) = chunk.split_at(last_newline + 1);
+        if !self.pending.is_empty() {
+            // Complete the pending line with the beginning of the chunk.
+            let first_newline = memchr::memchr(b'\n', lines).unwrap();
+            let (head, tail) = lines.split_at(first_newline + 1);
+            let mut line = mem::take(&mut self.pending);
+            line.extend_from_slice(head);
+            self.aggregate(&line);
+            lines = tail;
+        }
+        self.aggregate(lines);
+        self.pending.extend_from_slice(rest);
+    }
+
+    /// Aggregates the remaining line and returns the stations as JSON, in the
+    /// format of `1brc run --format json`. The aggregator is reset.
+    pub fn finish(&mut self) -> String {
+        let mut line = mem::take(&mut self.pending);
+        if !line.is_empty() {
+            line.push(b'\n');
+            self.aggregate(&line);
+        }
+        let stations = mem::take(&mut self.stations);
+        let records = stations
+            .iter()
+            .map(|(station, data)| StationRecord::new(station, data))
+            .collect::<Vec<_>>();
+        self.skipped_lines = 0;
+        // Serializing strings and numbers can't fail.
+        serde_json::to_string(&records).unwrap() + "\n"
+    }
+
+    /// The amount of invalid lines that were skipped, if the input is
+    /// validated.
+    #[wasm_bindgen(getter, js_name = skippedLines)]
+    #[must_use]
+    // wasm_bindgen doesn't support const functions.
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn skipped_lines(&self) -> u64 {
+        self.skipped_lines
+    }
+
+    /// Aggregates complete lines, each ending with a newline.
+    fn aggregate(&mut self, lines: &[u8]) {
+        if self.validate {
+            for line in lines.split_inclusive(|&byte| byte == b'\n') {
+                match parse_line(&line[..line.len() - 1]) {
+                    Ok((station, measurement)) => self.add(station, measurement),
+                    Err(_) => self.skipped_lines += 1,
+                }
+            }
+        } else {
+            // SAFETY: Without validation, the caller guarantees valid input,
+            // and `push` and `finish` only pass complete lines.
+            let stations = unsafe { phips_1brc_core::aggregate_lines(lines) };
+            for (station, data) in stations {
+                match self.stations.get_mut(station) {
+                    Some(existing) => existing.merge(&data),
+                    None => {
+                        self.stations.insert(station.to_string(), data);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add(&mut self, station: &str, measurement: i16) {
+        match self.stations.get_mut(station) {
+            Some(data) => data.add_datapoint(measurement),
+            None => {
+                let mut data = AggregatedData::default();
+                data.add_datapoint(measurement);
+                self.stations.insert(station.to_string(), data);
+            }
+        }
+    }
+}
+
+/// Aggregates all measurements at once. See [`Aggregator`].
+#[wasm_bindgen]
+#[must_use]
+pub fn aggregate(bytes: &[u8], validate: bool) -> String {
+    let mut aggregator = Aggregator::new(validate);
+    aggregator.push(bytes);
+    aggregator.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEASUREMENTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../measurements_10000.txt");
+
+    #[test]
+    fn test_chunks() {
+        let bytes = std::fs::read(MEASUREMENTS).unwrap();
+        let expected = aggregate(&bytes, false);
+        assert_eq!(expected.matches("\"station\"").count(), 413);
+
+        for validate in [false, true] {
+            let mut aggregator = Aggregator::new(validate);
+            // Chunks that split lines, and many without any newline.
+            for chunk in bytes.chunks(7) {
+                aggregator.push(chunk);
+            }
+            assert_eq!(aggregator.skipped_lines(), 0);
+            assert_eq!(aggregator.finish(), expected);
+        }
+    }
+
+    #[test]
+    fn test_same_json_as_cli() {
+        use phips_1brc::{OutputFormat, OutputOptions};
+        use std::num::NonZeroUsize;
+
+        let bytes = std::fs::read(MEASUREMENTS).unwrap();
+        let options = OutputOptions {
+            format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let mut expected = Vec::new();
+        phips_1brc::aggregate_buffer(&bytes, NonZeroUsize::MIN, None)
+            .write(&mut expected, &options)
+            .unwrap();
+        assert_eq!(
+            aggregate(&bytes, false),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_missing_final_newline_and_invalid_lines() {
+        let mut aggregator = Aggregator::new(true);
+        aggregator.push(b"Berlin;10.0\nHam");
+        aggregator.push(b"burg;1.0\ninvalid\nBerlin;-15.7");
+        assert_eq!(aggregator.skipped_lines(), 1);
+        assert_eq!(
+            aggregator.finish(),
+            aggregate(b"Berlin;10.0\nHamburg;1.0\nBerlin;-15.7\n", false)
+        );
+    }
+}